@@ -0,0 +1,76 @@
+use crate::*;
+use std::io::{Seek, SeekFrom};
+use zip::CompressionMethod;
+
+/*** random access ***/
+
+pub trait HrawRandomAccess {
+  fn read_pixel<T:RawNumber, U:PathOrIndex>(&mut self, subpath:U, x:usize, y:usize) -> anyhow::Result<T::Item>;
+  fn read_row<T:RawNumber, U:PathOrIndex>(&mut self, subpath:U, y:usize) -> anyhow::Result<Vec<T::Item>>;
+  fn read_roi<T:RawNumber, U:PathOrIndex>(&mut self, subpath:U, x:usize, y:usize, w:usize, h:usize) -> anyhow::Result<Vec<T::Item>>;
+}
+
+impl HrawRandomAccess for Hraw {
+
+  fn read_pixel<T:RawNumber, U:PathOrIndex>(&mut self, subpath:U, x:usize, y:usize) -> anyhow::Result<T::Item> {
+    let dst = self.read_roi::<T, U>(subpath, x, y, 1, 1)?;
+    Ok(dst[0])
+  }
+
+  fn read_row<T:RawNumber, U:PathOrIndex>(&mut self, subpath:U, y:usize) -> anyhow::Result<Vec<T::Item>> {
+    let width = self.header().to_struct().width;
+    self.read_roi::<T, U>(subpath, 0, y, width, 1)
+  }
+
+  fn read_roi<T:RawNumber, U:PathOrIndex>(&mut self, subpath:U, x:usize, y:usize, w:usize, h:usize) -> anyhow::Result<Vec<T::Item>> {
+    let header = self.header().to_struct();
+    let size = header.bitfield.size().context("bitfield unknown can not be read at random, decode the frame with from_hraw_with")?;
+    anyhow::ensure!(size == T::SIZE, "bitfield {:?} does not match {} byte read", header.bitfield, T::SIZE);
+    let inside = |start:usize, len:usize, end:usize| start.checked_add(len).is_some_and(|n| n <= end);
+    anyhow::ensure!(inside(x, w, header.width) && inside(y, h, header.height),
+      "roi ({x}, {y}) {w}x{h} out of range {}x{}", header.width, header.height);
+
+    let path = subpath.to_name(self)?;
+    let spans = (y..y + h)
+      .map(|row| ((header.offset + (row * header.stride + x) * T::SIZE) as u64, w * T::SIZE))
+      .collect::<Vec<_>>();
+    let buf = self.read_spans(path.as_str(), &spans)?;
+    Ok(buf.chunks_exact(T::SIZE).map(T::from_bytes).collect())
+  }
+
+}
+
+impl Hraw {
+
+  /// reads ascending, non-overlapping `(start, len)` byte spans of an entry.
+  /// stored entries are seeked directly, deflated ones are streamed from the top.
+  pub(crate) fn read_spans(&mut self, path:&str, spans:&[(u64, usize)]) -> anyhow::Result<Vec<u8>> {
+    let mut dst = vec![0u8; spans.iter().map(|n| n.1).sum()];
+    let mut file = self.zip.by_name(path)?;
+    let mut pos = 0usize;
+
+    if file.compression() == CompressionMethod::Stored {
+      let base = file.data_start();
+      let size = file.size();
+      drop(file);
+      let mut raw = std::fs::File::open(&self.path)?;
+      for (start, len) in spans.iter() {
+        anyhow::ensure!(start.checked_add(*len as u64).is_some_and(|n| n <= size), "{path} : read past end of entry");
+        raw.seek(SeekFrom::Start(base + start))?;
+        raw.read_exact(&mut dst[pos..pos + len])?;
+        pos += len;
+      }
+    } else {
+      let mut current = 0u64;
+      for (start, len) in spans.iter() {
+        anyhow::ensure!(current <= *start, "{path} : spans must be ascending");
+        std::io::copy(&mut (&mut file).take(start - current), &mut std::io::sink())?;
+        file.read_exact(&mut dst[pos..pos + len]).with_context(|| format!("{path} : read past end of entry"))?;
+        current = start + *len as u64;
+        pos += len;
+      }
+    }
+    Ok(dst)
+  }
+
+}
@@ -0,0 +1,134 @@
+use crate::*;
+use crate::buffer::FromHraw;
+use rayon::prelude::*;
+
+/*** dark / flat calibration ***/
+
+/*
+  calibration :
+    dark : calibration/dark.raw   # entry in `data` : decoded as the frames, otherwise le_f32 without offset
+    flat : calibration/flat.raw   # master flat, dark is subtracted before normalization
+    gain : 1.0                    # applied after the flat
+    cfa  : false                  # normalize the flat per CFA channel (x % 2 + (y % 2) * 2)
+  corrected = (value - dark) / normalized flat * gain
+*/
+
+/// `calibration/dark.raw`, `calibration/flat.raw` of `HrawWriter::write_calibration`
+pub const CALIBRATION_DARK : &str = "calibration/dark.raw";
+pub const CALIBRATION_FLAT : &str = "calibration/flat.raw";
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Combine { Mean, Median }
+
+/// `calibration` as written in the header
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+pub struct HeaderCalibration {
+  #[serde(default)]
+  pub dark: Option<String>,
+  #[serde(default)]
+  pub flat: Option<String>,
+  #[serde(default = "default_gain")]
+  pub gain: f64,
+  #[serde(default)]
+  pub cfa: bool,
+}
+fn default_gain() -> f64 { 1.0 }
+
+/// pixel wise mean or median of `frames`
+pub fn master_frame<F:AsRef<[f64]> + Sync>(frames:&[F], combine:Combine) -> anyhow::Result<Vec<f64>> {
+  anyhow::ensure!(!frames.is_empty(), "master frame : no frames");
+  let len = frames[0].as_ref().len();
+  anyhow::ensure!(frames.iter().all(|n| n.as_ref().len() == len), "master frame : frames differ in size");
+  Ok((0..len).into_par_iter().map(|i| {
+    match combine {
+      Combine::Mean => frames.iter().map(|n| n.as_ref()[i]).sum::<f64>() / frames.len() as f64,
+      Combine::Median => {
+        let mut values = frames.iter().map(|n| n.as_ref()[i]).collect::<Vec<_>>();
+        values.sort_unstable_by(f64::total_cmp);
+        match values.len() % 2 {
+          0 => (values[values.len() / 2 - 1] + values[values.len() / 2]) / 2.0,
+          _ => values[values.len() / 2]
+        }
+      }
+    }
+  }).collect())
+}
+
+/// `flat - dark` divided by its mean (per CFA channel with `cfa`), 1.0 on average
+pub fn normalize_flat(flat:&[f64], dark:Option<&[f64]>, width:usize, cfa:bool) -> Vec<f64> {
+  let channel = |i:usize| if cfa { i % width % 2 + (i / width % 2) * 2 } else { 0 };
+  let signal = flat.par_iter().enumerate().map(|(i, n)| n - dark.map(|d| d[i]).unwrap_or_default()).collect::<Vec<_>>();
+  let mut sum = [(0f64, 0usize); 4];
+  signal.iter().enumerate().for_each(|(i, n)| { sum[channel(i)].0 += n; sum[channel(i)].1 += 1; });
+  let mean = sum.map(|(s, c)| if c > 0 { s / c as f64 } else { 1.0 });
+  signal.par_iter().enumerate().map(|(i, n)| n / mean[channel(i)]).collect()
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Calibration {
+  pub dark: Option<Vec<f64>>,
+  /// normalized, see `normalize_flat`
+  pub flat: Option<Vec<f64>>,
+  pub gain: f64,
+}
+
+impl Calibration {
+
+  /// from master frames, the flat is normalized here
+  pub fn new(dark:Option<Vec<f64>>, flat:Option<&[f64]>, gain:f64, width:usize, cfa:bool) -> Calibration {
+    let flat = flat.map(|n| normalize_flat(n, dark.as_deref(), width, cfa));
+    Calibration { dark, flat, gain }
+  }
+
+  /// dark subtraction, flat normalization and gain in place. pixels with a flat <= 0 are not divided
+  pub fn apply<T>(&self, src:&mut [T]) -> anyhow::Result<()>
+  where T : Copy + Into<f64> + ClampFrom<f64> + Send {
+    for (name, n) in [("dark", &self.dark), ("flat", &self.flat)] {
+      if let Some(n) = n { anyhow::ensure!(n.len() >= src.len(), "calibration : {name} has {} pixels, {} expected", n.len(), src.len()); }
+    }
+    src.par_iter_mut().enumerate().for_each(|(i, d)| {
+      let mut value = (*d).into() - self.dark.as_ref().map(|n| n[i]).unwrap_or_default();
+      if let Some(flat) = self.flat.as_ref().map(|n| n[i]).filter(|n| *n > 0.0) { value /= flat; }
+      *d = T::clamp_from(value * self.gain);
+    });
+    Ok(())
+  }
+
+}
+
+impl Hraw {
+
+  /// `subpath` decoded as f64 (bitfield or decoder)
+  pub fn frame_f64<T:PathOrIndex>(&mut self, subpath:T, policy:&ScriptPolicy) -> anyhow::Result<Vec<f64>> {
+    let total = self.header().to_struct().total;
+    let path = self.contain_poi(subpath)?;
+    let mut dst = vec![0f64; total];
+    dst.as_mut_slice().from_hraw_with(&self.path, path.as_str(), policy)?;
+    Ok(dst)
+  }
+
+  /// mean or median of data entries
+  pub fn master_frame<T:PathOrIndex + Copy>(&mut self, subpaths:&[T], combine:Combine, policy:&ScriptPolicy) -> anyhow::Result<Vec<f64>> {
+    let frames = subpaths.iter().map(|n| self.frame_f64(*n, policy)).collect::<anyhow::Result<Vec<_>>>()?;
+    master_frame(&frames, combine)
+  }
+
+  /// `calibration` of the header, `None` when not given
+  pub fn calibration(&mut self, policy:&ScriptPolicy) -> anyhow::Result<Option<Calibration>> {
+    let header = self.header().to_struct();
+    let Some(calibration) = header.calibration else { return Ok(None) };
+    let data = header.data.iter().filter_map(|n| n.as_str().map(str::to_string)).collect::<Vec<_>>();
+    let mut read = |path:Option<String>| -> anyhow::Result<Option<Vec<f64>>> {
+      let Some(path) = path else { return Ok(None) };
+      if data.contains(&path) { return self.frame_f64(path.as_str(), policy).map(Some); }
+      let buf = self.read_bytes(&path).with_context(|| format!("calibration : {path}"))?;
+      anyhow::ensure!(buf.len() >= header.total * 4, "calibration : {path} is shorter than width * height le_f32");
+      Ok(Some(buf.chunks_exact(4).take(header.total).map(|n| le_f32::from_bytes(n) as f64).collect()))
+    };
+    let dark = read(calibration.dark)?;
+    let flat = read(calibration.flat)?;
+    Ok(Some(Calibration::new(dark, flat.as_deref(), calibration.gain, header.width, calibration.cfa)))
+  }
+
+}
@@ -0,0 +1,238 @@
+use crate::*;
+use crate::convert::Welford;
+use crate::processing::{plot_png, PlotSeries};
+use rayon::prelude::*;
+
+/*** EMVA 1288 style sensor characterization ***/
+
+/*
+  one archive per exposure level
+    exposure : 10.0                         # [ms], light = data[0..2], dark = data[2..4]
+    exposure :
+      time : 10.0                           # [ms]
+      photons : 150.0                       # Optional mean photons per pixel, gives responsivity and quantum efficiency
+      light : [light/0.raw, light/1.raw]    # 2 frames or more, default : data[0..2]
+      dark : [dark/0.raw, dark/1.raw]       # default : data[2..4]
+  statistics over the active area
+    temporal variance : var(a - b) / 2 of the first pair
+    spatial variance  : variance of the mean frame without its temporal part (var / frames)
+*/
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+pub struct ExposureSection {
+  pub time: f64,
+  #[serde(default)]
+  pub photons: Option<f64>,
+  #[serde(default)]
+  pub light: Vec<String>,
+  #[serde(default)]
+  pub dark: Vec<String>,
+}
+
+/// `exposure` as written in the header
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum HeaderExposure {
+  Time(f64),
+  Section(ExposureSection),
+}
+
+impl HeaderExposure {
+  pub fn section(&self) -> ExposureSection {
+    match self {
+      HeaderExposure::Time(time) => ExposureSection { time: *time, photons: None, light: Vec::new(), dark: Vec::new() },
+      HeaderExposure::Section(n) => n.to_owned(),
+    }
+  }
+}
+
+/// statistics of the light and dark frames of one exposure [DN]
+#[derive(serde::Serialize, Debug, Clone, PartialEq)]
+pub struct ExposureLevel {
+  pub time: f64,
+  pub photons: Option<f64>,
+  pub mean: f64,
+  pub dark_mean: f64,
+  pub temporal_variance: f64,
+  pub dark_temporal_variance: f64,
+  pub spatial_variance: f64,
+  pub dark_spatial_variance: f64,
+}
+
+impl ExposureLevel {
+  /// mean - dark mean
+  pub fn signal(&self) -> f64 { self.mean - self.dark_mean }
+  /// temporal variance - dark temporal variance
+  pub fn variance(&self) -> f64 { self.temporal_variance - self.dark_temporal_variance }
+}
+
+/// mean, temporal variance and spatial variance of a stack, streamed with the first pair kept
+fn stack<I:Iterator<Item = anyhow::Result<Vec<f64>>>>(frames:I) -> anyhow::Result<(f64, f64, f64)> {
+  let mut welford: Option<Welford> = None;
+  let mut pair = Vec::new();
+  for frame in frames {
+    let frame = frame?;
+    welford.get_or_insert_with(|| Welford::new(frame.len())).push(&frame)?;
+    if pair.len() < 2 { pair.push(frame); }
+  }
+  let welford = welford.filter(|n| n.count() >= 2).context("characterization : 2 frames or more are required")?;
+  let len = pair[0].len() as f64;
+  let diff = pair[0].par_iter().zip(pair[1].par_iter()).map(|(a, b)| a - b).collect::<Vec<_>>();
+  let diff_mean = diff.iter().sum::<f64>() / len;
+  let temporal = diff.iter().map(|n| (n - diff_mean).powi(2)).sum::<f64>() / (2.0 * len);
+  let image = welford.mean();
+  let mean = image.iter().sum::<f64>() / len;
+  let spatial = image.iter().map(|n| (n - mean).powi(2)).sum::<f64>() / (len - 1.0).max(1.0);
+  Ok((mean, temporal, spatial - temporal / welford.count() as f64))
+}
+
+/// least squares `y = slope * x + intercept`, `None` with less than 2 distinct x
+pub fn linear_fit(points:&[(f64, f64)]) -> Option<(f64, f64)> {
+  let count = points.len() as f64;
+  let (sx, sy) = points.iter().fold((0.0, 0.0), |(sx, sy), (x, y)| (sx + x, sy + y));
+  let (mx, my) = (sx / count, sy / count);
+  let sxx = points.iter().map(|(x, _)| (x - mx).powi(2)).sum::<f64>();
+  if points.len() < 2 || sxx <= 0.0 { return None; }
+  let slope = points.iter().map(|(x, y)| (x - mx) * (y - my)).sum::<f64>() / sxx;
+  Some((slope, my - slope * mx))
+}
+
+#[derive(serde::Serialize, Debug, Clone)]
+pub struct Characterization {
+  /// sorted by exposure
+  pub levels: Vec<ExposureLevel>,
+  /// K [DN/e-], slope of the photon transfer curve up to 70% of saturation
+  pub conversion_gain: f64,
+  /// sqrt of the dark temporal variance [DN], [e-]
+  pub read_noise_dn: f64,
+  pub read_noise_e: f64,
+  /// sqrt of the dark spatial variance [DN], [e-]
+  pub dsnu_dn: f64,
+  pub dsnu_e: f64,
+  /// spatial standard deviation of the signal near 50% of saturation [%]
+  pub prnu: f64,
+  /// signal of the maximum temporal variance [DN]
+  pub saturation_dn: f64,
+  /// saturation / K [e-]
+  pub full_well_e: f64,
+  /// (max - min) / 2 of the relative deviation from the linear fit, 5% to 95% of saturation [%]
+  pub linearity_error: f64,
+  /// [DN/photon], with `photons` in every level
+  pub responsivity: Option<f64>,
+  /// responsivity / K
+  pub quantum_efficiency: Option<f64>,
+}
+
+/// metrics from 2 exposure levels or more
+pub fn characterize(levels:&[ExposureLevel]) -> anyhow::Result<Characterization> {
+  anyhow::ensure!(levels.len() >= 2, "characterization : 2 exposure levels or more are required");
+  let mut levels = levels.to_vec();
+  levels.sort_by(|a, b| a.time.total_cmp(&b.time).then(a.signal().total_cmp(&b.signal())));
+  let count = levels.len() as f64;
+
+  let saturation = levels.iter().enumerate().max_by(|a, b| a.1.variance().total_cmp(&b.1.variance())).map(|n| n.0).unwrap();
+  let saturation_dn = levels[saturation].signal();
+  let below = |ratio:[f64; 2]| -> Vec<&ExposureLevel> {
+    let range = levels[0..=saturation].iter().filter(|n| ratio[0] * saturation_dn <= n.signal() && n.signal() <= ratio[1] * saturation_dn).collect::<Vec<_>>();
+    if range.len() >= 2 { range } else { levels[0..=saturation].iter().collect() }
+  };
+
+  /* photon transfer : variance = K * signal + c */
+  let ptc = below([f64::NEG_INFINITY, 0.7]).iter().map(|n| (n.signal(), n.variance())).collect::<Vec<_>>();
+  let (conversion_gain, _) = linear_fit(&ptc).context("characterization : photon transfer curve needs 2 distinct signals")?;
+  anyhow::ensure!(conversion_gain > 0.0, "characterization : conversion gain {conversion_gain} is not positive");
+
+  let read_noise_dn = (levels.iter().map(|n| n.dark_temporal_variance).sum::<f64>() / count).max(0.0).sqrt();
+  let dsnu_dn = (levels.iter().map(|n| n.dark_spatial_variance).sum::<f64>() / count).max(0.0).sqrt();
+  let half = levels[0..=saturation].iter().min_by(|a, b| (a.signal() - saturation_dn / 2.0).abs().total_cmp(&(b.signal() - saturation_dn / 2.0).abs())).unwrap();
+  let prnu = (half.spatial_variance - half.dark_spatial_variance).max(0.0).sqrt() / half.signal() * 100.0;
+
+  /* linearity : signal = a * exposure + b, exposure = photons when given */
+  let photons = levels.iter().all(|n| n.photons.is_some());
+  let exposure = |n:&ExposureLevel| if photons { n.photons.unwrap() } else { n.time };
+  let linear = below([0.05, 0.95]).iter().map(|n| (exposure(n), n.signal())).collect::<Vec<_>>();
+  let linearity_error = match linear_fit(&linear) {
+    Some((a, b)) => {
+      let deviation = linear.iter().map(|(x, y)| (y - (a * x + b)) / (a * x + b) * 100.0).collect::<Vec<_>>();
+      let (min, max) = deviation.iter().fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), n| (min.min(*n), max.max(*n)));
+      (max - min) / 2.0
+    },
+    None => 0.0
+  };
+  let responsivity = match photons {
+    true => linear_fit(&levels[0..=saturation].iter().map(|n| (n.photons.unwrap(), n.signal())).collect::<Vec<_>>()).map(|n| n.0),
+    false => None
+  };
+
+  Ok(Characterization {
+    conversion_gain,
+    read_noise_dn,
+    read_noise_e: read_noise_dn / conversion_gain,
+    dsnu_dn,
+    dsnu_e: dsnu_dn / conversion_gain,
+    prnu,
+    saturation_dn,
+    full_well_e: saturation_dn / conversion_gain,
+    linearity_error,
+    responsivity,
+    quantum_efficiency: responsivity.map(|n| n / conversion_gain),
+    levels,
+  })
+}
+
+impl Characterization {
+
+  /// variance vs signal [DN] with the conversion gain line
+  pub fn ptc_png(&self, width:usize, height:usize) -> Vec<u8> {
+    let points = self.levels.iter().map(|n| (n.signal(), n.variance())).collect::<Vec<_>>();
+    let fit = vec![(0.0, 0.0), (self.saturation_dn, self.saturation_dn * self.conversion_gain)];
+    plot_png(&[
+      PlotSeries { points: fit, color: [0, 120, 255], line: true },
+      PlotSeries { points, color: [220, 0, 0], line: false },
+    ], width, height)
+  }
+
+  /// signal [DN] vs exposure (photons when given, time otherwise)
+  pub fn linearity_png(&self, width:usize, height:usize) -> Vec<u8> {
+    let photons = self.responsivity.is_some();
+    let points = self.levels.iter().map(|n| (if photons { n.photons.unwrap() } else { n.time }, n.signal())).collect::<Vec<_>>();
+    plot_png(&[
+      PlotSeries { points: points.clone(), color: [0, 120, 255], line: true },
+      PlotSeries { points, color: [220, 0, 0], line: false },
+    ], width, height)
+  }
+
+}
+
+impl Hraw {
+
+  /// `exposure` of the header, `None` when not given
+  pub fn exposure(&mut self) -> Option<ExposureSection> {
+    self.header().to_struct().exposure.map(|n| n.section())
+  }
+
+  /// statistics of the light and dark frames of `exposure`, cropped to the active area
+  pub fn exposure_level(&mut self, policy:&ScriptPolicy) -> anyhow::Result<ExposureLevel> {
+    let header = self.header().to_struct();
+    let exposure = self.exposure().context("characterization : exposure is not declared")?;
+    let data = header.data.iter().filter_map(|n| n.as_str().map(str::to_string)).collect::<Vec<_>>();
+    let pick = |list:Vec<String>, range:std::ops::Range<usize>| if list.is_empty() { data.get(range).map(|n| n.to_vec()).unwrap_or_default() } else { list };
+    let (light, dark) = (pick(exposure.light, 0..2), pick(exposure.dark, 2..4));
+    let rect = self.active_area()?;
+    let mut frames = |list:Vec<String>| -> anyhow::Result<(f64, f64, f64)> {
+      stack(list.into_iter().map(|path| self.frame_f64(path.as_str(), policy).map(|n| crop(&n, header.width, &rect))))
+    };
+    let (mean, temporal_variance, spatial_variance) = frames(light).context("characterization : light frames")?;
+    let (dark_mean, dark_temporal_variance, dark_spatial_variance) = frames(dark).context("characterization : dark frames")?;
+    Ok(ExposureLevel { time: exposure.time, photons: exposure.photons, mean, dark_mean, temporal_variance, dark_temporal_variance, spatial_variance, dark_spatial_variance })
+  }
+
+}
+
+/// `characterize` of archives, one exposure level each
+pub fn characterize_archives(paths:&[&str], policy:&ScriptPolicy) -> anyhow::Result<Characterization> {
+  let levels = paths.iter()
+    .map(|path| Hraw::new(path).and_then(|mut n| n.exposure_level(policy)).with_context(|| format!("characterization : {path}")))
+    .collect::<anyhow::Result<Vec<_>>>()?;
+  characterize(&levels)
+}
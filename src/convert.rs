@@ -0,0 +1,149 @@
+use crate::*;
+use crate::writer::HrawWriter;
+use rayon::prelude::*;
+
+/*** convert : temporal statistics of the frames ***/
+
+/*
+  hraw convert data.hraw -o ave.hraw --num 100 --fixed 100
+    single_float.raw : the first frame
+    ave_float.raw    : mean
+    dev_float.raw    : standard deviation (n - 1)
+    min_float.raw / max_float.raw
+  le_f32 without offset, values multiplied by `fixed`. frames are streamed, one decoded frame in memory at a time
+*/
+
+pub const CONVERT_SINGLE : &str = "single_float.raw";
+pub const CONVERT_MEAN : &str = "ave_float.raw";
+pub const CONVERT_STD : &str = "dev_float.raw";
+pub const CONVERT_MIN : &str = "min_float.raw";
+pub const CONVERT_MAX : &str = "max_float.raw";
+
+#[derive(Debug, Clone, Copy)]
+struct Accumulator {
+  mean: f64,
+  m2: f64,
+  min: f64,
+  max: f64,
+}
+
+/// per pixel running mean / variance / min / max, updated one frame at a time
+#[derive(Debug, Clone, Default)]
+pub struct Welford {
+  count: usize,
+  pixels: Vec<Accumulator>,
+}
+
+impl Welford {
+
+  pub fn new(len:usize) -> Welford {
+    Welford { count: 0, pixels: vec![Accumulator { mean: 0.0, m2: 0.0, min: f64::INFINITY, max: f64::NEG_INFINITY }; len] }
+  }
+
+  pub fn push(&mut self, frame:&[f64]) -> anyhow::Result<()> {
+    anyhow::ensure!(frame.len() == self.pixels.len(), "welford : {} pixels, {} expected", frame.len(), self.pixels.len());
+    self.count += 1;
+    let count = self.count as f64;
+    self.pixels.par_iter_mut().zip(frame.par_iter()).for_each(|(d, n)| {
+      let delta = n - d.mean;
+      d.mean += delta / count;
+      d.m2 += delta * (n - d.mean);
+      d.min = d.min.min(*n);
+      d.max = d.max.max(*n);
+    });
+    Ok(())
+  }
+
+  pub fn count(&self) -> usize { self.count }
+  pub fn mean(&self) -> Vec<f64> { self.pixels.par_iter().map(|n| n.mean).collect() }
+  /// sample variance, 0 with a single frame
+  pub fn variance(&self) -> Vec<f64> {
+    let count = self.count;
+    self.pixels.par_iter().map(|n| if count > 1 { n.m2 / (count - 1) as f64 } else { 0.0 }).collect()
+  }
+  pub fn std(&self) -> Vec<f64> { self.variance().into_par_iter().map(f64::sqrt).collect() }
+  pub fn min(&self) -> Vec<f64> { self.pixels.par_iter().map(|n| n.min).collect() }
+  pub fn max(&self) -> Vec<f64> { self.pixels.par_iter().map(|n| n.max).collect() }
+
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct TemporalStats {
+  pub frames: usize,
+  /// the first frame as decoded
+  pub single: Vec<f64>,
+  pub mean: Vec<f64>,
+  pub std: Vec<f64>,
+  pub min: Vec<f64>,
+  pub max: Vec<f64>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ConvertOptions {
+  /// first `num` entries of `data`, all when `None`
+  pub num: Option<usize>,
+  /// scale of the written values, e.g. 100 for x100 fixed point
+  pub fixed: f64,
+}
+
+impl Default for ConvertOptions {
+  fn default() -> Self {
+    ConvertOptions { num: None, fixed: 1.0 }
+  }
+}
+
+impl Hraw {
+
+  /// statistics over the first `num` data entries (all when `None`), decoded as f64
+  pub fn temporal_stats(&mut self, num:Option<usize>, policy:&ScriptPolicy) -> anyhow::Result<TemporalStats> {
+    let header = self.header().to_struct();
+    let count = header.data.len();
+    let num = num.unwrap_or(count);
+    anyhow::ensure!(num > 0, "convert : no frames");
+    anyhow::ensure!(num <= count, "convert : {num} frames requested, data has {count}");
+    let mut welford = Welford::new(header.total);
+    let mut single = Vec::new();
+    for index in 0..num {
+      let frame = self.frame_f64(index, policy)?;
+      welford.push(&frame)?;
+      if index == 0 { single = frame; }
+    }
+    Ok(TemporalStats { frames: welford.count(), single, mean: welford.mean(), std: welford.std(), min: welford.min(), max: welford.max() })
+  }
+
+}
+
+/// `src` to a new archive `dst` with the entries above. the header is kept except the sections
+/// bound to the source encoding (`decoder`, `encoder`, `postprocess`, `calibration`, `defects`).
+/// `black_level` and `white_level` (the bitfield maximum when not given) are multiplied by `fixed`,
+/// regions (`optical_black`, `embedded_lines`, `active_area`) are pixel coordinates and stay as they are
+pub fn convert(src:&str, dst:&str, options:&ConvertOptions, policy:&ScriptPolicy) -> anyhow::Result<TemporalStats> {
+  let mut hraw = Hraw::new(src)?;
+  let stats = hraw.temporal_stats(options.num, policy)?;
+
+  let mut header = hraw.header();
+  let source = header.to_struct();
+  if let Some(n) = header.as_object_mut() {
+    ["decoder", "encoder", "postprocess", "calibration", "defects", "data"].iter().for_each(|key| { n.remove(*key); });
+  }
+  let scale = |n:f64| json!(n * options.fixed);
+  match source.black_level {
+    Some(HeaderBlackLevel::Global(n)) => header["black_level"] = scale(n),
+    Some(HeaderBlackLevel::Cfa(n)) => header["black_level"] = json!(n.map(|n| n * options.fixed)),
+    _ => {} // 無し, obは変換後のpixelから推定
+  }
+  if let Some(white) = source.white_level.or(default_white_level(source.bitfield)) {
+    header["white_level"] = scale(white);
+  }
+  header["bitfield"] = json!(BitField::le_f32);
+  header["offset"] = json!(0);
+  header["convert"] = json!({ "source" : src, "frames" : stats.frames, "fixed" : options.fixed });
+
+  let mut writer = HrawWriter::new(dst, header)?;
+  for (name, values) in [(CONVERT_SINGLE, &stats.single), (CONVERT_MEAN, &stats.mean), (CONVERT_STD, &stats.std), (CONVERT_MIN, &stats.min), (CONVERT_MAX, &stats.max)] {
+    let values = values.par_iter().map(|n| (n * options.fixed) as f32).collect::<Vec<_>>();
+    writer.write_data::<le_f32>(name, &values)?;
+  }
+  writer.finish()?;
+  Ok(stats)
+}
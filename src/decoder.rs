@@ -0,0 +1,183 @@
+use crate::*;
+
+/*** decoder source ***/
+
+/*
+  decoder : { lang: lua, file: decoders/unpack12.luau, modules: [decoders/lib] }
+  encoder : same keys, scripts return bytes instead of numbers
+    code    : inline script
+    layout  : byte layout instead of a script with `lang: layout`
+    file    : zip entry of the script, used when `code` is empty. `lang: wasm` requires a file (binary module)
+    modules : zip directories, `decoders/lib/bits.luau` is loaded by `require("bits")`
+*/
+
+#[derive(Debug, Clone)]
+pub struct DecoderSource {
+  pub lang: String,
+  pub code: String,
+  pub modules: ScriptModules,
+  /// `file` as is for binary decoders (`lang: wasm`), `code` is empty then
+  pub binary: Vec<u8>,
+  /// `lang: layout`, see `rawnumber::layout`
+  pub layout: Option<Layout>,
+}
+
+/// extensions of script entries, lua modules can be `.luau` or `.lua`
+pub fn module_ext(lang:&str) -> &'static [&'static str] {
+  match lang {
+    "py" => &[".py"],
+    "rhai" => &[".rhai"],
+    _ => &[".luau", ".lua"]
+  }
+}
+
+/// `file` and `modules` are looked up in `names` and read with `read`
+pub fn resolve_source(section:&str, script:HeaderDecoder, names:&[String], mut read:impl FnMut(&str) -> anyhow::Result<Vec<u8>>) -> anyhow::Result<DecoderSource> {
+  let text = |buf:Vec<u8>, name:&str| String::from_utf8(buf).with_context(|| format!("{name} : not utf-8"));
+  if script.lang == "layout" {
+    let layout = script.layout.with_context(|| format!("{section} : layout requires the layout key"))?;
+    return Ok(DecoderSource { lang: script.lang, code: String::new(), modules: ScriptModules::new(), binary: Vec::new(), layout: Some(layout) });
+  }
+  let (code, binary) = match (script.code.is_empty(), &script.file, script.lang.as_str()) {
+    (_, None, "wasm") => anyhow::bail!("{section} : wasm requires file"),
+    (true, Some(file), "wasm") => (String::new(), read(file)?),
+    (true, Some(file), _) => (text(read(file)?, file)?, Vec::new()),
+    (false, Some(file), _) => anyhow::bail!("{section} : both code and file ({file}) are given"),
+    (_, None, _) => (script.code, Vec::new()),
+  };
+
+  let mut modules = ScriptModules::new();
+  for dir in script.modules.iter() {
+    let dir = format!("{}/", dir.trim_end_matches('/'));
+    for name in names.iter().filter(|n| n.starts_with(dir.as_str())) {
+      let Some(module) = module_ext(&script.lang).iter().find_map(|ext| name[dir.len()..].strip_suffix(ext)) else { continue };
+      if modules.contains_key(module) { continue; } // 先に書いたdirを優先
+      modules.insert(module.to_string(), text(read(name)?, name)?);
+    }
+  }
+  Ok(DecoderSource { lang: script.lang, code, modules, binary, layout: None })
+}
+
+impl Hraw {
+
+  /// decoder of the header with `file` and `modules` read from the archive
+  pub fn decoder_source(&mut self) -> anyhow::Result<DecoderSource> {
+    let header = self.header();
+    self.decoder_source_of(&header)
+  }
+
+  /// `decoder_source` of an already read `header`
+  pub(crate) fn decoder_source_of(&mut self, header:&serde_json::Value) -> anyhow::Result<DecoderSource> {
+    let decoder = header.to_struct().decoder.context("bitfield unknown requires decoder")?;
+    let names = self.zip.file_names().map(|n| n.to_string()).collect::<Vec<_>>();
+    resolve_source("decoder", decoder, &names, |n| self.read_bytes(n))
+  }
+
+  /// encoder of the header, the inverse of `decoder_source`
+  pub fn encoder_source(&mut self) -> anyhow::Result<DecoderSource> {
+    let encoder = self.header().to_struct().encoder.context("encoder not found in header")?;
+    let names = self.zip.file_names().map(|n| n.to_string()).collect::<Vec<_>>();
+    resolve_source("encoder", encoder, &names, |n| self.read_bytes(n))
+  }
+
+  pub(crate) fn read_bytes(&mut self, path:&str) -> anyhow::Result<Vec<u8>> {
+    let mut file = self.zip.by_name(path).with_context(|| format!("{path} : not found in archive"))?;
+    let mut dst = Vec::new();
+    file.read_to_end(&mut dst)?;
+    Ok(dst)
+  }
+
+}
+
+/*** check ***/
+
+/// xorshift64, deterministic samples without a rand dependency. the seed must not be 0
+#[derive(Debug, Clone)]
+pub(crate) struct XorShift(pub u64);
+
+impl XorShift {
+  pub fn next_u64(&mut self) -> u64 {
+    self.0 ^= self.0 << 13; self.0 ^= self.0 >> 7; self.0 ^= self.0 << 17;
+    self.0
+  }
+}
+
+/// one failure of a dry run, `index` is `None` when the decoder fails as a whole
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct DecoderFault {
+  pub index: Option<usize>,
+  pub xy: Option<(usize, usize)>,
+  /// line in the decoder script
+  pub line: Option<usize>,
+  pub message: String,
+  /// raw bytes of the pixel (at most 16), estimated from the bitfield or `(len - offset) / total`
+  pub bytes: Vec<u8>,
+}
+
+impl std::fmt::Display for DecoderFault {
+  fn fmt(&self, f:&mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    if let (Some(index), Some((x, y))) = (self.index, self.xy) { write!(f, "pixel {index} ({x}, {y}) ")?; }
+    if let Some(line) = self.line { write!(f, "line {line} ")?; }
+    write!(f, ": {}", self.message)?;
+    if !self.bytes.is_empty() { write!(f, " bytes {:02x?}", self.bytes)?; }
+    Ok(())
+  }
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct DecoderReport {
+  pub lang: String,
+  /// (pixel index, decoded value) of the samples that passed
+  pub samples: Vec<(usize, f64)>,
+  pub faults: Vec<DecoderFault>,
+}
+
+impl DecoderReport {
+  pub fn is_ok(&self) -> bool { self.faults.is_empty() }
+}
+
+impl Hraw {
+
+  /// dry run of the decoder on the first, last and 16 random pixels of the first data entry
+  pub fn check_decoder(&mut self) -> anyhow::Result<DecoderReport> {
+    self.check_decoder_with(0, 16, &ScriptPolicy::default())
+  }
+
+  /// `Err` only when the archive can not be read, script failures are in `DecoderReport::faults`
+  pub fn check_decoder_with<T:PathOrIndex>(&mut self, subpath:T, samples:usize, policy:&ScriptPolicy) -> anyhow::Result<DecoderReport> {
+    let header = self.header();
+    let info = header.to_struct();
+    let source = self.decoder_source()?;
+    let src = self.to_vec_poi(subpath)?;
+    anyhow::ensure!(info.total > 0, "decoder : width and height must not be 0");
+
+    // 先頭・末尾 + xorshiftで疑似乱数
+    let mut indices = vec![0, info.total - 1];
+    let mut rng = XorShift((info.total as u64) ^ 0x9e37_79b9_7f4a_7c15);
+    for _ in 0..samples {
+      indices.push((rng.next_u64() % info.total as u64) as usize);
+    }
+    indices.sort_unstable();
+    indices.dedup();
+
+    let bpp = info.bitfield.size().unwrap_or(src.len().saturating_sub(info.offset) / info.total).max(1);
+    let fault = |index:Option<usize>, (line, message):ScriptFault| {
+      let bytes = index.map(|i| {
+        let start = (info.offset + i * bpp).min(src.len());
+        src[start..(start + bpp.min(16)).min(src.len())].to_vec()
+      }).unwrap_or_default();
+      DecoderFault { index, xy: index.map(|i| (i % info.width, i / info.width)), line, message, bytes }
+    };
+
+    let mut report = DecoderReport { lang: source.lang.clone(), samples: Vec::new(), faults: Vec::new() };
+    match check_source(&source, &src, &header, policy, &indices) {
+      Ok(results) => indices.iter().zip(results).for_each(|(&i, n)| match n {
+        Ok(value) => report.samples.push((i, value)),
+        Err(e) => report.faults.push(fault(Some(i), e)),
+      }),
+      Err(e) => report.faults.push(fault(None, e)),
+    }
+    Ok(report)
+  }
+
+}
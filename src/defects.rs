@@ -0,0 +1,203 @@
+use crate::*;
+use rayon::prelude::*;
+
+/*** defective pixels ***/
+
+/*
+  defects : [[3, 4], { x : 10, y : 2, kind : dead }]   # coordinates, kind defaults to hot
+  defects : defects.raw                                 # u8 mask entry, 0 : good, DefectKind as 1..
+  detection compares each pixel to the median of its CFA channel (x % 2 + (y % 2) * 2)
+*/
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum DefectKind {
+  /// dark mean too high
+  #[default]
+  Hot,
+  /// flat response too low
+  Dead,
+  /// no temporal noise in the flat stack
+  Stuck,
+  /// dark temporal variance too high
+  Noisy,
+}
+
+impl DefectKind {
+  const ALL : [DefectKind; 4] = [DefectKind::Hot, DefectKind::Dead, DefectKind::Stuck, DefectKind::Noisy];
+  /// value in a mask entry
+  pub fn code(&self) -> u8 { DefectKind::ALL.iter().position(|n| n == self).unwrap() as u8 + 1 }
+  pub fn from_code(code:u8) -> Option<DefectKind> { DefectKind::ALL.get((code as usize).checked_sub(1)?).copied() }
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Defect {
+  pub x: usize,
+  pub y: usize,
+  #[serde(default)]
+  pub kind: DefectKind,
+}
+
+/// `defects` as written in the header
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum HeaderDefects {
+  Entry(String),
+  List(Vec<HeaderDefect>),
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum HeaderDefect {
+  Pair([usize; 2]),
+  Defect(Defect),
+}
+
+/// per pixel mean and temporal variance of a stack
+#[derive(Debug, Clone, Default)]
+pub struct StackStats {
+  pub frames: usize,
+  pub mean: Vec<f64>,
+  pub variance: Vec<f64>,
+}
+
+pub fn stack_stats<F:AsRef<[f64]> + Sync>(frames:&[F]) -> anyhow::Result<StackStats> {
+  anyhow::ensure!(!frames.is_empty(), "stack : no frames");
+  let len = frames[0].as_ref().len();
+  anyhow::ensure!(frames.iter().all(|n| n.as_ref().len() == len), "stack : frames differ in size");
+  let (mean, variance) = (0..len).into_par_iter().map(|i| {
+    let count = frames.len() as f64;
+    let mean = frames.iter().map(|n| n.as_ref()[i]).sum::<f64>() / count;
+    let variance = match frames.len() {
+      1 => 0.0,
+      _ => frames.iter().map(|n| (n.as_ref()[i] - mean).powi(2)).sum::<f64>() / (count - 1.0)
+    };
+    (mean, variance)
+  }).unzip();
+  Ok(StackStats { frames: frames.len(), mean, variance })
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct DefectThresholds {
+  /// dark mean above the channel median by more than this [DN]
+  pub hot: f64,
+  /// dark temporal variance above this times the channel median
+  pub noisy: f64,
+  /// flat mean (dark subtracted) below this times the channel median
+  pub dead: f64,
+  /// flat temporal variance at or below this, needs `STUCK_MIN_FRAMES` frames or more
+  pub stuck: f64,
+}
+
+/// integer flats of low noise repeat a value by chance across 2 frames, rarely across 3
+pub const STUCK_MIN_FRAMES : usize = 3;
+
+impl Default for DefectThresholds {
+  fn default() -> Self {
+    DefectThresholds { hot: 100.0, noisy: 10.0, dead: 0.5, stuck: 0.0 }
+  }
+}
+
+/// median of each CFA channel
+fn channel_median(src:&[f64], width:usize) -> [f64; 4] {
+  let mut channels: [Vec<f64>; 4] = Default::default();
+  src.iter().enumerate().for_each(|(i, n)| channels[i % width % 2 + (i / width % 2) * 2].push(*n));
+  channels.map(|mut n| {
+    if n.is_empty() { return 0.0; }
+    let mid = n.len() / 2;
+    *n.select_nth_unstable_by(mid, f64::total_cmp).1
+  })
+}
+
+/// one kind per pixel, dead and stuck first. dark and flat must be the same size
+pub fn detect_defects(dark:Option<&StackStats>, flat:Option<&StackStats>, width:usize, thresholds:&DefectThresholds) -> anyhow::Result<Vec<Defect>> {
+  let channel = |i:usize| i % width % 2 + (i / width % 2) * 2;
+  let len = dark.or(flat).map(|n| n.mean.len()).unwrap_or_default();
+  for (name, stats) in [("dark", dark), ("flat", flat)] {
+    let Some(stats) = stats else { continue };
+    anyhow::ensure!(stats.mean.len() == len && stats.variance.len() == len,
+      "defects : {name} has {} means and {} variances, {len} expected", stats.mean.len(), stats.variance.len());
+  }
+  anyhow::ensure!(width > 0 || len == 0, "defects : width must not be 0");
+  let signal = flat.map(|f| f.mean.iter().enumerate().map(|(i, n)| n - dark.map(|d| d.mean[i]).unwrap_or_default()).collect::<Vec<_>>());
+  let flat_median = signal.as_ref().map(|n| channel_median(n, width));
+  let dark_median = dark.map(|n| channel_median(&n.mean, width));
+  let variance_median = dark.map(|n| channel_median(&n.variance, width));
+
+  Ok((0..len).filter_map(|i| {
+    let c = channel(i);
+    let kind = match () {
+      _ if signal.as_ref().is_some_and(|n| n[i] < thresholds.dead * flat_median.unwrap()[c]) => DefectKind::Dead,
+      _ if flat.is_some_and(|n| n.frames >= STUCK_MIN_FRAMES && n.variance[i] <= thresholds.stuck) => DefectKind::Stuck,
+      _ if dark.is_some_and(|n| n.mean[i] > dark_median.unwrap()[c] + thresholds.hot) => DefectKind::Hot,
+      _ if dark.is_some_and(|n| n.variance[i] > thresholds.noisy * variance_median.unwrap()[c]) => DefectKind::Noisy,
+      _ => return None
+    };
+    Some(Defect { x: i % width, y: i / width, kind })
+  }).collect::<Vec<_>>())
+}
+
+/// u8 mask of `width * height`, see `DefectKind::code`
+pub fn defect_mask(defects:&[Defect], width:usize, height:usize) -> Vec<u8> {
+  let mut dst = vec![0u8; width * height];
+  defects.iter().filter(|n| n.x < width && n.y < height).for_each(|n| dst[n.x + n.y * width] = n.kind.code());
+  dst
+}
+
+/// median of the valid neighbours of the same CFA channel (2 pixels away with `cfa`, adjacent otherwise)
+pub fn correct_defects<T>(src:&mut [T], width:usize, height:usize, defects:&[Defect], cfa:bool)
+where T : Copy + Into<f64> + ClampFrom<f64> {
+  let mask = defect_mask(defects, width, height);
+  let step = if cfa { 2 } else { 1 };
+  let fixed = defects.iter().filter(|n| n.x < width && n.y < height).filter_map(|n| {
+    let (x, y) = (n.x as isize, n.y as isize);
+    let mut values = [(x - step, y), (x + step, y), (x, y - step), (x, y + step)].iter()
+      .filter(|(x, y)| *x >= 0 && *y >= 0 && (*x as usize) < width && (*y as usize) < height)
+      .map(|(x, y)| *x as usize + *y as usize * width)
+      .filter(|i| mask[*i] == 0)
+      .map(|i| src[i].into())
+      .collect::<Vec<f64>>();
+    if values.is_empty() { return None; }
+    values.sort_unstable_by(f64::total_cmp);
+    let mid = values.len() / 2;
+    let value = if values.len() % 2 == 0 { (values[mid - 1] + values[mid]) / 2.0 } else { values[mid] };
+    Some((n.x + n.y * width, value))
+  }).collect::<Vec<_>>();
+  fixed.into_iter().for_each(|(i, n)| src[i] = T::clamp_from(n.round()));
+}
+
+impl Hraw {
+
+  /// `defects` of the header, empty when not given
+  pub fn defects(&mut self) -> anyhow::Result<Vec<Defect>> {
+    let header = self.header().to_struct();
+    Ok(match header.defects {
+      None => Vec::new(),
+      Some(HeaderDefects::List(list)) => list.into_iter().map(|n| match n {
+        HeaderDefect::Pair([x, y]) => Defect { x, y, kind: DefectKind::Hot },
+        HeaderDefect::Defect(n) => n,
+      }).collect(),
+      Some(HeaderDefects::Entry(path)) => {
+        let mask = self.read_bytes(&path).with_context(|| format!("defects : {path}"))?;
+        anyhow::ensure!(mask.len() >= header.total, "defects : {path} is shorter than width * height");
+        mask.iter().take(header.total).enumerate().filter(|(_, n)| **n != 0)
+          .map(|(i, n)| Ok(Defect { x: i % header.width, y: i / header.width, kind: DefectKind::from_code(*n).with_context(|| format!("defects : unknown code {n}"))? }))
+          .collect::<anyhow::Result<Vec<_>>>()?
+      }
+    })
+  }
+
+  /// detection from dark and / or flat data entries
+  pub fn detect_defects<T:PathOrIndex + Copy>(&mut self, dark:&[T], flat:&[T], thresholds:&DefectThresholds, policy:&ScriptPolicy) -> anyhow::Result<Vec<Defect>> {
+    let width = self.header().to_struct().width;
+    let mut stats = |subpaths:&[T]| -> anyhow::Result<Option<StackStats>> {
+      if subpaths.is_empty() { return Ok(None); }
+      let frames = subpaths.iter().map(|n| self.frame_f64(*n, policy)).collect::<anyhow::Result<Vec<_>>>()?;
+      stack_stats(&frames).map(Some)
+    };
+    let (dark, flat) = (stats(dark)?, stats(flat)?);
+    anyhow::ensure!(dark.is_some() || flat.is_some(), "defects : no dark or flat frames");
+    detect_defects(dark.as_ref(), flat.as_ref(), width, thresholds)
+  }
+
+}
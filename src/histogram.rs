@@ -0,0 +1,179 @@
+use crate::*;
+use crate::processing::{plot_png, PlotSeries};
+use rayon::prelude::*;
+
+/*** histogram and percentiles ***/
+
+/*
+  bins cover `range`, default : the full range of the integer bitfield, white_level or the data min / max otherwise
+    bin i : [min + i * bin_width, min + (i + 1) * bin_width), values outside the range go to the first / last bin
+  default bin width : 1 up to 65536 values, the range / 65536 above, the range / 1024 for floats
+  clipped : values at or beyond the ends of the range, white_level below the range end replaces it
+  NaN : counted in `nan` only, not in `counts` / `total`
+  per CFA channel (x % 2 + (y % 2) * 2) with `cfa`
+*/
+
+/// at most 2^24 bins
+const MAX_BINS : usize = 1 << 24;
+
+/// [min, max] of an integer bitfield
+pub fn bitfield_range(bitfield:BitField) -> Option<[f64; 2]> {
+  use BitField::*;
+  match bitfield {
+    le_i8 | be_i8 => Some([i8::MIN as f64, i8::MAX as f64]),
+    le_i16 | be_i16 => Some([i16::MIN as f64, i16::MAX as f64]),
+    le_i32 | be_i32 => Some([i32::MIN as f64, i32::MAX as f64]),
+    le_i64 | be_i64 => Some([i64::MIN as f64, i64::MAX as f64]),
+    _ => default_white_level(bitfield).filter(|n| *n > 1.0).map(|n| [0.0, n])
+  }
+}
+
+#[derive(Debug, Clone)]
+pub struct HistogramOptions {
+  /// default : see above
+  pub bin_width: Option<f64>,
+  /// default : see above
+  pub range: Option<[f64; 2]>,
+  pub cfa: bool,
+  /// [%] reported in `Histogram::percentiles`
+  pub percentiles: Vec<f64>,
+}
+
+impl Default for HistogramOptions {
+  fn default() -> Self {
+    HistogramOptions { bin_width: None, range: None, cfa: false, percentiles: vec![0.1, 1.0, 5.0, 50.0, 95.0, 99.0, 99.9] }
+  }
+}
+
+#[derive(serde::Serialize, Debug, Clone, PartialEq)]
+pub struct Histogram {
+  /// CFA index, 0 without cfa
+  pub channel: usize,
+  pub range: [f64; 2],
+  pub bin_width: f64,
+  pub counts: Vec<u64>,
+  pub total: u64,
+  /// values <= range[0]
+  pub clipped_low: u64,
+  /// values >= white_level or range[1]
+  pub clipped_high: u64,
+  /// NaN values, left out of `counts` and `total`
+  pub nan: u64,
+  pub mean: f64,
+  /// (percent, value) of `HistogramOptions::percentiles`
+  pub percentiles: Vec<(f64, f64)>,
+}
+
+impl Histogram {
+
+  /// lower edge of the bin holding `percent` [%] of the pixels, `range[0]` when empty
+  pub fn percentile(&self, percent:f64) -> f64 {
+    let target = (percent / 100.0).clamp(0.0, 1.0) * self.total as f64;
+    let mut sum = 0u64;
+    for (i, n) in self.counts.iter().enumerate() {
+      sum += n;
+      if *n > 0 && sum as f64 >= target { return (self.range[0] + i as f64 * self.bin_width).min(self.range[1]); }
+    }
+    self.range[0]
+  }
+
+  pub fn clipped(&self) -> u64 { self.clipped_low + self.clipped_high }
+
+}
+
+/// `[range, bin_width, bins]` of `options`, data min / max of `src` as the last resort
+fn bins<T:Copy + Into<f64> + Sync>(src:&[T], bitfield:Option<BitField>, white_level:Option<f64>, options:&HistogramOptions) -> anyhow::Result<([f64; 2], f64, usize)> {
+  let range = match options.range.or(bitfield.and_then(bitfield_range)).or(white_level.map(|n| [0.0, n])) {
+    Some(n) => n,
+    None => src.par_iter().map(|n| (*n).into()).filter(|n:&f64| !n.is_nan()).fold(|| [f64::INFINITY, f64::NEG_INFINITY], |[min, max], n| [min.min(n), max.max(n)])
+      .reduce(|| [f64::INFINITY, f64::NEG_INFINITY], |a, b| [a[0].min(b[0]), a[1].max(b[1])]),
+  };
+  anyhow::ensure!(range[0].is_finite() && range[1].is_finite() && range[0] <= range[1], "histogram : invalid range {range:?}");
+  let span = range[1] - range[0];
+  let integer = bitfield.is_some_and(|n| bitfield_range(n).is_some());
+  let bin_width = options.bin_width.unwrap_or(match integer {
+    true => (span / 65536.0).ceil().max(1.0),
+    false => if span > 0.0 { span / 1024.0 } else { 1.0 }
+  });
+  anyhow::ensure!(bin_width.is_finite() && bin_width > 0.0, "histogram : bin width {bin_width} is not positive");
+  let bins = (span / bin_width).floor() + 1.0;
+  anyhow::ensure!(bins <= MAX_BINS as f64, "histogram : {bins} bins, use a larger bin width");
+  Ok((range, bin_width, bins as usize))
+}
+
+/// histograms of a `width x height` frame, one per CFA channel with `cfa`
+pub fn histogram<T:Copy + Into<f64> + Sync>(src:&[T], width:usize, height:usize, bitfield:Option<BitField>, white_level:Option<f64>, options:&HistogramOptions) -> anyhow::Result<Vec<Histogram>> {
+  anyhow::ensure!(src.len() >= width * height, "histogram : {} pixels, {width}x{height} expected", src.len());
+  let src = &src[0..width * height];
+  let (range, bin_width, bins) = bins(src, bitfield, white_level, options)?;
+  let channels = if options.cfa { 4 } else { 1 };
+  let channel = |i:usize| if options.cfa { i % width % 2 + (i / width % 2) * 2 } else { 0 };
+  let white = white_level.filter(|n| *n < range[1]).unwrap_or(range[1]);
+
+  /* channelごとに [counts.., clipped_low, clipped_high, nan] と合計 */
+  let empty = || (vec![vec![0u64; bins + 3]; channels], vec![0f64; channels]);
+  let (counts, sums) = src.par_iter().enumerate().fold(empty, |(mut counts, mut sums), (i, n)| {
+    let value = (*n).into();
+    let c = channel(i);
+    if value.is_nan() {
+      counts[c][bins + 2] += 1;
+      return (counts, sums);
+    }
+    let bin = ((value - range[0]) / bin_width).floor().clamp(0.0, (bins - 1) as f64) as usize;
+    counts[c][bin] += 1;
+    if value <= range[0] { counts[c][bins] += 1; }
+    if value >= white { counts[c][bins + 1] += 1; }
+    sums[c] += value;
+    (counts, sums)
+  }).reduce(empty, |(mut a, mut sa), (b, sb)| {
+    a.iter_mut().zip(b.iter()).for_each(|(a, b)| a.iter_mut().zip(b.iter()).for_each(|(a, b)| *a += b));
+    sa.iter_mut().zip(sb.iter()).for_each(|(a, b)| *a += b);
+    (a, sa)
+  });
+
+  Ok(counts.into_iter().zip(sums).enumerate().map(|(channel, (mut counts, sum))| {
+    let nan = counts.pop().unwrap();
+    let clipped_high = counts.pop().unwrap();
+    let clipped_low = counts.pop().unwrap();
+    let total = counts.iter().sum::<u64>();
+    let mut dst = Histogram { channel, range, bin_width, counts, total, clipped_low, clipped_high, nan, mean: sum / total.max(1) as f64, percentiles: Vec::new() };
+    dst.percentiles = options.percentiles.iter().map(|p| (*p, dst.percentile(*p))).collect();
+    dst
+  }).collect())
+}
+
+/// counts against the bin value from `range[0]`, log10(1 + count) with `log`
+pub fn histogram_png(histograms:&[Histogram], width:usize, height:usize, log:bool) -> Vec<u8> {
+  const COLORS : [[u8; 3]; 4] = [[220, 0, 0], [0, 160, 0], [0, 100, 60], [0, 80, 255]];
+  let series = histograms.iter().map(|n| PlotSeries {
+    points: n.counts.iter().enumerate().map(|(i, c)| (i as f64 * n.bin_width, if log { (*c as f64 + 1.0).log10() } else { *c as f64 })).collect(),
+    color: if histograms.len() == 1 { [40, 40, 40] } else { COLORS[n.channel % 4] },
+    line: true,
+  }).collect::<Vec<_>>();
+  plot_png(&series, width, height)
+}
+
+/// black at the `low` percentile (per channel with several histograms), white at the highest `high` percentile [%]
+pub fn auto_levels(histograms:&[Histogram], low:f64, high:f64) -> anyhow::Result<Levels> {
+  anyhow::ensure!(!histograms.is_empty(), "histogram : no channels");
+  let black = histograms.iter().map(|n| n.percentile(low)).collect::<Vec<_>>();
+  let white = histograms.iter().map(|n| n.percentile(high) + n.bin_width).fold(f64::NEG_INFINITY, f64::max);
+  let black = match black.as_slice() {
+    [a, b, c, d] => BlackLevel::Cfa([*a, *b, *c, *d]),
+    _ => BlackLevel::Global(black.iter().copied().fold(f64::INFINITY, f64::min)),
+  };
+  Ok(Levels { black, white })
+}
+
+impl Hraw {
+
+  /// histograms of frame `subpath` over the active area (aligned to even x / y with `cfa`)
+  pub fn histogram<T:PathOrIndex>(&mut self, subpath:T, options:&HistogramOptions, policy:&ScriptPolicy) -> anyhow::Result<Vec<Histogram>> {
+    let header = self.header().to_struct();
+    let rect = if options.cfa { self.active_area()?.align_cfa() } else { self.active_area()? };
+    let src = crop(&self.frame_f64(subpath, policy)?, header.width, &rect);
+    let bitfield = Some(header.bitfield).filter(|n| *n != BitField::unknown);
+    histogram(&src, rect.width, rect.height, bitfield, header.white_level, options)
+  }
+
+}
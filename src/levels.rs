@@ -0,0 +1,158 @@
+use crate::*;
+use crate::buffer::FromHraw;
+use crate::regions::Rect;
+
+/*** black / white level ***/
+
+/*
+  black_level : 64                  # global
+  black_level : [64, 65, 64, 66]    # per CFA channel, index = x % 2 + (y % 2) * 2
+  black_level : { ob : rows }       # estimated from optical_black (global | cfa | rows | columns)
+  white_level : 4095                # default : max of the integer bitfield, 1.0 for floats
+  optical_black : { left : 16, right : 0, top : 0, bottom : 0 }   # masked margins [pixel] or rectangles, see `regions`
+*/
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ObMode { Global, Cfa, Rows, Columns }
+
+/// `black_level` as written in the header
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum HeaderBlackLevel {
+  Global(f64),
+  Cfa([f64; 4]),
+  Ob { ob: ObMode },
+}
+
+/// masked margins of the frame
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+pub struct ObMargins {
+  #[serde(default)]
+  pub left: usize,
+  #[serde(default)]
+  pub right: usize,
+  #[serde(default)]
+  pub top: usize,
+  #[serde(default)]
+  pub bottom: usize,
+}
+
+#[derive(serde::Serialize, Debug, Clone, PartialEq)]
+pub enum BlackLevel {
+  Global(f64),
+  Cfa([f64; 4]),
+  /// per row, `height` values
+  Rows(Vec<f64>),
+  /// per column, `width` values
+  Columns(Vec<f64>),
+}
+
+impl BlackLevel {
+  #[inline(always)]
+  pub fn at(&self, x:usize, y:usize) -> f64 {
+    match self {
+      BlackLevel::Global(n) => *n,
+      BlackLevel::Cfa(n) => n[x % 2 + (y % 2) * 2],
+      BlackLevel::Rows(n) => n[y],
+      BlackLevel::Columns(n) => n[x],
+    }
+  }
+}
+
+#[derive(serde::Serialize, Debug, Clone, PartialEq)]
+pub struct Levels {
+  pub black: BlackLevel,
+  pub white: f64,
+}
+
+impl Levels {
+
+  /// 0 at black, 1 at white
+  #[inline(always)]
+  pub fn normalize(&self, value:f64, x:usize, y:usize) -> f64 {
+    let black = self.black.at(x, y);
+    (value - black) / (self.white - black)
+  }
+
+  /// `normalize` of the whole frame
+  pub fn apply<T:Copy + Into<f64> + Sync>(&self, src:&[T], width:usize, height:usize) -> Vec<f64> {
+    use rayon::prelude::*;
+    src[0..width*height].par_iter().enumerate().map(|(i, n)| self.normalize((*n).into(), i % width, i / width)).collect()
+  }
+
+  /// value - black, white is not applied
+  pub fn subtract<T:Copy + Into<f64> + Sync>(&self, src:&[T], width:usize, height:usize) -> Vec<f64> {
+    use rayon::prelude::*;
+    src[0..width*height].par_iter().enumerate().map(|(i, n)| (*n).into() - self.black.at(i % width, i / width)).collect()
+  }
+
+}
+
+/// mean of the pixels in `optical_black` per `mode`, every row (column) needs masked pixels with `rows` (`columns`)
+pub fn estimate_black_level<T:Copy + Into<f64>>(src:&[T], width:usize, height:usize, optical_black:&[Rect], mode:ObMode) -> anyhow::Result<BlackLevel> {
+  anyhow::ensure!(src.len() >= width * height, "black level : {} pixels, {width}x{height} expected", src.len());
+  anyhow::ensure!(optical_black.iter().all(|n| n.is_inside(width, height)), "black level : optical_black out of range {width}x{height}");
+  let mean = |pixels:&mut dyn Iterator<Item = (usize, usize)>| -> Option<f64> {
+    let (sum, count) = pixels.fold((0f64, 0usize), |(sum, count), (x, y)| (sum + src[x + y * width].into(), count + 1));
+    (count > 0).then(|| sum / count as f64)
+  };
+  let masked = |&(x, y):&(usize, usize)| optical_black.iter().any(|n| n.contains(x, y));
+  let all = || (0..height).flat_map(move |y| (0..width).map(move |x| (x, y)));
+
+  let dst = match mode {
+    ObMode::Global => mean(&mut all().filter(masked)).map(BlackLevel::Global),
+    ObMode::Cfa => (0..4).map(|c| mean(&mut all().filter(|n| masked(n) && n.0 % 2 + (n.1 % 2) * 2 == c)))
+      .collect::<Option<Vec<_>>>().map(|n| BlackLevel::Cfa([n[0], n[1], n[2], n[3]])),
+    ObMode::Rows => (0..height).map(|y| mean(&mut (0..width).map(|x| (x, y)).filter(masked)))
+      .collect::<Option<Vec<_>>>().map(BlackLevel::Rows),
+    ObMode::Columns => (0..width).map(|x| mean(&mut (0..height).map(|y| (x, y)).filter(masked)))
+      .collect::<Option<Vec<_>>>().map(BlackLevel::Columns),
+  };
+  dst.ok_or(anyhow::anyhow!("black level : optical_black has no pixels for {mode:?}"))
+}
+
+/// max of an integer bitfield, 1.0 for floats
+pub fn default_white_level(bitfield:BitField) -> Option<f64> {
+  use BitField::*;
+  match bitfield {
+    le_u8 | be_u8 => Some(u8::MAX as f64),
+    le_i8 | be_i8 => Some(i8::MAX as f64),
+    le_u16 | be_u16 => Some(u16::MAX as f64),
+    le_i16 | be_i16 => Some(i16::MAX as f64),
+    le_u32 | be_u32 => Some(u32::MAX as f64),
+    le_i32 | be_i32 => Some(i32::MAX as f64),
+    le_u64 | be_u64 => Some(u64::MAX as f64),
+    le_i64 | be_i64 => Some(i64::MAX as f64),
+    le_f32 | be_f32 | le_f64 | be_f64 => Some(1.0),
+    unknown => None
+  }
+}
+
+impl Hraw {
+
+  /// `black_level` and `white_level` of the header, `{ ob : .. }` is estimated from frame `subpath`
+  pub fn levels<T:PathOrIndex>(&mut self, subpath:T, policy:&ScriptPolicy) -> anyhow::Result<Levels> {
+    let header = self.header().to_struct();
+    let black = match header.black_level {
+      None => BlackLevel::Global(0.0),
+      Some(HeaderBlackLevel::Global(n)) => BlackLevel::Global(n),
+      Some(HeaderBlackLevel::Cfa(n)) => BlackLevel::Cfa(n),
+      Some(HeaderBlackLevel::Ob { ob }) => self.estimate_black_level(subpath, ob, policy)?,
+    };
+    let white = header.white_level.or(default_white_level(header.bitfield)).context("white_level is required with bitfield unknown")?;
+    Ok(Levels { black, white })
+  }
+
+  /// black level of frame `subpath` from the `optical_black` regions of the header
+  pub fn estimate_black_level<T:PathOrIndex>(&mut self, subpath:T, mode:ObMode, policy:&ScriptPolicy) -> anyhow::Result<BlackLevel> {
+    let header = self.header().to_struct();
+    let optical_black = self.optical_black()?;
+    anyhow::ensure!(!optical_black.is_empty(), "black level : optical_black is not declared");
+    let path = subpath.to_name(self)?;
+    let mut src = vec![0f64; header.total];
+    src.as_mut_slice().from_hraw_with(&self.path, path.as_str(), policy)?;
+    estimate_black_level(&src, header.width, header.height, &optical_black, mode)
+  }
+
+}
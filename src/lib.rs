@@ -8,16 +8,32 @@ pub mod opencv;
 
 pub mod processing;
 pub mod rawnumber;
+pub mod access;
+pub mod mapping;
+pub mod writer;
+pub mod decoder;
+pub mod levels;
+pub mod regions;
+pub mod calibration;
+pub mod defects;
+pub mod convert;
+pub mod characterization;
+pub mod noise;
+pub mod histogram;
 // pub mod extension;
 // use byteorder::LE;
 // use std::borrow::Cow;
 // use std::io::BufReader;
 use rawnumber::*;
+use levels::*;
+use regions::*;
+use calibration::*;
+use defects::*;
+use characterization::*;
 
 use anyhow::Context as _;
 use serde_json::json;
 use std::io::Read;
-use paste::paste;
 use zip::read::ZipFile;
 
 const HEADER_LIST : [&str; 3]= ["header.yaml", "header.yml", "header.json"];
@@ -26,6 +42,7 @@ const DEFAULT_DATA : &str = "data.raw";
 /*** Hraw : Raw image format ***/
 
 pub struct Hraw {
+  path: String,
   zip: zip::ZipArchive<std::io::BufReader<std::fs::File>>,
 }
 
@@ -35,7 +52,7 @@ impl Hraw {
     let file = std::fs::File::open(path)?;
     let reader = std::io::BufReader::new(file);
     let zip: zip::ZipArchive<std::io::BufReader<std::fs::File>> = zip::ZipArchive::new(reader)?;
-    Ok(Hraw { zip })
+    Ok(Hraw { path: path.to_string(), zip })
   }
 
   pub fn info(&mut self) -> anyhow::Result<()> {
@@ -93,15 +110,34 @@ impl Hraw {
 
 }
 
+/// `decoder` and `encoder` sections
 #[derive(serde::Serialize, serde::Deserialize, Debug)]
 pub struct HeaderDecoder {
   #[serde(default = "default_lang")]
   lang: String,
   #[serde(default)]
-  code: String
+  code: String,
+  #[serde(default)]
+  file: Option<String>,
+  #[serde(default)]
+  modules: Vec<String>,
+  /// `lang: layout` only
+  #[serde(default)]
+  layout: Option<Layout>
 }
 fn default_lang() -> String { "lua".to_string() }
 
+/// `postprocess` section, script keys of `decoder` or `ops` with `lang: ops`
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct HeaderPostprocess {
+  #[serde(flatten)]
+  script: HeaderDecoder,
+  #[serde(default)]
+  ops: Vec<PostOp>,
+  #[serde(default)]
+  inputs: std::collections::BTreeMap<String, String>
+}
+
 #[allow(unused)]
 #[derive(serde::Serialize, serde::Deserialize, Debug)]
 pub struct Header {
@@ -122,7 +158,33 @@ pub struct Header {
   data      : Vec<serde_json::Value>,
 
   #[serde(default)]
-  decoder   : Option<HeaderDecoder>
+  decoder   : Option<HeaderDecoder>,
+
+  #[serde(default)]
+  encoder   : Option<HeaderDecoder>,
+
+  #[serde(default)]
+  postprocess : Option<HeaderPostprocess>,
+
+  #[serde(default)]
+  black_level : Option<HeaderBlackLevel>,
+  #[serde(default)]
+  white_level : Option<f64>,
+  #[serde(default)]
+  optical_black : Option<HeaderOpticalBlack>,
+  #[serde(default)]
+  active_area : Option<Rect>,
+  #[serde(default)]
+  embedded_lines : Option<HeaderEmbeddedLines>,
+
+  #[serde(default)]
+  calibration : Option<HeaderCalibration>,
+
+  #[serde(default)]
+  defects : Option<HeaderDefects>,
+
+  #[serde(default)]
+  exposure : Option<HeaderExposure>
 }
 fn default_bitfield() -> BitField { BitField::le_i32 }
 fn default_data() -> Vec<serde_json::Value> { serde_json::json!([DEFAULT_DATA]).as_array().unwrap().to_owned() }
@@ -199,20 +261,21 @@ pub mod buffer {
     stream : ZipFile<'a>,
     index: usize,
     max: usize,
+    width: usize,
     phantom: std::marker::PhantomData<T>
   }
 
   pub trait HrawEnumerater {
-    fn enumerate_index<T: RawNumber>(&mut self, path:usize) -> HrawIterator<T>;
-    fn enumerate_path<T: RawNumber>(&mut self, path:&str) -> HrawIterator<T>;
-    fn enumerate_poi<T:RawNumber, U:PathOrIndex>(&mut self, subpath:U) -> HrawIterator<T>;
+    fn enumerate_index<T: RawNumber>(&mut self, path:usize) -> HrawIterator<'_, T>;
+    fn enumerate_path<T: RawNumber>(&mut self, path:&str) -> HrawIterator<'_, T>;
+    fn enumerate_poi<T:RawNumber, U:PathOrIndex>(&mut self, subpath:U) -> HrawIterator<'_, T>;
   }
   impl HrawEnumerater for Hraw {
-    fn enumerate_index<T: RawNumber>(&mut self, subpath: usize) -> HrawIterator<T> {
+    fn enumerate_index<T: RawNumber>(&mut self, subpath: usize) -> HrawIterator<'_, T> {
       let path = self.header().to_data_dict(subpath).unwrap_or(DEFAULT_DATA.to_owned());
       self.enumerate_path(path.as_str())
     }
-    fn enumerate_path<T: RawNumber>(&mut self, subpath: &str) -> HrawIterator<T> {
+    fn enumerate_path<T: RawNumber>(&mut self, subpath: &str) -> HrawIterator<'_, T> {
       let header = &self.header().to_struct();
       let mut stream = self.zip.by_name(subpath).unwrap();
       let mut buf = [0u8];
@@ -220,81 +283,125 @@ pub mod buffer {
         let _ = stream.read_exact(&mut buf);
       }
       HrawIterator {
-        stream: stream,
+        stream,
         index: 0,
         max: header.total,
+        width: header.width,
         phantom: std::marker::PhantomData
       }
     }
-    fn enumerate_poi<T:RawNumber, U:PathOrIndex>(&mut self, subpath: U) -> HrawIterator<T> {
+    fn enumerate_poi<T:RawNumber, U:PathOrIndex>(&mut self, subpath: U) -> HrawIterator<'_, T> {
       let path = subpath.to_name(self).unwrap();
       self.enumerate_path(path.as_str())
       
     }
   }
   
-  impl<'a> Iterator for HrawIterator<'a, be_i32> {
-    type Item = (usize, i32);
+  impl<'a, T: RawNumber> Iterator for HrawIterator<'a, T> {
+    type Item = (usize, T::Item);
     fn next(&mut self) -> Option<Self::Item> {
-      let mut buf : [u8; std::mem::size_of::<i32>()] = unsafe { std::mem::MaybeUninit::zeroed().assume_init() };
+      let mut buf = [0u8; 8];
       let current = self.index;
       self.index += 1;
       if current >= self.max { return None; }
-      if self.stream.read_exact(&mut buf).is_err() { return None; }
-      Some((current, i32::from_le_bytes(buf)))
+      if self.stream.read_exact(&mut buf[0..T::SIZE]).is_err() { return None; }
+      Some((current, T::from_bytes(&buf)))
     }
   }
 
-  macro_rules! impl_rawnum_iter { ($(($t:ident,$u:ty))*) => { paste! {
-    $(
-      impl<'a> Iterator for HrawIterator<'a, [<$t _ $u>]> {
-        type Item = (usize, $u);
-        fn next(&mut self) -> Option<Self::Item> {
-          let mut buf : [u8; std::mem::size_of::<$u>()] = unsafe { std::mem::MaybeUninit::zeroed().assume_init() };
-          let current = self.index;
-          self.index += 1;
-          if current >= self.max { return None; }
-          if self.stream.read_exact(&mut buf).is_err() { return None; }
-          Some((current, $u::[<from_ $t _bytes>](buf)))
-        }
-      }
-    )*
-  }}}
-  impl_rawnum_iter!{
-    (le,u8) (be,u8) (le,i8) (be,i8)
-    (le,u16) (be,u16) (le,i16) (be,i16)
-    (le,u32) (be,u32) (le,i32)
-    (le,u64) (be,u64) (le,i64) (be,i64)
-    (le,f32) (be,f32) (le,f64) (be,f64)
+
+  /* 2D adaptors, width comes from the header */
+  impl<'a, T: RawNumber + 'a> HrawIterator<'a, T> {
+    pub fn width(&self) -> usize { self.width }
+
+    /// (x, y, value)
+    pub fn xy(self) -> impl Iterator<Item = (usize, usize, T::Item)> + 'a {
+      let width = self.width;
+      self.map(move |(i, n)| (i % width, i / width, n))
+    }
+
+    /// (y, row), an incomplete last row is dropped
+    pub fn rows(self) -> HrawRows<Self> {
+      HrawRows { width: self.width, iter: self, y: 0 }
+    }
+
+    /// (y, value) of column `x`
+    pub fn column(self, x:usize) -> impl Iterator<Item = (usize, T::Item)> + 'a {
+      let width = self.width;
+      self.filter(move |(i, _)| i % width == x).map(move |(i, n)| (i / width, n))
+    }
+
+    /// every `step` pixel from `origin`, yields (x, y, value) in subsampled coordinates. panics when a step is 0, as `step_by`
+    pub fn subsample(self, (left, top):(usize, usize), (step_x, step_y):(usize, usize)) -> impl Iterator<Item = (usize, usize, T::Item)> + 'a {
+      assert!(step_x > 0 && step_y > 0, "subsample : step ({step_x}, {step_y}) must be positive");
+      self.xy()
+        .filter(move |(x, y, _)| *x >= left && *y >= top && (x - left) % step_x == 0 && (y - top) % step_y == 0)
+        .map(move |(x, y, n)| ((x - left) / step_x, (y - top) / step_y, n))
+    }
+
+    /// one CFA plane of a 2x2 bayer pattern, `origin` is (0|1, 0|1)
+    pub fn bayer_plane(self, origin:(usize, usize)) -> impl Iterator<Item = (usize, usize, T::Item)> + 'a {
+      self.subsample(origin, (2, 2))
+    }
   }
 
+  pub struct HrawRows<I> {
+    iter: I,
+    width: usize,
+    y: usize,
+  }
+  impl<I: Iterator<Item = (usize, V)>, V> Iterator for HrawRows<I> {
+    type Item = (usize, Vec<V>);
+    fn next(&mut self) -> Option<Self::Item> {
+      let row = self.iter.by_ref().take(self.width).map(|n| n.1).collect::<Vec<V>>();
+      if row.is_empty() || row.len() < self.width { return None; }
+      self.y += 1;
+      Some((self.y - 1, row))
+    }
+  }
 
+  #[allow(clippy::wrong_self_convention)]
   pub trait FromHraw {
-    fn from_hraw<T:PathOrIndex>(&mut self, path:&str, subpath:T);
+    /// decoder scripts run with `ScriptPolicy::default()`, python decoders are refused with an error
+    fn from_hraw<T:PathOrIndex>(&mut self, path:&str, subpath:T) -> anyhow::Result<()> {
+      self.from_hraw_with(path, subpath, &ScriptPolicy::default())
+    }
+    fn from_hraw_with<T:PathOrIndex>(&mut self, path:&str, subpath:T, policy:&ScriptPolicy) -> anyhow::Result<()> {
+      let mut raw = Hraw::new(path)?;
+      let header = raw.header();
+      self.from_raw_with(&mut raw, &header, subpath, policy)
+    }
+    /// `from_hraw_with` of an open archive and its `header`
+    fn from_raw_with<T:PathOrIndex>(&mut self, raw:&mut Hraw, header:&serde_json::Value, subpath:T, policy:&ScriptPolicy) -> anyhow::Result<()>;
+    /// `from_hraw_with` followed by the `postprocess` section of the header, if any
+    fn from_hraw_processed<T:PathOrIndex>(&mut self, path:&str, subpath:T, policy:&ScriptPolicy) -> anyhow::Result<()> where Self : HrawScripting {
+      let mut raw = Hraw::new(path)?;
+      let header = raw.header();
+      self.from_raw_with(&mut raw, &header, subpath, policy)?;
+      let Some(source) = raw.postprocess_source_of(&header)? else { return Ok(()) };
+      let inputs = raw.postprocess_inputs_of(&source, &header, policy)?;
+      self.postprocess(&source, &inputs, &header, policy)
+    }
   }
-  macro_rules! impl_from_hraw { ($t:tt; $self:ident, $path:ident, $subpath:ident; $($tt:tt)*) => {
-    let mut raw = Hraw::new($path).unwrap();
-    let header = raw.header().to_struct();
+  macro_rules! impl_from_hraw { ($t:tt; $self:ident, $raw:ident, $value:ident, $subpath:ident, $policy:ident; $($tt:tt)*) => {
+    let header = $value.to_struct();
     match header.bitfield {
       $(
-        BitField::$tt => raw.enumerate_poi::<$tt, _>($subpath).for_each(|(i, n)| { $self[i] = $t::clamp_from(n); }),
+        BitField::$tt => $raw.enumerate_poi::<$tt, _>($subpath).for_each(|(i, n)| { $self[i] = $t::clamp_from(n); }),
       )*
       BitField::unknown => {
-        let decoder = header.decoder.unwrap();
-        let vec = raw.to_vec_poi($subpath).unwrap(); // ランダムアクセスさせるので一度全部読む
-        match decoder.lang.as_str() {
-          "py" => { $self.from_py_script(decoder.code.as_str(), vec.as_slice(), header.width, header.height); },
-          _=> { $self.from_lua_script(decoder.code.as_str(), vec.as_slice(), header.width, header.height); }
-        }
+        let decoder = $raw.decoder_source_of($value)?;
+        let vec = $raw.to_vec_poi($subpath)?; // ランダムアクセスさせるので一度全部読む
+        $self.from_source(&decoder, vec.as_slice(), $value, $policy)?;
       },
     }
+    Ok(())
   }}
 
   impl FromHraw for [i32] {
-    fn from_hraw<T:PathOrIndex>(&mut self, path:&str, subpath:T) {
-
+    fn from_raw_with<T:PathOrIndex>(&mut self, raw:&mut Hraw, header:&serde_json::Value, subpath:T, policy:&ScriptPolicy) -> anyhow::Result<()> {
       impl_from_hraw!{
-        i32; self, path, subpath;
+        i32; self, raw, header, subpath, policy;
         le_u8 be_u8 le_i8 be_i8
         le_u16 be_u16 le_i16 be_i16
         le_u32 be_u32 le_i32 be_i32
@@ -304,9 +411,9 @@ pub mod buffer {
     }
   }
   impl FromHraw for [f32] {
-    fn from_hraw<T:PathOrIndex>(&mut self, path:&str, subpath:T) {
+    fn from_raw_with<T:PathOrIndex>(&mut self, raw:&mut Hraw, header:&serde_json::Value, subpath:T, policy:&ScriptPolicy) -> anyhow::Result<()> {
       impl_from_hraw!{
-        f32; self, path, subpath;
+        f32; self, raw, header, subpath, policy;
         le_u8 be_u8 le_i8 be_i8
         le_u16 be_u16 le_i16 be_i16
         le_u32 be_u32 le_i32 be_i32
@@ -316,9 +423,9 @@ pub mod buffer {
     }
   }
   impl FromHraw for [f64] {
-    fn from_hraw<T:PathOrIndex>(&mut self, path:&str, subpath:T) {
+    fn from_raw_with<T:PathOrIndex>(&mut self, raw:&mut Hraw, header:&serde_json::Value, subpath:T, policy:&ScriptPolicy) -> anyhow::Result<()> {
       impl_from_hraw!{
-        f64; self, path, subpath;
+        f64; self, raw, header, subpath, policy;
         le_u8 be_u8 le_i8 be_i8
         le_u16 be_u16 le_i16 be_i16
         le_u32 be_u32 le_i32 be_i32
@@ -330,6 +437,7 @@ pub mod buffer {
 
 }
 
+#[allow(clippy::wrong_self_convention)]
 pub trait FromPng {
   fn from_png(&mut self, path:&str);
 }
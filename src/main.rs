@@ -0,0 +1,145 @@
+use clap::{Parser, Subcommand};
+use hraw::rawnumber::ScriptPolicy;
+use hraw::processing::{plot_png, PlotSeries};
+use hraw::HrawHeader;
+
+/*** hraw command line ***/
+
+#[derive(Parser)]
+#[command(name = "hraw", version, about = "raw image archive utils")]
+struct Cli {
+  #[command(subcommand)]
+  command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+  /// mean / std / min / max of the frames as le_f32 entries in a new archive
+  Convert {
+    src: String,
+    #[arg(short, long)]
+    output: String,
+    /// first `num` entries of data, default : all
+    #[arg(long)]
+    num: Option<usize>,
+    /// scale of the written values, 100 for x100 fixed point
+    #[arg(long, default_value_t = 1.0)]
+    fixed: f64,
+  },
+  /// EMVA 1288 style metrics from archives of one exposure level each, as json
+  Characterize {
+    archives: Vec<String>,
+    #[arg(short, long)]
+    output: String,
+    /// directory of ptc.png and linearity.png
+    #[arg(long)]
+    plots: Option<String>,
+  },
+  /// row / column profiles, FPN, noise decomposition and power spectra per CFA channel, as json
+  Noise {
+    src: String,
+    #[arg(short, long)]
+    output: String,
+    /// first `num` entries of data, default : all
+    #[arg(long)]
+    num: Option<usize>,
+    /// the whole frame as one channel
+    #[arg(long)]
+    mono: bool,
+    /// directory of profile_{channel}.png, spectrum_{channel}.png (1D) and spectrum_2d_{channel}.png
+    #[arg(long)]
+    plots: Option<String>,
+  },
+  /// histogram over the full bitfield range with percentiles and clipped counts, as json
+  Histogram {
+    src: String,
+    #[arg(short, long)]
+    output: String,
+    /// index in data
+    #[arg(long, default_value_t = 0)]
+    index: usize,
+    #[arg(long)]
+    bin_width: Option<f64>,
+    /// bins from MIN to MAX instead of the bitfield range
+    #[arg(long, num_args = 2, value_names = ["MIN", "MAX"], allow_negative_numbers = true)]
+    range: Option<Vec<f64>>,
+    /// one histogram per CFA channel
+    #[arg(long)]
+    cfa: bool,
+    /// histogram png, log10(1 + count) with `--log`
+    #[arg(long)]
+    png: Option<String>,
+    #[arg(long)]
+    log: bool,
+    /// preview png stretched from the `--low` to the `--high` percentile, `--color` of slice_to_png
+    #[arg(long)]
+    preview: Option<String>,
+    #[arg(long, default_value_t = 0.1)]
+    low: f64,
+    #[arg(long, default_value_t = 99.9)]
+    high: f64,
+    #[arg(long, default_value_t = 0)]
+    color: i32,
+  },
+}
+
+fn main() -> anyhow::Result<()> {
+  let cli = Cli::parse();
+  let policy = ScriptPolicy::default();
+  match cli.command {
+    Command::Convert { src, output, num, fixed } => {
+      let options = hraw::convert::ConvertOptions { num, fixed };
+      let stats = hraw::convert::convert(&src, &output, &options, &policy)?;
+      println!("{output} : {} frames", stats.frames);
+    },
+    Command::Characterize { archives, output, plots } => {
+      let archives = archives.iter().map(String::as_str).collect::<Vec<_>>();
+      let report = hraw::characterization::characterize_archives(&archives, &policy)?;
+      std::fs::write(&output, serde_json::to_string_pretty(&report)?)?;
+      if let Some(dir) = plots {
+        std::fs::create_dir_all(&dir)?;
+        std::fs::write(std::path::Path::new(&dir).join("ptc.png"), report.ptc_png(640, 480))?;
+        std::fs::write(std::path::Path::new(&dir).join("linearity.png"), report.linearity_png(640, 480))?;
+      }
+      println!("{output} : K {:.4} DN/e-, read noise {:.2} e-, full well {:.0} e-", report.conversion_gain, report.read_noise_e, report.full_well_e);
+    },
+    Command::Noise { src, output, num, mono, plots } => {
+      let mut hraw = hraw::Hraw::new(&src)?;
+      let count = num.unwrap_or(hraw.header()["data"].as_array().map(Vec::len).unwrap_or(1));
+      let analysis = hraw.noise_analysis(&(0..count).collect::<Vec<_>>(), !mono, &policy)?;
+      std::fs::write(&output, serde_json::to_string_pretty(&analysis)?)?;
+      if let Some(dir) = plots {
+        std::fs::create_dir_all(&dir)?;
+        let line = |src:&[f64], color:[u8; 3], offset:f64| PlotSeries { points: src.iter().enumerate().map(|(i, n)| (i as f64, n - offset)).collect(), color, line: true };
+        for n in analysis.channels.iter() {
+          let path = |name:&str| std::path::Path::new(&dir).join(format!("{name}_{}.png", n.channel));
+          std::fs::write(path("profile"), plot_png(&[line(&n.row_profile, [220, 0, 0], n.mean), line(&n.column_profile, [0, 120, 255], n.mean)], 640, 480))?;
+          std::fs::write(path("spectrum"), plot_png(&[line(&n.vertical_spectrum, [220, 0, 0], 0.0), line(&n.horizontal_spectrum, [0, 120, 255], 0.0)], 640, 480))?;
+          std::fs::write(path("spectrum_2d"), hraw::noise::spectrum_png(&n.spectrum_2d(), n.width, n.height))?;
+        }
+      }
+      for n in analysis.channels.iter() {
+        println!("{output} : channel {} total {:.3} temporal {:.3} spatial {:.3} row fpn {:.3} column fpn {:.3}", n.channel, n.total, n.temporal, n.spatial, n.row_fpn, n.column_fpn);
+      }
+    },
+    Command::Histogram { src, output, index, bin_width, range, cfa, png, log, preview, low, high, color } => {
+      let mut hraw = hraw::Hraw::new(&src)?;
+      let range = range.map(|n| [n[0], n[1]]);
+      let options = hraw::histogram::HistogramOptions { bin_width, range, cfa, ..Default::default() };
+      let histograms = hraw.histogram(index, &options, &policy)?;
+      std::fs::write(&output, serde_json::to_string_pretty(&histograms)?)?;
+      if let Some(path) = png {
+        std::fs::write(path, hraw::histogram::histogram_png(&histograms, 640, 480, log))?;
+      }
+      if let Some(path) = preview {
+        let (width, height, _) = hraw.header().to_size();
+        let frame = hraw.frame_f64(index, &policy)?;
+        std::fs::write(path, hraw::processing::slice_to_png_auto(&frame, width, height, low, high, None, color)?)?;
+      }
+      for n in histograms.iter() {
+        println!("{output} : channel {} clipped {} / {}, median {}", n.channel, n.clipped(), n.total, n.percentile(50.0));
+      }
+    },
+  }
+  Ok(())
+}
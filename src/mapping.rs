@@ -0,0 +1,47 @@
+use crate::*;
+use zip::CompressionMethod;
+
+/*** memory mapped entry ***/
+
+/// read-only view of a stored (uncompressed) data entry, starting after the header `offset`
+pub struct HrawMap {
+  mmap: memmap2::Mmap,
+  bitfield: BitField,
+  total: usize,
+}
+
+impl Hraw {
+
+  pub fn map_entry<T:PathOrIndex>(&mut self, subpath:T) -> anyhow::Result<HrawMap> {
+    let header = self.header().to_struct();
+    let path = subpath.to_name(self)?;
+    let file = self.zip.by_name(path.as_str())?;
+    anyhow::ensure!(file.compression() == CompressionMethod::Stored, "{path} : compressed entry can not be mapped");
+    anyhow::ensure!(header.offset as u64 <= file.size(), "{path} : offset exceeds entry");
+    let start = file.data_start() + header.offset as u64;
+    let len = (file.size() - header.offset as u64) as usize;
+    drop(file);
+
+    let raw = std::fs::File::open(&self.path)?;
+    let mmap = unsafe { memmap2::MmapOptions::new().offset(start).len(len).map(&raw)? };
+    Ok(HrawMap { mmap, bitfield: header.bitfield, total: header.total })
+  }
+
+}
+
+impl HrawMap {
+
+  pub fn as_bytes(&self) -> &[u8] { &self.mmap }
+
+  /// zero-copy typed view of `width * height` pixels.
+  /// fails unless `T` is the header bitfield in native byte order and the entry is aligned for `T::Item`
+  pub fn as_slice<T:RawNumber>(&self) -> anyhow::Result<&[T::Item]> {
+    anyhow::ensure!(self.bitfield == T::BITFIELD, "bitfield {:?} does not match {:?}", self.bitfield, T::BITFIELD);
+    anyhow::ensure!(T::NATIVE, "{:?} is not native byte order", T::BITFIELD);
+    anyhow::ensure!(self.mmap.len() >= self.total * T::SIZE, "entry is shorter than width * height");
+    let ptr = self.mmap.as_ptr();
+    anyhow::ensure!(ptr.align_offset(std::mem::align_of::<T::Item>()) == 0, "entry is not aligned to {} bytes", std::mem::align_of::<T::Item>());
+    Ok(unsafe { std::slice::from_raw_parts(ptr.cast::<T::Item>(), self.total) })
+  }
+
+}
@@ -0,0 +1,220 @@
+use crate::*;
+use crate::convert::Welford;
+use rayon::prelude::*;
+use rustfft::num_complex::Complex;
+
+/*** spatial noise : profiles, row / column FPN and power spectra ***/
+
+/*
+  each CFA channel (x % 2 + (y % 2) * 2) is a half resolution plane, `cfa : false` uses the whole frame
+  variances of a stack of L frames [DN²], reported as standard deviations [DN]
+    temporal   : mean of the per pixel variance
+    spatial    : variance of the mean frame - temporal / L
+    total      : mean of the variance of each frame
+    row_fpn    : variance of the row means of the mean frame - their temporal variance / L, column_fpn likewise
+    pixel_fpn  : spatial - row_fpn - column_fpn
+    row_temporal : per row variance of the row means across frames - temporal / plane width (banding changing every frame)
+  power spectrum : |fft|² / n of the mean frame without its mean, averaged over rows (horizontal) or columns (vertical)
+*/
+
+/// CFA channels of `cfa`, a single channel 0 otherwise
+pub fn channels(cfa:bool) -> std::ops::Range<usize> { if cfa { 0..4 } else { 0..1 } }
+
+/// (width, height) of `channel`
+pub fn plane_size(width:usize, height:usize, channel:usize, cfa:bool) -> (usize, usize) {
+  if !cfa { return (width, height); }
+  ((width + 1).saturating_sub(channel % 2) / 2, (height + 1).saturating_sub(channel / 2) / 2)
+}
+
+/// (width, height, pixels) of `channel`
+pub fn plane(src:&[f64], width:usize, height:usize, channel:usize, cfa:bool) -> (usize, usize, Vec<f64>) {
+  if !cfa { return (width, height, src[0..width * height].to_vec()); }
+  let (ox, oy) = (channel % 2, channel / 2);
+  let (w, h) = plane_size(width, height, channel, cfa);
+  (w, h, (0..w * h).map(|i| src[(ox + i % w * 2) + (oy + i / w * 2) * width]).collect())
+}
+
+pub fn row_means(src:&[f64], width:usize, height:usize) -> Vec<f64> {
+  (0..height).map(|y| src[y * width..(y + 1) * width].iter().sum::<f64>() / width as f64).collect()
+}
+
+pub fn column_means(src:&[f64], width:usize, height:usize) -> Vec<f64> {
+  (0..width).map(|x| (0..height).map(|y| src[x + y * width]).sum::<f64>() / height as f64).collect()
+}
+
+fn mean(src:&[f64]) -> f64 { src.iter().sum::<f64>() / src.len().max(1) as f64 }
+
+/// sample variance, 0 below 2 values
+fn variance(src:&[f64]) -> f64 {
+  if src.len() < 2 { return 0.0; }
+  let mean = mean(src);
+  src.iter().map(|n| (n - mean).powi(2)).sum::<f64>() / (src.len() - 1) as f64
+}
+
+/// |fft|² / n of each line of `len` in `src`, averaged, frequencies 0 ..= len / 2 [cycles / len]
+fn line_spectrum(src:&[f64], len:usize) -> Vec<f64> {
+  if len == 0 { return Vec::new(); }
+  let fft = rustfft::FftPlanner::<f64>::new().plan_fft_forward(len);
+  let lines = src.len() / len;
+  let sum = src.par_chunks_exact(len).map(|line| {
+    let mut buf = line.iter().map(|n| Complex::new(*n, 0.0)).collect::<Vec<_>>();
+    fft.process(&mut buf);
+    buf[0..=len / 2].iter().map(|n| n.norm_sqr() / len as f64).collect::<Vec<_>>()
+  }).reduce(|| vec![0.0; len / 2 + 1], |a, b| a.iter().zip(b.iter()).map(|(a, b)| a + b).collect());
+  sum.into_iter().map(|n| n / lines.max(1) as f64).collect()
+}
+
+fn transpose(src:&[f64], width:usize, height:usize) -> Vec<f64> {
+  (0..width * height).map(|i| src[i / height + (i % height) * width]).collect()
+}
+
+/// 1D power spectrum of `src` without its mean
+pub fn power_spectrum(src:&[f64]) -> Vec<f64> {
+  if src.is_empty() { return Vec::new(); }
+  let mean = mean(src);
+  line_spectrum(&src.iter().map(|n| n - mean).collect::<Vec<_>>(), src.len())
+}
+
+/// 2D power spectrum |fft|² / (width * height) of `src` without its mean, shifted with DC at (width / 2, height / 2), empty for an empty plane
+pub fn power_spectrum_2d(src:&[f64], width:usize, height:usize) -> Vec<f64> {
+  if width * height == 0 { return Vec::new(); }
+  let mean = mean(&src[0..width * height]);
+  let mut buf = src[0..width * height].iter().map(|n| Complex::new(n - mean, 0.0)).collect::<Vec<_>>();
+  let mut planner = rustfft::FftPlanner::<f64>::new();
+  let (rows, columns) = (planner.plan_fft_forward(width), planner.plan_fft_forward(height));
+  buf.par_chunks_exact_mut(width).for_each(|n| rows.process(n));
+  let mut transposed = (0..width * height).map(|i| buf[i / height + (i % height) * width]).collect::<Vec<_>>();
+  transposed.par_chunks_exact_mut(height).for_each(|n| columns.process(n));
+  let total = (width * height) as f64;
+  (0..width * height).map(|i| {
+    let (x, y) = ((i % width + width - width / 2) % width, (i / width + height - height / 2) % height);
+    transposed[y + x * height].norm_sqr() / total
+  }).collect()
+}
+
+/// log scaled gray image of a `power_spectrum_2d`
+pub fn spectrum_png(spectrum:&[f64], width:usize, height:usize) -> Vec<u8> {
+  let log = spectrum.iter().map(|n| (n + 1e-12).log10()).collect::<Vec<_>>();
+  let (min, max) = log.iter().fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), n| (min.min(*n), max.max(*n)));
+  let scale = if max > min { 255.0 / (max - min) } else { 0.0 };
+  let img = image::GrayImage::from_fn(width as u32, height as u32, |x, y| image::Luma([((log[x as usize + y as usize * width] - min) * scale).round() as u8]));
+  let mut writer = Vec::new();
+  img.write_with_encoder(image::codecs::png::PngEncoder::new(&mut writer)).unwrap();
+  writer
+}
+
+#[derive(serde::Serialize, Debug, Clone)]
+pub struct NoiseChannel {
+  /// CFA index, 0 without cfa
+  pub channel: usize,
+  pub width: usize,
+  pub height: usize,
+  pub mean: f64,
+  /// standard deviations [DN]
+  pub total: f64,
+  pub temporal: f64,
+  pub spatial: f64,
+  pub row_fpn: f64,
+  pub column_fpn: f64,
+  pub pixel_fpn: f64,
+  pub row_temporal: f64,
+  pub column_temporal: f64,
+  /// row means of the mean frame, `height` values
+  pub row_profile: Vec<f64>,
+  /// column means of the mean frame, `width` values
+  pub column_profile: Vec<f64>,
+  /// power along x averaged over rows, `width / 2 + 1` values
+  pub horizontal_spectrum: Vec<f64>,
+  /// power along y averaged over columns, `height / 2 + 1` values
+  pub vertical_spectrum: Vec<f64>,
+  /// mean frame of the channel
+  #[serde(skip)]
+  pub mean_frame: Vec<f64>,
+}
+
+impl NoiseChannel {
+  /// `power_spectrum_2d` of the mean frame
+  pub fn spectrum_2d(&self) -> Vec<f64> { power_spectrum_2d(&self.mean_frame, self.width, self.height) }
+}
+
+#[derive(serde::Serialize, Debug, Clone)]
+pub struct NoiseAnalysis {
+  pub frames: usize,
+  pub cfa: bool,
+  pub channels: Vec<NoiseChannel>,
+}
+
+struct NoiseAccumulator {
+  width: usize,
+  height: usize,
+  pixels: Welford,
+  rows: Welford,
+  columns: Welford,
+  total: f64,
+}
+
+/// `width x height` frames streamed one at a time, 2 frames or more
+pub fn analyze_noise<I:Iterator<Item = anyhow::Result<Vec<f64>>>>(frames:I, width:usize, height:usize, cfa:bool) -> anyhow::Result<NoiseAnalysis> {
+  let mut acc = channels(cfa).map(|c| {
+    let (w, h) = plane_size(width, height, c, cfa);
+    NoiseAccumulator { width: w, height: h, pixels: Welford::new(w * h), rows: Welford::new(h), columns: Welford::new(w), total: 0.0 }
+  }).collect::<Vec<_>>();
+  let mut count = 0;
+  for frame in frames {
+    let frame = frame?;
+    anyhow::ensure!(frame.len() >= width * height, "noise : {} pixels, {width}x{height} expected", frame.len());
+    for (c, n) in acc.iter_mut().enumerate() {
+      let (w, h, src) = plane(&frame, width, height, c, cfa);
+      n.pixels.push(&src)?;
+      n.rows.push(&row_means(&src, w, h))?;
+      n.columns.push(&column_means(&src, w, h))?;
+      n.total += variance(&src);
+    }
+    count += 1;
+  }
+  anyhow::ensure!(count >= 2, "noise : 2 frames or more are required");
+  let frames = count as f64;
+
+  let channels = acc.into_iter().enumerate().map(|(channel, n)| {
+    let (w, h) = (n.width, n.height);
+    let mean_frame = n.pixels.mean();
+    let temporal = mean(&n.pixels.variance());
+    let spatial = variance(&mean_frame) - temporal / frames;
+    let row_profile = row_means(&mean_frame, w, h);
+    let column_profile = column_means(&mean_frame, w, h);
+    let (rows, columns) = (mean(&n.rows.variance()), mean(&n.columns.variance()));
+    let row_fpn = variance(&row_profile) - rows / frames;
+    let column_fpn = variance(&column_profile) - columns / frames;
+    let level = mean(&mean_frame);
+    let residual = mean_frame.iter().map(|v| v - level).collect::<Vec<_>>();
+    let sd = |n:f64| n.max(0.0).sqrt();
+    NoiseChannel {
+      channel, width: w, height: h,
+      mean: level,
+      total: sd(n.total / frames),
+      temporal: sd(temporal),
+      spatial: sd(spatial),
+      row_fpn: sd(row_fpn),
+      column_fpn: sd(column_fpn),
+      pixel_fpn: sd(spatial - row_fpn.max(0.0) - column_fpn.max(0.0)),
+      row_temporal: sd(rows - temporal / w as f64),
+      column_temporal: sd(columns - temporal / h as f64),
+      horizontal_spectrum: line_spectrum(&residual, w),
+      vertical_spectrum: line_spectrum(&transpose(&residual, w, h), h),
+      row_profile, column_profile, mean_frame,
+    }
+  }).collect();
+  Ok(NoiseAnalysis { frames: count, cfa, channels })
+}
+
+impl Hraw {
+
+  /// `analyze_noise` of data entries over the active area, aligned to even x / y with `cfa`
+  pub fn noise_analysis<T:PathOrIndex + Copy>(&mut self, subpaths:&[T], cfa:bool, policy:&ScriptPolicy) -> anyhow::Result<NoiseAnalysis> {
+    let header = self.header().to_struct();
+    let rect = if cfa { self.active_area()?.align_cfa() } else { self.active_area()? };
+    let frames = subpaths.iter().map(|n| self.frame_f64(*n, policy).map(|n| crop(&n, header.width, &rect)));
+    analyze_noise(frames, rect.width, rect.height, cfa)
+  }
+
+}
@@ -26,7 +26,7 @@ pub mod test {
 
     let mut vec_i32 = vec![0i32; 640*480];
     let mut vec_u8 = vec![0u8; 640*480];
-    vec_i32.as_mut_slice().from_hraw(TEST_FILE_I32, "data.raw");
+    vec_i32.as_mut_slice().from_hraw(TEST_FILE_I32, "data.raw")?;
 
     vec_i32.iter().zip(vec_u8.iter_mut()).for_each(|(a, b)|{ 
       *b = num::clamp(*a >> 8 , u8::MIN as i32, u8::MAX as i32) as u8; 
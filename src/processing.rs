@@ -48,7 +48,7 @@ impl CustomConverter for i32 {
   }
   #[inline(always)]
   fn bitshift(self, shift:i32) -> i32 {
-    if shift > 0 { self >> shift } else { self << -1*shift }
+    if shift > 0 { self >> shift } else { self << -shift }
   }  
   #[inline(always)]
   fn to_rgb(self) -> image::Rgb<u8> {
@@ -126,7 +126,7 @@ pub fn slice_to_png (src: &[i32], width:usize, height:usize, bitshift:i32, mat:
         /* png to png, RBBA */
         (-1, x, y) => dm.view_range(x, y).to_scalar().to_rgb(),
         /* bayer to RGB */
-        (1..=4, x, y) if 0 < x && x < width as usize - 1 && 0 < y && y < height as usize - 1 => {
+        (1..=4, x, y) if 0 < x && x < width - 1 && 0 < y && y < height - 1 => {
           let view = dm.view((x - 1, y - 1), (3, 3));
           let slice = na::Matrix3::new(
             view[(0, 0)].bitshift(bitshift) as f64, view[(0, 1)].bitshift(bitshift) as f64, view[(0, 2)].bitshift(bitshift) as f64,
@@ -152,6 +152,110 @@ pub fn slice_to_png (src: &[i32], width:usize, height:usize, bitshift:i32, mat:
 }
 
 
+/// `slice_to_png` after black subtraction, `levels.white` maps to 255 instead of a bitshift. `slice_to_png_with` combines it with defects and stretch
+pub fn slice_to_png_levels(src: &[i32], width:usize, height:usize, levels:&crate::levels::Levels, mat: Option<[[f64;3];3]>, color:i32) -> Vec<u8> {
+  let scaled = levels.apply(src, width, height).into_iter()
+    .map(|n| (n * u8::MAX as f64).round() as i32)
+    .collect::<Vec<_>>();
+  slice_to_png(&scaled, width, height, 0, mat, color)
+}
+
+/// `slice_to_png` after defect correction, CFA aware for the bayer colors (1..=8). `slice_to_png_with` combines it with levels and stretch
+pub fn slice_to_png_defects(src: &[i32], width:usize, height:usize, defects:&[crate::defects::Defect], bitshift:i32, mat: Option<[[f64;3];3]>, color:i32) -> Vec<u8> {
+  let mut corrected = src[0..(width*height)].to_vec();
+  crate::defects::correct_defects(&mut corrected, width, height, defects, (1..=8).contains(&color));
+  slice_to_png(&corrected, width, height, bitshift, mat, color)
+}
+
+
+/// `slice_to_png_levels` stretched from the `low` to the `high` percentile [%], black per CFA channel for the bayer colors (1..=8)
+pub fn slice_to_png_auto<T:Copy + Into<f64> + Sync>(src: &[T], width:usize, height:usize, low:f64, high:f64, mat: Option<[[f64;3];3]>, color:i32) -> anyhow::Result<Vec<u8>> {
+  slice_to_png_with(src, width, height, &PngOptions { stretch: Some((low, high)), mat, color, ..Default::default() })
+}
+
+/// preprocessing of `slice_to_png_with`, applied in order : defects -> black / white -> stretch
+#[derive(Debug, Clone, Default)]
+pub struct PngOptions {
+  /// corrected first, CFA aware for the bayer colors (1..=8)
+  pub defects: Vec<crate::defects::Defect>,
+  /// black subtraction, `white` maps to 255
+  pub levels: Option<crate::levels::Levels>,
+  /// `low` and `high` percentiles [%] mapped to 0 and 255, black per CFA channel for the bayer colors
+  pub stretch: Option<(f64, f64)>,
+  /// used when neither `levels` nor `stretch` is given
+  pub bitshift: i32,
+  pub mat: Option<[[f64;3];3]>,
+  pub color: i32,
+}
+
+/// `slice_to_png` after the preprocessing of `options`
+pub fn slice_to_png_with<T:Copy + Into<f64> + Sync>(src: &[T], width:usize, height:usize, options:&PngOptions) -> anyhow::Result<Vec<u8>> {
+  let cfa = (1..=8).contains(&options.color);
+  let mut values = src[0..(width*height)].iter().map(|n| (*n).into()).collect::<Vec<f64>>();
+  crate::defects::correct_defects(&mut values, width, height, &options.defects, cfa);
+  if let Some(levels) = &options.levels {
+    values = levels.apply(&values, width, height);
+  }
+  if let Some((low, high)) = options.stretch {
+    let histograms = crate::histogram::histogram(&values, width, height, None, None, &crate::histogram::HistogramOptions { cfa, ..Default::default() })?;
+    values = crate::histogram::auto_levels(&histograms, low, high)?.apply(&values, width, height);
+  }
+  // levels, stretchの後は0..1
+  let normalized = options.levels.is_some() || options.stretch.is_some();
+  let scale = if normalized { u8::MAX as f64 } else { 1.0 };
+  let scaled = values.iter().map(|n| (n * scale).round() as i32).collect::<Vec<_>>();
+  Ok(slice_to_png(&scaled, width, height, if normalized { 0 } else { options.bitshift }, options.mat, options.color))
+}
+
+/// points of a `plot_png` chart, joined with `line`
+#[derive(Debug, Clone)]
+pub struct PlotSeries {
+  pub points: Vec<(f64, f64)>,
+  pub color: [u8; 3],
+  pub line: bool,
+}
+
+/// series on a white `width x height` chart without labels, both axes scaled to the data with 0 included
+pub fn plot_png(series:&[PlotSeries], width:usize, height:usize) -> Vec<u8> {
+  const MARGIN : f64 = 8.0;
+  let mut img = image::RgbImage::from_pixel(width as u32, height as u32, image::Rgb([255, 255, 255]));
+  let points = || series.iter().flat_map(|n| n.points.iter()).filter(|(x, y)| x.is_finite() && y.is_finite());
+  let range = |f:fn(&(f64, f64)) -> f64| {
+    let (min, max) = points().map(f).fold((0f64, 0f64), |(min, max), n| (min.min(n), max.max(n)));
+    (min, if max > min { max } else { min + 1.0 })
+  };
+  let ((x0, x1), (y0, y1)) = (range(|n| n.0), range(|n| n.1));
+  let (w, h) = ((width as f64 - MARGIN * 2.0).max(1.0), (height as f64 - MARGIN * 2.0).max(1.0));
+  let to_px = |(x, y):(f64, f64)| (MARGIN + (x - x0) / (x1 - x0) * w, MARGIN + h - (y - y0) / (y1 - y0) * h);
+  let mut put = |(x, y):(f64, f64), color:[u8; 3]| {
+    if x >= 0.0 && y >= 0.0 && (x as u32) < img.width() && (y as u32) < img.height() { img.put_pixel(x as u32, y as u32, image::Rgb(color)); }
+  };
+
+  /* 軸 : x = 0, y = 0 */
+  let (ox, oy) = to_px((0.0, 0.0));
+  (0..width).for_each(|x| put((x as f64, oy), [160, 160, 160]));
+  (0..height).for_each(|y| put((ox, y as f64), [160, 160, 160]));
+
+  for n in series.iter() {
+    let pixels = n.points.iter().filter(|(x, y)| x.is_finite() && y.is_finite()).map(|n| to_px(*n)).collect::<Vec<_>>();
+    if n.line {
+      pixels.windows(2).for_each(|pair| {
+        let (a, b) = (pair[0], pair[1]);
+        let steps = (b.0 - a.0).abs().max((b.1 - a.1).abs()).ceil().max(1.0) as usize;
+        (0..=steps).for_each(|i| {
+          let t = i as f64 / steps as f64;
+          put((a.0 + (b.0 - a.0) * t, a.1 + (b.1 - a.1) * t), n.color);
+        });
+      });
+    } else {
+      pixels.iter().for_each(|(x, y)| (-1..=1).for_each(|dy| (-1..=1).for_each(|dx| put((x + dx as f64, y + dy as f64), n.color))));
+    }
+  }
+  let mut writer = Vec::new();
+  img.write_with_encoder(PngEncoder::new(&mut writer)).unwrap();
+  writer
+}
+
 
 #[allow(dead_code)]
 #[deprecated]
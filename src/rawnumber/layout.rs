@@ -0,0 +1,164 @@
+use crate::rawnumber::{ClampFrom, ScriptFault, CheckResult, check_number, script_size};
+
+/*
+  declarative decoder, interpreted natively (no script engine)
+    decoder :
+      lang : layout
+      layout :
+        group : 3            # bytes per group, 1..=16
+        endian : le          # the group is read as one le / be integer, bit 0 is its lsb
+        row_bytes : 0        # bytes per row including padding, groups restart at each row. 0 : continuous
+        samples :            # samples of one group in pixel order
+          - { bits : [0, 12], repeat : 2 }
+  a sample is `bits : [start, end)` or `fields` (msb first), concatenated into one value of at most 64 bits.
+  `repeat` copies the sample, each field moving by its own `step` (default : its width).
+  `signed` sign extends from the top bit of the sample.
+*/
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+pub struct Layout {
+  pub group: usize,
+  #[serde(default = "default_endian")]
+  pub endian: String,
+  #[serde(default)]
+  pub row_bytes: usize,
+  pub samples: Vec<LayoutSample>,
+}
+fn default_endian() -> String { "le".to_string() }
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Default)]
+pub struct LayoutSample {
+  #[serde(default)]
+  pub bits: Option<[u32; 2]>,
+  #[serde(default)]
+  pub fields: Vec<LayoutField>,
+  #[serde(default)]
+  pub signed: bool,
+  #[serde(default = "default_repeat")]
+  pub repeat: usize,
+}
+fn default_repeat() -> usize { 1 }
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+pub struct LayoutField {
+  pub bits: [u32; 2],
+  #[serde(default)]
+  pub step: Option<u32>,
+}
+
+/// (bit start, width) of each field, msb first
+#[derive(Debug, Clone)]
+struct Sample { fields: Vec<(u32, u32)>, width: u32, signed: bool }
+
+impl Sample {
+  #[inline(always)]
+  fn extract(&self, group:u128) -> f64 {
+    let value = self.fields.iter().fold(0u64, |dst, &(start, width)| {
+      let field = (group >> start) as u64 & (u64::MAX >> (64 - width));
+      dst.checked_shl(width).unwrap_or(0) | field
+    });
+    match self.signed {
+      true => ((value << (64 - self.width)) as i64 >> (64 - self.width)) as f64,
+      false => value as f64
+    }
+  }
+}
+
+impl Layout {
+
+  /// samples with `repeat` expanded, bit ranges validated
+  fn compile(&self) -> anyhow::Result<Vec<Sample>> {
+    anyhow::ensure!((1..=16).contains(&self.group), "layout : group must be 1..=16 bytes, got {}", self.group);
+    anyhow::ensure!(["le", "be"].contains(&self.endian.as_str()), "layout : endian must be le or be, got {}", self.endian);
+    anyhow::ensure!(self.row_bytes == 0 || self.row_bytes >= self.group, "layout : row_bytes is smaller than group");
+    let mut dst = Vec::new();
+    for (n, sample) in self.samples.iter().enumerate() {
+      let fields = match (sample.bits, sample.fields.is_empty()) {
+        (Some(bits), true) => vec![LayoutField { bits, step: None }],
+        (None, false) => sample.fields.clone(),
+        _ => anyhow::bail!("layout : sample {n} requires either bits or fields"),
+      };
+      for r in 0..sample.repeat as u32 {
+        let fields = fields.iter().map(|f| {
+          let width = f.bits[1].saturating_sub(f.bits[0]);
+          let start = f.bits[0] + r * f.step.unwrap_or(width);
+          anyhow::ensure!(width > 0, "layout : sample {n} has an empty bit range {:?}", f.bits);
+          anyhow::ensure!(start + width <= self.group as u32 * 8, "layout : sample {n} bits {}..{} exceed the group", start, start + width);
+          Ok((start, width))
+        }).collect::<anyhow::Result<Vec<_>>>()?;
+        let width = fields.iter().map(|n| n.1).sum::<u32>();
+        anyhow::ensure!(width <= 64, "layout : sample {n} is {width} bits, 64 at most");
+        dst.push(Sample { fields, width, signed: sample.signed });
+      }
+    }
+    anyhow::ensure!(!dst.is_empty(), "layout : no samples");
+    Ok(dst)
+  }
+
+  /// one group of `bytes` as an integer, bit 0 is the lsb
+  #[inline(always)]
+  fn read(&self, bytes:&[u8]) -> u128 {
+    let mut buf = [0u8; 16];
+    match self.endian.as_str() {
+      "be" => { buf[16 - self.group..].copy_from_slice(&bytes[..self.group]); u128::from_be_bytes(buf) },
+      _ => { buf[..self.group].copy_from_slice(&bytes[..self.group]); u128::from_le_bytes(buf) }
+    }
+  }
+
+  /// bytes of `width * height` pixels after the header offset
+  pub fn frame_bytes(&self, width:usize, height:usize) -> usize {
+    let per_group = self.samples.iter().map(|n| n.repeat).sum::<usize>().max(1);
+    match self.row_bytes {
+      0 => (width * height).div_ceil(per_group) * self.group,
+      row_bytes => row_bytes * height.saturating_sub(1) + width.div_ceil(per_group) * self.group,
+    }
+  }
+
+}
+
+/// decodes `width * height` pixels of `src` (offset included) in parallel by rows
+pub fn layout_call<T>(layout:&Layout, src:&[u8], dst:&mut [T], header:&serde_json::Value) -> anyhow::Result<()>
+where T : ClampFrom<f64> + Send {
+  use rayon::prelude::*;
+  let samples = layout.compile()?;
+  let (width, height) = script_size(header)?;
+  anyhow::ensure!(width > 0 && height > 0, "layout : empty frame {width}x{height}");
+  let offset = header["offset"].as_u64().unwrap_or_default() as usize;
+  let need = offset + layout.frame_bytes(width, height);
+  anyhow::ensure!(src.len() >= need, "layout : {} bytes, {need} expected for {width}x{height}", src.len());
+  anyhow::ensure!(dst.len() >= width * height, "layout : dst has {} pixels, {width}x{height} expected", dst.len());
+
+  let (group, per_group) = (layout.group, samples.len());
+  let read = |at:usize| layout.read(&src[at..at + group]);
+  dst[0..width*height].par_chunks_mut(width).enumerate().for_each(|(y, row)| {
+    // row_bytesが無ければ行をまたいで連続
+    let (mut at, skip) = match layout.row_bytes {
+      0 => (offset + (y * width / per_group) * group, y * width % per_group),
+      row_bytes => (offset + y * row_bytes, 0),
+    };
+    let mut n = skip;
+    let mut value = read(at);
+    for d in row.iter_mut() {
+      if n == per_group { n = 0; at += group; value = read(at); }
+      *d = T::clamp_from(samples[n].extract(value));
+      n += 1;
+    }
+  });
+  Ok(())
+}
+
+/// each of `indices` is decoded alone, a pixel whose group is past the end of `src` is a fault of that pixel
+pub fn layout_check(layout:&Layout, src:&[u8], header:&serde_json::Value, indices:&[usize]) -> Result<Vec<CheckResult>, ScriptFault> {
+  let samples = layout.compile().map_err(|e| (None, e.to_string()))?;
+  let (width, _) = script_size(header).map_err(|e| (None, e.to_string()))?;
+  let offset = header["offset"].as_u64().unwrap_or_default() as usize;
+  let per_group = samples.len();
+  Ok(indices.iter().map(|&i| {
+    let (at, n) = match layout.row_bytes {
+      0 => (offset + i / per_group * layout.group, i % per_group),
+      row_bytes => (offset + i / width * row_bytes + i % width / per_group * layout.group, i % width % per_group),
+    };
+    let bytes = src.get(at..at + layout.group).ok_or((None, format!("layout : bytes {at}..{} out of range, {} bytes", at + layout.group, src.len())))?;
+    check_number(samples[n].extract(layout.read(bytes)))
+  }).collect())
+}
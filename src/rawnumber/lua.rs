@@ -0,0 +1,346 @@
+use mlua::prelude::*;
+use crate::rawnumber::{ClampFrom, ScriptModules, ScriptPolicy, ScriptFault, CheckResult, PostInputs, check_number, script_header, script_size};
+
+/// pixels per `decode_block` call
+pub const LUA_BLOCK : usize = 4096;
+
+/*
+  entry points
+    function(index)                 : chunk returns a function, called per pixel, `src` is a table (1-based)
+    decode_row(y, out)              : global, called per row
+    decode_block(start, count, out) : global, called per LUA_BLOCK pixels
+  row / block entries read `src` as a luau buffer (0-based) and write f64 to `out` (buffer.writef64)
+    f64 is exact up to 2^53, u64 / i64 values above lose their low bits
+  globals : `header` (read-only), `x` and `y` of the current pixel for function(index)
+  `src` is a single copy of the raw bytes per lua state (see `LuaSrc`), not zero-copy
+
+  encoder entry points, the inverse of the above. every call returns bytes (string or buffer), appended in order
+    function(index, value)            : chunk returns a function, called per pixel
+    encode_row(y, values)             : global, called per row
+    encode_block(start, count, values): global, called per LUA_BLOCK pixels
+  `values` is a luau buffer of f64 (buffer.readf64)
+*/
+/// `src` of one lua state, the table / buffer is created on first use and shared by the row ranges of the state
+pub struct LuaSrc<'a> {
+  bytes: &'a [u8],
+  table: std::cell::OnceCell<LuaRegistryKey>,
+  buffer: std::cell::OnceCell<LuaRegistryKey>,
+}
+
+impl<'a> LuaSrc<'a> {
+  pub fn new(bytes:&'a [u8]) -> LuaSrc<'a> {
+    LuaSrc { bytes, table: Default::default(), buffer: Default::default() }
+  }
+
+  fn get<'lua>(lua:&'lua Lua, key:&std::cell::OnceCell<LuaRegistryKey>, create:impl FnOnce() -> LuaResult<LuaValue<'lua>>) -> LuaResult<LuaValue<'lua>> {
+    if let Some(key) = key.get() { return lua.registry_value(key); }
+    let value = create()?;
+    let _ = key.set(lua.create_registry_value(value.clone())?);
+    Ok(value)
+  }
+
+  /// 1-based table for function(index)
+  pub fn table<'lua>(&self, lua:&'lua Lua) -> LuaResult<LuaValue<'lua>> {
+    Self::get(lua, &self.table, || self.bytes.into_lua(lua))
+  }
+
+  /// luau buffer for the row / block entries
+  pub fn buffer<'lua>(&self, lua:&'lua Lua) -> LuaResult<LuaValue<'lua>> {
+    Self::get(lua, &self.buffer, || lua.create_buffer(self.bytes).map(LuaValue::UserData))
+  }
+}
+
+pub trait LuaEx {
+  fn call_func<'lua, T : mlua::FromLuaMulti<'lua> + ClampFrom<f64>>(&'lua self, src:&[u8], dst:&mut [T], header:&serde_json::Value, code:&'lua str) -> anyhow::Result<()>;
+  /// decodes pixels `start..start + dst.len()`, `start` and `dst.len()` are multiples of `width`
+  fn call_range<'lua, T : mlua::FromLuaMulti<'lua> + ClampFrom<f64>>(&'lua self, src:&LuaSrc, dst:&mut [T], start:usize, header:&serde_json::Value, code:&'lua str) -> anyhow::Result<()>;
+  /// encodes pixels `start..start + src.len()`, same alignment as `call_range`
+  fn encode_range<T : Copy + Into<f64>>(&self, src:&[T], start:usize, header:&serde_json::Value, code:&str) -> anyhow::Result<Vec<u8>>;
+}
+
+impl LuaEx for Lua {
+  fn call_func<'lua, T : mlua::FromLuaMulti<'lua> + ClampFrom<f64>>(&'lua self, src:&[u8], dst:&mut [T], header:&serde_json::Value, code:&'lua str) -> anyhow::Result<()> {
+    let (width, height) = script_size(header)?;
+    self.call_range(&LuaSrc::new(src), &mut dst[0..width*height], 0, header, code)
+  }
+
+  fn call_range<'lua, T : mlua::FromLuaMulti<'lua> + ClampFrom<f64>>(&'lua self, src:&LuaSrc, dst:&mut [T], start:usize, header:&serde_json::Value, code:&'lua str) -> anyhow::Result<()> {
+    reset_interrupts(self);
+    let (width, _) = script_size(header)?;
+    let globals = self.globals();
+    globals.set("header", lua_readonly(self.to_value(&script_header(header))?)?)?;
+    if let LuaValue::Function(func) = self.load(code).eval::<LuaValue>()? {
+      globals.set("src", src.table(self)?)?;
+      return dst.iter_mut().enumerate().try_for_each(|(i, dst)|{ 
+        globals.raw_set("x", (start + i) % width)?;
+        globals.raw_set("y", (start + i) / width)?;
+        *dst = func.call::<_, T>(start + i).map_err(|e| anyhow::anyhow!("pixel {} : {e}", start + i))?;
+        Ok(())
+      });
+    }
+
+    globals.set("src", src.buffer(self)?)?;
+    let (func, chunk, is_row) = match (globals.get::<_, LuaFunction>("decode_row"), globals.get::<_, LuaFunction>("decode_block")) {
+      (Ok(func), _) => (func, width, true),
+      (_, Ok(func)) => (func, LUA_BLOCK, false),
+      _ => anyhow::bail!("entry point not found : function(index), decode_row or decode_block")
+    };
+    let out = self.create_buffer(vec![0u8; chunk * std::mem::size_of::<f64>()])?;
+    for (n, dst) in dst.chunks_mut(chunk).enumerate() {
+      match is_row {
+        true => func.call::<_, ()>((start / width + n, out.clone()))?,
+        false => func.call::<_, ()>((start + n * chunk, dst.len(), out.clone()))?
+      }
+      let buf = bstr::BString::from_lua(LuaValue::UserData(out.clone()), self)?;
+      buf.chunks_exact(std::mem::size_of::<f64>()).zip(dst.iter_mut())
+        .for_each(|(b, d)| *d = T::clamp_from(f64::from_ne_bytes(b.try_into().unwrap())));
+    }
+    Ok(())
+  }
+
+  fn encode_range<T : Copy + Into<f64>>(&self, src:&[T], start:usize, header:&serde_json::Value, code:&str) -> anyhow::Result<Vec<u8>> {
+    reset_interrupts(self);
+    let (width, _) = script_size(header)?;
+    let globals = self.globals();
+    globals.set("header", lua_readonly(self.to_value(&script_header(header))?)?)?;
+    let mut dst = Vec::new();
+    if let LuaValue::Function(func) = self.load(code).eval::<LuaValue>()? {
+      for (i, value) in src.iter().enumerate() {
+        globals.raw_set("x", (start + i) % width)?;
+        globals.raw_set("y", (start + i) / width)?;
+        dst.extend_from_slice(&func.call::<_, bstr::BString>((start + i, (*value).into()))?);
+      }
+      return Ok(dst);
+    }
+
+    let (func, chunk, is_row) = match (globals.get::<_, LuaFunction>("encode_row"), globals.get::<_, LuaFunction>("encode_block")) {
+      (Ok(func), _) => (func, width, true),
+      (_, Ok(func)) => (func, LUA_BLOCK, false),
+      _ => anyhow::bail!("entry point not found : function(index, value), encode_row or encode_block")
+    };
+    for (n, src) in src.chunks(chunk).enumerate() {
+      let values = self.create_buffer(src.iter().flat_map(|v| Into::<f64>::into(*v).to_ne_bytes()).collect::<Vec<u8>>())?;
+      let bytes = match is_row {
+        true => func.call::<_, bstr::BString>((start / width + n, values))?,
+        false => func.call::<_, bstr::BString>((start + n * chunk, src.len(), values))?
+      };
+      dst.extend_from_slice(&bytes);
+    }
+    Ok(dst)
+  }
+}
+
+fn lua_readonly(value:LuaValue) -> LuaResult<LuaValue> {
+  if let LuaValue::Table(table) = &value {
+    for pair in table.clone().pairs::<LuaValue, LuaValue>() {
+      lua_readonly(pair?.1)?;
+    }
+    table.set_readonly(true);
+  }
+  Ok(value)
+}
+
+/// lua state of a rayon worker, reused by the row ranges it runs (`map_init`)
+fn lua_state(modules:&ScriptModules, policy:&ScriptPolicy) -> anyhow::Result<Lua> {
+  let lua = policy.lua()?;
+  lua_require(&lua, modules)?;
+  Ok(lua)
+}
+
+/// the error of a failed `lua_state`, once per row range
+fn lua_ref(lua:&anyhow::Result<Lua>) -> anyhow::Result<&Lua> {
+  lua.as_ref().map_err(|e| anyhow::anyhow!("{e:#}"))
+}
+
+/// splits the frame into row aligned ranges, one lua state per rayon worker.
+/// every pixel only depends on `index` and `src`, so the result is the same as a serial run
+pub fn lua_par_call<T>(code:&str, modules:&ScriptModules, src:&[u8], dst:&mut [T], header:&serde_json::Value, policy:&ScriptPolicy) -> anyhow::Result<()>
+where T : for<'lua> mlua::FromLuaMulti<'lua> + ClampFrom<f64> + Send {
+  use rayon::prelude::*;
+  let (width, height) = script_size(header)?;
+  if width * height == 0 { return Ok(()); }
+  let rows = height.div_ceil(rayon::current_num_threads()).max(1);
+  let init = || (lua_state(modules, policy), LuaSrc::new(src));
+  dst[0..width*height].par_chunks_mut(rows * width).enumerate().try_for_each_init(init, |(lua, src), (n, dst)| {
+    lua_ref(lua)?.call_range(src, dst, n * rows * width, header, code)
+  })
+}
+
+/// `lua_par_call` for encoders, the bytes of each row range are concatenated in order
+pub fn lua_par_encode<T>(code:&str, modules:&ScriptModules, src:&[T], header:&serde_json::Value, policy:&ScriptPolicy) -> anyhow::Result<Vec<u8>>
+where T : Copy + Into<f64> + Sync {
+  use rayon::prelude::*;
+  let (width, height) = script_size(header)?;
+  anyhow::ensure!(src.len() >= width * height, "encoder : {} pixels, {width}x{height} expected", src.len());
+  if width * height == 0 { return Ok(Vec::new()); }
+  let rows = height.div_ceil(rayon::current_num_threads()).max(1);
+  let chunks = src[0..width*height].par_chunks(rows * width).enumerate().map_init(|| lua_state(modules, policy), |lua, (n, src)| {
+    lua_ref(lua)?.encode_range(src, n * rows * width, header, code)
+  }).collect::<anyhow::Result<Vec<_>>>()?;
+  Ok(chunks.concat())
+}
+
+/// per pixel `function(index, value, inputs)` of a `postprocess` section, row aligned ranges in parallel as `lua_par_call`
+pub fn lua_postprocess(code:&str, modules:&ScriptModules, values:&mut [f64], inputs:&PostInputs, header:&serde_json::Value, policy:&ScriptPolicy) -> anyhow::Result<()> {
+  use rayon::prelude::*;
+  let (width, height) = script_size(header)?;
+  if width * height == 0 { return Ok(()); }
+  let rows = height.div_ceil(rayon::current_num_threads()).max(1);
+  values[0..width*height].par_chunks_mut(rows * width).enumerate().try_for_each_init(|| lua_state(modules, policy), |lua, (n, dst)| {
+    let start = n * rows * width;
+    let lua = lua_ref(lua)?;
+    reset_interrupts(lua);
+    let globals = lua.globals();
+    globals.set("header", lua_readonly(lua.to_value(&script_header(header))?)?)?;
+    let func = lua.load(code).set_name("postprocess").eval::<LuaFunction>()?;
+    let table = lua.create_table()?; // 同じtableを使い回す
+    dst.iter_mut().enumerate().try_for_each(|(i, d)| {
+      let index = start + i;
+      globals.raw_set("x", index % width)?;
+      globals.raw_set("y", index / width)?;
+      inputs.iter().try_for_each(|(name, input)| table.raw_set(name.as_str(), input[index]))?;
+      *d = func.call::<_, f64>((index, *d, table.clone())).map_err(|e| anyhow::anyhow!("pixel {index} : {e}"))?;
+      Ok(())
+    })
+  })
+}
+
+/// replaces `require` with a loader of archive modules, each module runs once per lua state
+pub fn lua_require(lua:&Lua, modules:&ScriptModules) -> anyhow::Result<()> {
+  if modules.is_empty() { return Ok(()); }
+  let modules = modules.clone();
+  lua.set_named_registry_value("hraw_loaded", lua.create_table()?)?;
+  let require = lua.create_function(move |lua, name:String| {
+    let loaded = lua.named_registry_value::<LuaTable>("hraw_loaded")?;
+    if let Some(value) = loaded.get::<_, Option<LuaValue>>(name.as_str())? {
+      return Ok(value);
+    }
+    let code = modules.get(&name).ok_or_else(|| LuaError::runtime(format!("module '{name}' not found in archive")))?;
+    let value = match lua.load(code).set_name(name.as_str()).eval::<LuaValue>()? {
+      LuaNil => LuaValue::Boolean(true),
+      value => value
+    };
+    loaded.set(name, value.clone())?;
+    Ok(value)
+  })?;
+  lua.globals().raw_set("require", require)?;
+  Ok(())
+}
+
+pub fn lua_call(code:&str) {
+  use mlua::prelude::*;
+  let lua = Lua::new();
+  let func: mlua::Function = lua.load(code).eval().unwrap();
+  println!("{:?}", func.call::<_, ()>(()).unwrap());
+}
+
+/// interrupts counted against `ScriptPolicy::instruction_limit`, reset per row range
+struct LuaInterrupts(std::cell::Cell<u64>);
+
+/// restarts the instruction count of a reused lua state
+fn reset_interrupts(lua:&Lua) {
+  if let Some(n) = lua.app_data_ref::<LuaInterrupts>() { n.0.set(0); }
+}
+
+const LUA_UNSAFE_GLOBALS : [&str; 7] = ["require", "loadstring", "getfenv", "setfenv", "collectgarbage", "gcinfo", "newproxy"];
+
+impl ScriptPolicy {
+
+  pub fn lua(&self) -> anyhow::Result<Lua> {
+    let lua = match self.sandbox {
+      true => {
+        let lua = Lua::new_with(LuaStdLib::BUFFER | LuaStdLib::BIT | LuaStdLib::MATH | LuaStdLib::STRING, LuaOptions::new())?;
+        LUA_UNSAFE_GLOBALS.iter().try_for_each(|n| lua.globals().raw_set(*n, LuaNil))?;
+        lua.sandbox(true)?;
+        lua
+      },
+      false => Lua::new()
+    };
+    if let Some(limit) = self.memory_limit {
+      lua.set_memory_limit(limit)?;
+    }
+    if self.instruction_limit.is_some() || self.time_limit.is_some() {
+      let (instruction_limit, time_limit) = (self.instruction_limit, self.time_limit);
+      let start = std::time::Instant::now();
+      lua.set_app_data(LuaInterrupts(std::cell::Cell::new(0)));
+      lua.set_interrupt(move |lua| {
+        let count = lua.app_data_ref::<LuaInterrupts>().map_or(0, |n| { n.0.set(n.0.get() + 1); n.0.get() });
+        if instruction_limit.is_some_and(|n| count > n) {
+          return Err(LuaError::runtime("instruction limit exceeded"));
+        }
+        if time_limit.is_some_and(|n| start.elapsed() > n) {
+          return Err(LuaError::runtime("time limit exceeded"));
+        }
+        Ok(mlua::VmState::Continue)
+      });
+    }
+    Ok(lua)
+  }
+
+}
+
+/*** check ***/
+
+/// `[string "decoder"]:12: message` -> 12
+/// line of the innermost `decoder` chunk frame, errors raised from rust only have it in the traceback
+fn lua_fault(e:LuaError) -> ScriptFault {
+  let message = e.to_string();
+  let at = message.find("\"decoder\"]:").map(|i| i + 11).or(message.find("]:").map(|i| i + 2));
+  let line = at.and_then(|i| message[i..].split(':').next()?.parse().ok());
+  (line, message)
+}
+
+/// dry run of `indices`, per pixel `src` raises on reads past the end instead of returning nil
+pub fn lua_check(code:&str, modules:&ScriptModules, src:&[u8], header:&serde_json::Value, policy:&ScriptPolicy, indices:&[usize]) -> Result<Vec<CheckResult>, ScriptFault> {
+  let (width, _) = script_size(header).map_err(|e| (None, e.to_string()))?;
+  let lua = policy.lua().map_err(|e| (None, e.to_string()))?;
+  lua_require(&lua, modules).map_err(|e| (None, e.to_string()))?;
+  let globals = lua.globals();
+  let setup = || -> LuaResult<LuaValue> {
+    globals.set("header", lua_readonly(lua.to_value(&script_header(header))?)?)?;
+    lua.load(code).set_name("decoder").eval::<LuaValue>()
+  };
+  let number = |value:LuaValue| match value {
+    LuaValue::Integer(n) => check_number(n as f64),
+    LuaValue::Number(n) => check_number(n),
+    value => Err((None, format!("number expected, got {}", value.type_name())))
+  };
+
+  if let LuaValue::Function(func) = setup().map_err(lua_fault)? {
+    let len = src.len();
+    let setup = || -> LuaResult<()> {
+      let table = lua.create_sequence_from(src.iter().copied())?;
+      let meta = lua.create_table()?;
+      meta.set("__index", lua.create_function(move |_, (_, key):(LuaValue, LuaValue)| -> LuaResult<()> {
+        Err(LuaError::runtime(format!("src[{}] out of range, #src = {len}", key.to_string()?)))
+      })?)?;
+      table.set_metatable(Some(meta));
+      globals.set("src", table)
+    };
+    setup().map_err(lua_fault)?;
+    return Ok(indices.iter().map(|&i| {
+      globals.raw_set("x", i % width).map_err(lua_fault)?;
+      globals.raw_set("y", i / width).map_err(lua_fault)?;
+      number(func.call::<_, LuaValue>(i).map_err(lua_fault)?)
+    }).collect());
+  }
+
+  globals.set("src", lua.create_buffer(src).map_err(lua_fault)?).map_err(lua_fault)?;
+  let (func, is_row) = match (globals.get::<_, LuaFunction>("decode_row"), globals.get::<_, LuaFunction>("decode_block")) {
+    (Ok(func), _) => (func, true),
+    (_, Ok(func)) => (func, false),
+    _ => return Err((None, "entry point not found : function(index), decode_row or decode_block".to_string()))
+  };
+  Ok(indices.iter().map(|&i| {
+    let (out, at) = match is_row {
+      true => (lua.create_buffer(vec![0u8; width * 8]).map_err(lua_fault)?, i % width),
+      false => (lua.create_buffer([0u8; 8]).map_err(lua_fault)?, 0)
+    };
+    match is_row {
+      true => func.call::<_, ()>((i / width, out.clone())),
+      false => func.call::<_, ()>((i, 1, out.clone()))
+    }.map_err(lua_fault)?;
+    let buf = bstr::BString::from_lua(LuaValue::UserData(out), &lua).map_err(lua_fault)?;
+    check_number(f64::from_ne_bytes(buf[at * 8..at * 8 + 8].try_into().unwrap()))
+  }).collect())
+}
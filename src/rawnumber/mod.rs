@@ -1,10 +1,32 @@
 mod clamp;
 pub mod scripting;
+pub mod layout;
+pub mod postprocess;
+#[cfg(feature = "lua")]
+pub mod lua;
+#[cfg(feature = "py")]
+pub mod python;
+#[cfg(feature = "rhai")]
+pub mod rhai;
+#[cfg(feature = "wasm")]
+pub mod wasm;
 pub use scripting::*;
+pub use layout::*;
+pub use postprocess::*;
+#[cfg(feature = "lua")]
+pub use lua::*;
+#[cfg(feature = "py")]
+pub use python::*;
+#[cfg(feature = "rhai")]
+pub use self::rhai::*;
+#[cfg(feature = "wasm")]
+pub use wasm::*;
 pub use clamp::*;
 
+use paste::paste;
+
 #[allow(non_camel_case_types)]
-#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq)]
 pub enum BitField {
   le_u8, be_u8, le_i8, be_i8,
   le_u16, be_u16, le_i16, be_i16,
@@ -14,18 +36,56 @@ pub enum BitField {
   unknown
 }
 
-pub trait RawNumber { }
-macro_rules! impl_rawnum_strcut { ($($t:ident)*) => {
+impl BitField {
+  /// bytes per pixel, `None` for `unknown`
+  pub fn size(&self) -> Option<usize> {
+    use BitField::*;
+    match self {
+      le_u8 | be_u8 | le_i8 | be_i8 => Some(1),
+      le_u16 | be_u16 | le_i16 | be_i16 => Some(2),
+      le_u32 | be_u32 | le_i32 | be_i32 | le_f32 | be_f32 => Some(4),
+      le_u64 | be_u64 | le_i64 | be_i64 | le_f64 | be_f64 => Some(8),
+      unknown => None
+    }
+  }
+}
+
+pub trait RawNumber {
+  type Item: Copy;
+  const SIZE: usize;
+  const BITFIELD: BitField;
+  /// byte order matches the target, so the bytes can be viewed as `Item` in place
+  const NATIVE: bool;
+  fn from_bytes(buf: &[u8]) -> Self::Item;
+  fn to_bytes(value: Self::Item, buf: &mut [u8]);
+}
+const PROBE: [u8; 8] = [1, 0, 0, 0, 0, 0, 0, 0];
+macro_rules! impl_rawnum_strcut { ($(($t:ident,$u:ty))*) => { paste! {
   $(
     #[allow(non_camel_case_types)]
-    pub struct $t{ }
-    impl RawNumber for $t{}
+    pub struct [<$t _ $u>]{ }
+    impl RawNumber for [<$t _ $u>] {
+      type Item = $u;
+      const SIZE: usize = std::mem::size_of::<$u>();
+      const BITFIELD: BitField = BitField::[<$t _ $u>];
+      const NATIVE: bool = Self::SIZE == 1 || u64::[<from_ $t _bytes>](PROBE) == u64::from_ne_bytes(PROBE);
+      #[inline(always)]
+      fn from_bytes(buf: &[u8]) -> $u {
+        let mut dst = [0u8; std::mem::size_of::<$u>()];
+        dst.copy_from_slice(&buf[0..Self::SIZE]);
+        $u::[<from_ $t _bytes>](dst)
+      }
+      #[inline(always)]
+      fn to_bytes(value: $u, buf: &mut [u8]) {
+        buf[0..Self::SIZE].copy_from_slice(&value.[<to_ $t _bytes>]());
+      }
+    }
   )*
-}}
+}}}
 impl_rawnum_strcut!{
-  le_u8 be_u8 le_i8 be_i8
-  le_u16 be_u16 le_i16 be_i16
-  le_u32 be_u32 le_i32 be_i32
-  le_u64 be_u64 le_i64 be_i64
-  le_f32 be_f32 le_f64 be_f64
+  (le,u8) (be,u8) (le,i8) (be,i8)
+  (le,u16) (be,u16) (le,i16) (be,i16)
+  (le,u32) (be,u32) (le,i32) (be,i32)
+  (le,u64) (be,u64) (le,i64) (be,i64)
+  (le,f32) (be,f32) (le,f64) (be,f64)
 }
@@ -0,0 +1,163 @@
+#[allow(unused_imports)]
+use crate::rawnumber::*;
+use crate::decoder::{DecoderSource, resolve_source};
+use crate::{Hraw, HrawHeader, HrawPathOrIndex};
+use anyhow::Context;
+use rayon::prelude::*;
+
+/*
+  per frame transforms applied after decoding
+    postprocess :
+      lang : ops                   # lua / py (script keys of `decoder`) or ops
+      inputs : { dark : dark.raw } # other data entries, decoded the same way as the frame
+      ops :                        # lang: ops, applied in order
+        - { sub : dark }
+        - { mul : 1.5 }
+        - { clamp : [0, 4095] }
+  scripts
+    lua    : function(index, value, inputs)  -> number, `inputs.dark` is the value of the same pixel
+    python : def function(index, value, inputs), or vectorized `postprocess(frame, inputs, header)` with numpy
+*/
+
+/// number or name of an input
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum PostOperand {
+  Value(f64),
+  Input(String),
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+#[serde(rename_all = "lowercase")]
+pub enum PostOp {
+  Add(PostOperand),
+  Sub(PostOperand),
+  Mul(PostOperand),
+  Div(PostOperand),
+  /// [min, max]
+  Clamp([f64; 2]),
+  /// the value rounded and clamped to the table is the index
+  Lut(Vec<f64>),
+}
+
+/// input name -> decoded pixels
+pub type PostInputs = std::collections::BTreeMap<String, Vec<f64>>;
+
+#[derive(Debug, Clone)]
+pub struct PostprocessSource {
+  /// `lang` and the script, empty with `lang: ops`
+  pub script: DecoderSource,
+  pub ops: Vec<PostOp>,
+  /// input name -> data entry
+  pub inputs: std::collections::BTreeMap<String, String>,
+}
+
+/// `dst` is converted to f64, transformed and clamped back
+#[allow(unused_variables)]
+pub fn postprocess_call<T>(source:&PostprocessSource, dst:&mut [T], inputs:&PostInputs, header:&serde_json::Value, policy:&ScriptPolicy) -> anyhow::Result<()>
+where T : Copy + Into<f64> + ClampFrom<f64> + Send + Sync {
+  let (width, height) = script_size(header)?;
+  let total = width * height;
+  anyhow::ensure!(dst.len() >= total, "postprocess : {} pixels, {width}x{height} expected", dst.len());
+  for (name, input) in inputs.iter() {
+    anyhow::ensure!(input.len() >= total, "postprocess : input {name} has {} pixels, {width}x{height} expected", input.len());
+  }
+  let mut values = dst[0..total].par_iter().map(|n| (*n).into()).collect::<Vec<f64>>();
+  match source.script.lang.as_str() {
+    "ops" => ops_call(&source.ops, &mut values, inputs)?,
+    #[cfg(feature = "lua")]
+    "lua" => lua_postprocess(&source.script.code, &source.script.modules, &mut values, inputs, header, policy)?,
+    #[cfg(feature = "py")]
+    "py" => py_postprocess(&source.script.code, &source.script.modules, &mut values, inputs, header, policy)?,
+    lang => anyhow::bail!("postprocess : lang {lang} is not supported, check the cargo features")
+  }
+  dst[0..total].par_iter_mut().zip(values.par_iter()).for_each(|(d, n)| *d = T::clamp_from(*n));
+  Ok(())
+}
+
+/// `clamp` bounds are finite and ordered
+pub fn check_ops(ops:&[PostOp]) -> anyhow::Result<()> {
+  for op in ops.iter() {
+    if let PostOp::Clamp([min, max]) = op {
+      anyhow::ensure!(min.is_finite() && max.is_finite() && min <= max, "postprocess : clamp [{min}, {max}] is not a finite [min, max]");
+    }
+  }
+  Ok(())
+}
+
+/// declarative `ops`, each op runs over the whole frame
+pub fn ops_call(ops:&[PostOp], values:&mut [f64], inputs:&PostInputs) -> anyhow::Result<()> {
+  check_ops(ops)?;
+  let apply = |values:&mut [f64], n:&PostOperand, f:fn(f64, f64) -> f64| -> anyhow::Result<()> {
+    match n {
+      PostOperand::Value(v) => values.par_iter_mut().for_each(|d| *d = f(*d, *v)),
+      PostOperand::Input(name) => {
+        let src = inputs.get(name).ok_or_else(|| anyhow::anyhow!("postprocess : input {name} not found"))?;
+        values.par_iter_mut().zip(src.par_iter()).for_each(|(d, v)| *d = f(*d, *v));
+      },
+    }
+    Ok(())
+  };
+  for op in ops.iter() {
+    match op {
+      PostOp::Add(n) => apply(values, n, |a, b| a + b)?,
+      PostOp::Sub(n) => apply(values, n, |a, b| a - b)?,
+      PostOp::Mul(n) => apply(values, n, |a, b| a * b)?,
+      PostOp::Div(n) => apply(values, n, |a, b| a / b)?,
+      PostOp::Clamp([min, max]) => values.par_iter_mut().for_each(|d| *d = d.clamp(*min, *max)),
+      PostOp::Lut(table) => {
+        anyhow::ensure!(!table.is_empty(), "postprocess : empty lut");
+        let last = (table.len() - 1) as f64;
+        values.par_iter_mut().for_each(|d| *d = table[d.round().clamp(0.0, last) as usize]);
+      },
+    }
+  }
+  Ok(())
+}
+
+impl Hraw {
+
+  /// `postprocess` of the header, `None` when not given
+  pub fn postprocess_source(&mut self) -> anyhow::Result<Option<PostprocessSource>> {
+    let header = self.header();
+    self.postprocess_source_of(&header)
+  }
+
+  /// `postprocess_source` of an already read `header`.
+  /// `ops` without `lang`, `code` and `file` is `lang: ops`, `ops` with another lang is an error
+  pub(crate) fn postprocess_source_of(&mut self, header:&serde_json::Value) -> anyhow::Result<Option<PostprocessSource>> {
+    let Some(mut post) = header.to_struct().postprocess else { return Ok(None) };
+    if header["postprocess"].get("lang").is_none() && !post.ops.is_empty() && post.script.code.is_empty() && post.script.file.is_none() {
+      post.script.lang = "ops".to_string();
+    }
+    anyhow::ensure!(post.ops.is_empty() || post.script.lang == "ops", "postprocess : ops require lang : ops, got lang : {}", post.script.lang);
+    check_ops(&post.ops)?;
+    let script = match post.script.lang.as_str() {
+      "ops" => DecoderSource { lang: post.script.lang, code: String::new(), modules: ScriptModules::new(), binary: Vec::new(), layout: None },
+      _ => {
+        let names = self.zip.file_names().map(|n| n.to_string()).collect::<Vec<_>>();
+        resolve_source("postprocess", post.script, &names, |n| self.read_bytes(n))?
+      }
+    };
+    Ok(Some(PostprocessSource { script, ops: post.ops, inputs: post.inputs }))
+  }
+
+  /// each input entry decoded as the frames are (bitfield or decoder), without postprocess
+  pub fn postprocess_inputs(&mut self, source:&PostprocessSource, policy:&ScriptPolicy) -> anyhow::Result<PostInputs> {
+    let header = self.header();
+    self.postprocess_inputs_of(source, &header, policy)
+  }
+
+  /// `postprocess_inputs` of an already read `header`
+  pub(crate) fn postprocess_inputs_of(&mut self, source:&PostprocessSource, header:&serde_json::Value, policy:&ScriptPolicy) -> anyhow::Result<PostInputs> {
+    use crate::buffer::FromHraw;
+    let total = header.to_struct().total;
+    source.inputs.iter().map(|(name, entry)| {
+      self.contain_poi(entry.as_str()).with_context(|| format!("postprocess : input {name} ({entry}) not found"))?;
+      let mut dst = vec![0f64; total];
+      dst.as_mut_slice().from_raw_with(self, header, entry.as_str(), policy)?;
+      Ok((name.clone(), dst))
+    }).collect()
+  }
+
+}
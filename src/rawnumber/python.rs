@@ -0,0 +1,235 @@
+use pyo3::prelude::*;
+use crate::rawnumber::{ClampFrom, ScriptModules, ScriptPolicy, ScriptFault, CheckResult, PostInputs, check_number, script_header, script_size};
+
+/// per pixel `function(index)` or vectorized `decode(src, header)`
+pub fn py_script_call<T>(code:&str, modules:&ScriptModules, src:&[u8], dst:&mut [T], header:&serde_json::Value, policy:&ScriptPolicy) -> anyhow::Result<()>
+where T : ClampFrom<f64> + for<'py> FromPyObject<'py> {
+  anyhow::ensure!(policy.allow_python, "python decoder is not allowed by the script policy");
+  let (width, height) = script_size(header)?;
+  Python::with_gil(|py| {
+    let finder = py_modules(py, modules)?;
+    let result = (|| {
+      let module = PyModule::from_code_bound(py, code, "", "",)?;
+      if module.hasattr("decode")? {
+        let decoded = py_decode(py, &module.getattr("decode")?, src.to_vec(), py_readonly(py, &script_header(header))?, width, height)?;
+        decoded.iter().zip(dst.iter_mut()).for_each(|(n, dst)| *dst = T::clamp_from(*n));
+        return Ok(());
+      }
+      module.add("src", src)?;
+      module.add("header", py_readonly(py, &script_header(header))?)?;
+      let func = module.getattr("function")?;
+      (0..width*height).try_for_each(|i|{ 
+        module.setattr("x", i % width)?;
+        module.setattr("y", i / width)?;
+        dst[i] = func.call1((i,)).and_then(|n| n.extract::<T>())
+          .map_err(|e| pyo3::exceptions::PyRuntimeError::new_err(format!("pixel {i} : {e}")))?;
+        Ok::<_, PyErr>(())
+      })
+    })();
+    if let Some(finder) = finder { finder.call_method0("remove")?; }
+    Ok(result?)
+  })
+}
+
+/// per pixel `function(index, value)` returning `bytes`
+pub fn py_script_encode<T>(code:&str, modules:&ScriptModules, src:&[T], header:&serde_json::Value, policy:&ScriptPolicy) -> anyhow::Result<Vec<u8>>
+where T : Copy + IntoPy<PyObject> {
+  anyhow::ensure!(policy.allow_python, "python encoder is not allowed by the script policy");
+  let (width, height) = script_size(header)?;
+  anyhow::ensure!(src.len() >= width * height, "encoder : {} pixels, {width}x{height} expected", src.len());
+  Python::with_gil(|py| {
+    let finder = py_modules(py, modules)?;
+    let dst = (|| {
+      let module = PyModule::from_code_bound(py, code, "", "",)?;
+      module.add("header", py_readonly(py, &script_header(header))?)?;
+      let func = module.getattr("function")?;
+      let mut dst = Vec::new();
+      for (i, value) in src[0..width*height].iter().enumerate() {
+        module.setattr("x", i % width)?;
+        module.setattr("y", i / width)?;
+        dst.extend_from_slice(func.call1((i, *value))?.downcast::<pyo3::types::PyBytes>()?.as_bytes());
+      }
+      Ok::<_, PyErr>(dst)
+    })();
+    if let Some(finder) = finder { finder.call_method0("remove")?; }
+    Ok(dst?)
+  })
+}
+
+/// per pixel `function(index, value, inputs)` or vectorized `postprocess(frame, inputs, header)` of a `postprocess` section
+pub fn py_postprocess(code:&str, modules:&ScriptModules, values:&mut [f64], inputs:&PostInputs, header:&serde_json::Value, policy:&ScriptPolicy) -> anyhow::Result<()> {
+  anyhow::ensure!(policy.allow_python, "python postprocess is not allowed by the script policy");
+  let (width, height) = script_size(header)?;
+  Python::with_gil(|py| {
+    let finder = py_modules(py, modules)?;
+    let result = (|| {
+      let module = PyModule::from_code_bound(py, code, "", "",)?;
+      let readonly = py_readonly(py, &script_header(header))?;
+      if module.hasattr("postprocess")? {
+        let out = py_postprocess_array(py, &module.getattr("postprocess")?, values, inputs, readonly, width, height)?;
+        values[0..width*height].copy_from_slice(&out);
+        return Ok(());
+      }
+      module.add("header", readonly)?;
+      let func = module.getattr("function")?;
+      let dict = pyo3::types::PyDict::new_bound(py);
+      (0..width*height).try_for_each(|i| {
+        module.setattr("x", i % width)?;
+        module.setattr("y", i / width)?;
+        inputs.iter().try_for_each(|(name, input)| dict.set_item(name, input[i]))?;
+        values[i] = func.call1((i, values[i], &dict)).and_then(|n| n.extract::<f64>())
+          .map_err(|e| pyo3::exceptions::PyRuntimeError::new_err(format!("pixel {i} : {e}")))?;
+        Ok::<_, PyErr>(())
+      })
+    })();
+    if let Some(finder) = finder { finder.call_method0("remove")?; }
+    Ok(result?)
+  })
+}
+
+/// `frame` and each input are float64 ndarrays of shape (height, width), copies of the decoded pixels
+fn py_postprocess_array(py:Python<'_>, func:&Bound<'_, PyAny>, values:&[f64], inputs:&PostInputs, header:Bound<'_, PyAny>, width:usize, height:usize) -> PyResult<Vec<f64>> {
+  use numpy::{PyArrayMethods, PyUntypedArrayMethods};
+  let np = py.import_bound("numpy")?;
+  let array = |src:&[f64]| numpy::PyArray1::from_slice_bound(py, &src[0..width*height]).reshape([height, width]);
+  let dict = pyo3::types::PyDict::new_bound(py);
+  inputs.iter().try_for_each(|(name, input)| dict.set_item(name, array(input)?))?;
+  let out = func.call1((array(values)?, dict, header))?;
+  let out = np.call_method1("ascontiguousarray", (out, "float64"))?;
+  let out = out.downcast::<numpy::PyArray2<f64>>()?.readonly();
+  if out.shape() != [height, width] {
+    return Err(pyo3::exceptions::PyValueError::new_err(format!("postprocess returned shape {:?}, ({height}, {width}) expected", out.shape())));
+  }
+  Ok(out.as_slice()?.to_vec())
+}
+
+pub fn py_call(code:&str) {
+  Python::with_gil(|py| {
+    let func = PyModule::from_code_bound(py,code,"", "",)
+      .unwrap()
+      .getattr("function").unwrap();
+    println!("{:?}", func.call0().unwrap());
+  });
+}
+
+const PY_FINDER : &str = r#"
+import sys, importlib.abc, importlib.util
+
+class ArchiveFinder(importlib.abc.MetaPathFinder, importlib.abc.Loader):
+  def __init__(self, modules):
+    self.modules = modules
+  def find_spec(self, name, path, target=None):
+    if name in self.modules:
+      return importlib.util.spec_from_loader(name, self)
+  def create_module(self, spec):
+    return None
+  def exec_module(self, module):
+    exec(compile(self.modules[module.__name__], module.__name__, 'exec'), module.__dict__)
+  def remove(self):
+    sys.meta_path.remove(self)
+    for name in self.modules:
+      sys.modules.pop(name, None)
+"#;
+
+/// installs an importer of archive modules (`lib/bits.py` is `import lib.bits`), call `remove()` afterwards.
+/// the interpreter is shared, so modules are dropped from `sys.modules` again
+fn py_modules<'py>(py:Python<'py>, modules:&ScriptModules) -> PyResult<Option<Bound<'py, PyAny>>> {
+  if modules.is_empty() { return Ok(None); }
+  let modules = modules.iter().map(|(k, v)| (k.replace('/', "."), v.as_str())).collect::<std::collections::HashMap<_, _>>();
+  let finder = PyModule::from_code_bound(py, PY_FINDER, "", "")?.getattr("ArchiveFinder")?.call1((modules,))?;
+  py.import_bound("sys")?.getattr("meta_path")?.call_method1("insert", (0, &finder))?;
+  Ok(Some(finder))
+}
+
+/// vectorized entry `decode(src, header)`.
+/// `src` is moved into the uint8 ndarray (`from_vec`, owned by numpy, not a view), the script may keep it.
+/// callers lending `&[u8]` pass a single copy of the raw bytes.
+/// the result is any array-like of shape (height, width)
+fn py_decode(py:Python<'_>, func:&Bound<'_, PyAny>, src:Vec<u8>, header:Bound<'_, PyAny>, width:usize, height:usize) -> PyResult<Vec<f64>> {
+  use numpy::{PyArrayMethods, PyUntypedArrayMethods};
+  let np = py.import_bound("numpy")?;
+  let out = func.call1((numpy::PyArray1::from_vec_bound(py, src), header))?;
+  let out = np.call_method1("ascontiguousarray", (out, "float64"))?;
+  let out = out.downcast::<numpy::PyArray2<f64>>()?.readonly();
+  if out.shape() != [height, width] {
+    return Err(pyo3::exceptions::PyValueError::new_err(format!("decode returned shape {:?}, ({height}, {width}) expected", out.shape())));
+  }
+  Ok(out.as_slice()?.to_vec())
+}
+
+/// json -> `types.MappingProxyType` recursively, arrays become tuples
+fn py_readonly<'py>(py:Python<'py>, value:&serde_json::Value) -> PyResult<Bound<'py, PyAny>> {
+  use pyo3::types::{PyDict, PyTuple};
+  match value {
+    serde_json::Value::Object(map) => {
+      let dict = PyDict::new_bound(py);
+      map.iter().try_for_each(|(k, v)| dict.set_item(k, py_readonly(py, v)?))?;
+      py.import_bound("types")?.call_method1("MappingProxyType", (dict,))
+    },
+    serde_json::Value::Array(list) => {
+      let items = list.iter().map(|n| py_readonly(py, n)).collect::<PyResult<Vec<_>>>()?;
+      Ok(PyTuple::new_bound(py, items).into_any())
+    },
+    value => py.import_bound("json")?.call_method1("loads", (value.to_string(),))
+  }
+}
+
+/*** check ***/
+
+const PY_CHECKED_SRC : &str = r#"
+class CheckedSrc(bytes):
+  def __getitem__(self, key):
+    stop = key.stop if isinstance(key, slice) else key + 1
+    if stop is not None and stop > len(self):
+      raise IndexError(f'src[{key}] out of range, len(src) = {len(self)}')
+    return bytes.__getitem__(self, key)
+"#;
+
+/// line of the innermost frame in `decoder.py`, or of a syntax error
+fn py_fault(py:Python<'_>, e:PyErr) -> ScriptFault {
+  let mut line = e.value_bound(py).getattr("lineno").ok().and_then(|n| n.extract::<usize>().ok());
+  let mut tb = e.traceback_bound(py).map(|n| n.into_any());
+  while let Some(frame) = tb.filter(|n| !n.is_none()) {
+    let file = frame.getattr("tb_frame").and_then(|n| n.getattr("f_code")).and_then(|n| n.getattr("co_filename"));
+    if file.is_ok_and(|n| n.to_string() == "decoder.py") {
+      line = frame.getattr("tb_lineno").ok().and_then(|n| n.extract().ok());
+    }
+    tb = frame.getattr("tb_next").ok();
+  }
+  (line, e.to_string())
+}
+
+/// dry run of `indices`, `src[i]` and `src[a:b]` past the end raise IndexError instead of truncating
+pub fn py_check(code:&str, modules:&ScriptModules, src:&[u8], header:&serde_json::Value, policy:&ScriptPolicy, indices:&[usize]) -> Result<Vec<CheckResult>, ScriptFault> {
+  if !policy.allow_python { return Err((None, "python decoder is not allowed by the script policy".to_string())); }
+  let (width, height) = script_size(header).map_err(|e| (None, e.to_string()))?;
+  Python::with_gil(|py| {
+    let finder = py_modules(py, modules).map_err(|e| py_fault(py, e))?;
+    let result = (|| {
+      let module = PyModule::from_code_bound(py, code, "decoder.py", "decoder").map_err(|e| py_fault(py, e))?;
+      let readonly = py_readonly(py, &script_header(header)).map_err(|e| py_fault(py, e))?;
+      if module.hasattr("decode").unwrap_or_default() {
+        let decode = module.getattr("decode").map_err(|e| py_fault(py, e))?;
+        let decoded = py_decode(py, &decode, src.to_vec(), readonly, width, height).map_err(|e| py_fault(py, e))?;
+        return Ok(indices.iter().map(|&i| check_number(decoded[i])).collect());
+      }
+      let setup = || -> PyResult<Bound<'_, PyAny>> {
+        let checked = PyModule::from_code_bound(py, PY_CHECKED_SRC, "", "")?.getattr("CheckedSrc")?;
+        module.add("src", checked.call1((pyo3::types::PyBytes::new_bound(py, src),))?)?;
+        module.add("header", readonly)?;
+        module.getattr("function")
+      };
+      let func = setup().map_err(|e| py_fault(py, e))?;
+      Ok(indices.iter().map(|&i| {
+        let value = (|| {
+          module.setattr("x", i % width)?;
+          module.setattr("y", i / width)?;
+          func.call1((i,))?.extract::<f64>()
+        })();
+        check_number(value.map_err(|e| py_fault(py, e))?)
+      }).collect())
+    })();
+    if let Some(finder) = finder { finder.call_method0("remove").map_err(|e| py_fault(py, e))?; }
+    result
+  })
+}
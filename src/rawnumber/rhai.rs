@@ -0,0 +1,198 @@
+#![allow(deprecated)] // Engine::on_var is marked volatile
+use rhai::*;
+use crate::rawnumber::{ClampFrom, ScriptModules, ScriptPolicy, ScriptFault, CheckResult, check_number, script_header, script_size};
+use std::{cell::Cell, rc::Rc};
+
+/*
+  rhai (pure rust) entry points
+    fn decode(index) : called per pixel, returns a number
+    fn decode_row(y) : called per row, returns an array of `width` numbers
+    fn encode(index, value) : encoder, called per pixel, returns a blob
+  `src` is a blob of the raw bytes (offset included), read with builtins such as `parse_le_int(src, start, len)`
+  `header` (read-only), `x` and `y` of the current pixel are visible inside functions.
+  archive modules are `import "bits" as bits;`, resolved once at compile time.
+  top level statements run once per engine, entry points are then called without them
+  `ScriptPolicy::memory_limit` bounds strings (bytes), arrays, blobs and maps (items)
+*/
+
+/// archive modules only, the default resolver would read files
+struct ArchiveResolver(ScriptModules);
+
+impl ModuleResolver for ArchiveResolver {
+  fn resolve(&self, engine:&Engine, _source:Option<&str>, path:&str, pos:Position) -> Result<Shared<Module>, Box<EvalAltResult>> {
+    let code = self.0.get(path).ok_or_else(|| Box::new(EvalAltResult::ErrorModuleNotFound(path.into(), pos)))?;
+    let ast = engine.compile(code)?;
+    Ok(Module::eval_ast_as_new(Scope::new(), &ast, engine)?.into())
+  }
+}
+
+/// current (x, y), shared with the `on_var` callback
+type RhaiXY = Rc<Cell<(INT, INT)>>;
+
+fn rhai_err(e:impl std::fmt::Display) -> anyhow::Error { anyhow::anyhow!("rhai : {e}") }
+
+/// engine with `src`, `header`, `x` and `y`
+fn rhai_engine(src:&[u8], modules:&ScriptModules, header:&serde_json::Value, policy:&ScriptPolicy) -> anyhow::Result<(Engine, RhaiXY)> {
+  let mut engine = Engine::new();
+  engine.set_module_resolver(ArchiveResolver(modules.clone()));
+  if let Some(n) = policy.instruction_limit {
+    engine.set_max_operations(n);
+  }
+  if let Some(n) = policy.memory_limit {
+    engine.set_max_string_size(n).set_max_array_size(n).set_max_map_size(n);
+  }
+  if let Some(limit) = policy.time_limit {
+    let start = std::time::Instant::now();
+    engine.on_progress(move |_| (start.elapsed() > limit).then(|| Dynamic::from("time limit exceeded")));
+  }
+
+  let src = Dynamic::from_blob(src.to_vec()).into_shared();
+  let header = serde::to_dynamic(script_header(header)).map_err(rhai_err)?.into_read_only().into_shared();
+  let xy = Rc::new(Cell::new((0, 0)));
+  let current = xy.clone();
+  engine.on_var(move |name, _, context| Ok(match name {
+    _ if context.scope().contains(name) => None, // localが優先
+    "src" => Some(src.clone()),
+    "header" => Some(header.clone()),
+    "x" => Some(Dynamic::from_int(current.get().0)),
+    "y" => Some(Dynamic::from_int(current.get().1)),
+    _ => None
+  }));
+  Ok((engine, xy))
+}
+
+/// runs the top level statements once, the functions go to a global module of `engine`
+fn rhai_compile(engine:&mut Engine, code:&str) -> Result<AST, Box<EvalAltResult>> {
+  let ast = engine.compile_into_self_contained(&Scope::new(), code)?;
+  let module = Module::eval_ast_as_new(Scope::new(), &ast, engine)?;
+  engine.register_global_module(module.into());
+  Ok(ast)
+}
+
+/// entry point of `rhai_compile`, top level statements are not evaluated again
+fn rhai_call<T:std::any::Any + Clone>(engine:&Engine, name:&str, args:impl FuncArgs) -> Result<T, Box<EvalAltResult>> {
+  let options = CallFnOptions::new().eval_ast(false).in_all_namespaces(true);
+  engine.call_fn_with_options(options, &mut Scope::new(), &AST::empty(), name, args)
+}
+
+fn rhai_number(value:&Dynamic) -> anyhow::Result<f64> {
+  match (value.as_int(), value.as_float()) {
+    (Ok(n), _) => Ok(n as f64),
+    (_, Ok(n)) => Ok(n),
+    _ => anyhow::bail!("rhai : number expected, got {}", value.type_name())
+  }
+}
+
+/// row aligned ranges in parallel, one engine per rayon task as `lua_par_call`
+pub fn rhai_par_call<T>(code:&str, modules:&ScriptModules, src:&[u8], dst:&mut [T], header:&serde_json::Value, policy:&ScriptPolicy) -> anyhow::Result<()>
+where T : ClampFrom<f64> + Send {
+  use rayon::prelude::*;
+  let (width, height) = script_size(header)?;
+  if width * height == 0 {
+    return Ok(());
+  }
+  let rows = height.div_ceil(rayon::current_num_threads()).max(1);
+  dst[0..width*height].par_chunks_mut(rows * width).enumerate().try_for_each(|(n, dst)| {
+    let start = n * rows * width;
+    let (mut engine, xy) = rhai_engine(src, modules, header, policy)?;
+    let ast = rhai_compile(&mut engine, code).map_err(rhai_err)?;
+
+    if ast.iter_functions().any(|f| f.name == "decode_row") {
+      for (row, dst) in dst.chunks_mut(width).enumerate() {
+        let values = rhai_call::<Array>(&engine, "decode_row", ((start / width + row) as INT,)).map_err(rhai_err)?;
+        anyhow::ensure!(values.len() == width, "rhai : decode_row returned {} values, {width} expected", values.len());
+        values.iter().zip(dst.iter_mut()).try_for_each(|(v, d)| { *d = T::clamp_from(rhai_number(v)?); anyhow::Ok(()) })?;
+      }
+      return Ok(());
+    }
+    anyhow::ensure!(ast.iter_functions().any(|f| f.name == "decode"), "entry point not found : decode(index) or decode_row(y)");
+    dst.iter_mut().enumerate().try_for_each(|(i, d)| {
+      xy.set((((start + i) % width) as INT, ((start + i) / width) as INT));
+      let value = rhai_call::<Dynamic>(&engine, "decode", ((start + i) as INT,)).map_err(rhai_err)?;
+      *d = T::clamp_from(rhai_number(&value)?);
+      Ok(())
+    })
+  })
+}
+
+/// per pixel `encode(index, value)`, blobs are concatenated in order
+pub fn rhai_encode<T>(code:&str, modules:&ScriptModules, src:&[T], header:&serde_json::Value, policy:&ScriptPolicy) -> anyhow::Result<Vec<u8>>
+where T : Copy + Into<f64> {
+  let (width, height) = script_size(header)?;
+  anyhow::ensure!(src.len() >= width * height, "encoder : {} pixels, {width}x{height} expected", src.len());
+  let (mut engine, xy) = rhai_engine(&[], modules, header, policy)?;
+  rhai_compile(&mut engine, code).map_err(rhai_err)?;
+  let mut dst = Vec::new();
+  for (i, value) in src[0..width*height].iter().enumerate() {
+    xy.set(((i % width) as INT, (i / width) as INT));
+    let bytes = rhai_call::<Blob>(&engine, "encode", (i as INT, Into::<f64>::into(*value) as FLOAT)).map_err(rhai_err)?;
+    dst.extend_from_slice(&bytes);
+  }
+  Ok(dst)
+}
+
+/*** check ***/
+
+fn rhai_fault(e:impl Into<Box<EvalAltResult>>) -> ScriptFault {
+  let e = e.into();
+  (e.position().line(), e.to_string())
+}
+
+/// `start..start + len` of a blob of `size` bytes (negative `start` from the end), an error past either end
+fn rhai_bounds(size:usize, start:INT, len:INT) -> Result<std::ops::Range<usize>, Box<EvalAltResult>> {
+  let begin = if start < 0 { start.saturating_add(size as INT) } else { start };
+  let end = begin.saturating_add(len.max(0));
+  if begin < 0 || end > size as INT {
+    return Err(format!("blob[{start}..{start} + {len}] out of range, {size} bytes").into());
+  }
+  Ok(begin as usize..end as usize)
+}
+
+/// blob reads of `rhai_check` raise past the end instead of truncating or returning 0
+fn rhai_checked_reads(engine:&mut Engine) {
+  fn bytes(blob:&Blob, start:INT, len:INT) -> Result<[u8; 8], Box<EvalAltResult>> {
+    let range = rhai_bounds(blob.len(), start, len)?;
+    let mut buf = [0u8; 8];
+    let n = range.len().min(8);
+    buf[..n].copy_from_slice(&blob[range][..n]);
+    Ok(buf)
+  }
+  for (name, le) in [("parse_le_int", true), ("parse_be_int", false)] {
+    let int = move |blob:&mut Blob, start:INT, len:INT| bytes(blob, start, len).map(|n| if le { INT::from_le_bytes(n) } else { INT::from_be_bytes(n) });
+    engine.register_fn(name, int);
+    engine.register_fn(name, move |blob:&mut Blob, range:std::ops::Range<INT>| int(blob, range.start, range.end - range.start));
+    engine.register_fn(name, move |blob:&mut Blob, range:std::ops::RangeInclusive<INT>| int(blob, *range.start(), *range.end() - *range.start() + 1));
+  }
+  for (name, le) in [("parse_le_float", true), ("parse_be_float", false)] {
+    let float = move |blob:&mut Blob, start:INT, len:INT| bytes(blob, start, len).map(|n| if le { FLOAT::from_le_bytes(n) } else { FLOAT::from_be_bytes(n) });
+    engine.register_fn(name, float);
+    engine.register_fn(name, move |blob:&mut Blob, range:std::ops::Range<INT>| float(blob, range.start, range.end - range.start));
+    engine.register_fn(name, move |blob:&mut Blob, range:std::ops::RangeInclusive<INT>| float(blob, *range.start(), *range.end() - *range.start() + 1));
+  }
+  engine.register_fn("get", |blob:&mut Blob, index:INT| rhai_bounds(blob.len(), index, 1).map(|n| blob[n.start] as INT));
+  engine.register_fn("extract", |blob:&mut Blob, start:INT, len:INT| rhai_bounds(blob.len(), start, len).map(|n| blob[n].to_vec()));
+}
+
+/// dry run of `indices`, `src[i]`, `get`, `extract` and `parse_*` past the end of a blob raise instead of truncating
+pub fn rhai_check(code:&str, modules:&ScriptModules, src:&[u8], header:&serde_json::Value, policy:&ScriptPolicy, indices:&[usize]) -> Result<Vec<CheckResult>, ScriptFault> {
+  let (width, _) = script_size(header).map_err(|e| (None, e.to_string()))?;
+  let (mut engine, xy) = rhai_engine(src, modules, header, policy).map_err(|e| (None, e.to_string()))?;
+  rhai_checked_reads(&mut engine);
+  let ast = rhai_compile(&mut engine, code).map_err(rhai_fault)?;
+  let number = |value:Dynamic| rhai_number(&value).map_err(|e| (None, e.to_string())).and_then(check_number);
+
+  if ast.iter_functions().any(|f| f.name == "decode_row") {
+    return Ok(indices.iter().map(|&i| {
+      let values = rhai_call::<Array>(&engine, "decode_row", ((i / width) as INT,)).map_err(rhai_fault)?;
+      let value = values.get(i % width).cloned().ok_or((None, format!("decode_row returned {} values, {width} expected", values.len())))?;
+      number(value)
+    }).collect());
+  }
+  if !ast.iter_functions().any(|f| f.name == "decode") {
+    return Err((None, "entry point not found : decode(index) or decode_row(y)".to_string()));
+  }
+  Ok(indices.iter().map(|&i| {
+    xy.set(((i % width) as INT, (i / width) as INT));
+    number(rhai_call::<Dynamic>(&engine, "decode", (i as INT,)).map_err(rhai_fault)?)
+  }).collect())
+}
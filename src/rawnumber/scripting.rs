@@ -1,54 +1,38 @@
-// use anyhow::Context as _;
-use mlua::prelude::*;
-use pyo3::prelude::*;
-// use numpy::{PyArray, PyArray2};
-
-pub trait LuaEx {
-  fn call_func<'lua, T : mlua::FromLuaMulti<'lua>>(&'lua self, src:&[u8], dst:&mut [T], width:usize, height:usize, code:&'lua str) -> anyhow::Result<()>;    
-}
-
-impl LuaEx for Lua {
-  fn call_func<'lua, T : mlua::FromLuaMulti<'lua>>(&'lua self, src:&[u8], dst:&mut [T], width:usize, height:usize, code:&'lua str) -> anyhow::Result<()> {
-    let globals = self.globals();
-    globals.set("src", src)?;
-    let func: mlua::Function = self.load(code).eval()?;
-    (0..width*height).for_each(|i|{ 
-      dst[i] = func.call::<_, T>(i).unwrap();
-    });
-    Ok(())
-  }
-}
-
-
-pub fn lua_call(code:&str) {
-  use mlua::prelude::*;
-  let lua = Lua::new();
-  let func: mlua::Function = lua.load(code).eval().unwrap();
-  println!("{:?}", func.call::<_, ()>(()).unwrap());
+#[allow(unused_imports)]
+use crate::rawnumber::*;
+use crate::decoder::DecoderSource;
+
+/// module name -> code, see `Hraw::decoder_source`
+pub type ScriptModules = std::collections::BTreeMap<String, String>;
+
+/// the archive header as seen by scripts, `stride`, `offset` and `total` are always present
+pub fn script_header(header:&serde_json::Value) -> serde_json::Value {
+  let (width, height) = script_size(header).unwrap_or_default();
+  let mut dst = header.to_owned();
+  if !dst.is_object() { dst = serde_json::json!({}); }
+  dst["stride"] = header.get("stride").cloned().unwrap_or(serde_json::json!(width));
+  dst["offset"] = header.get("offset").cloned().unwrap_or(serde_json::json!(0));
+  dst["total"] = serde_json::json!(width * height);
+  dst
 }
 
-pub fn py_call(code:&str) {
-  Python::with_gil(|py| {
-    let func = PyModule::from_code_bound(py,code,"", "",)
-      .unwrap()
-      .getattr("function").unwrap();
-    println!("{:?}", func.call0().unwrap());
-  });
+pub(crate) fn script_size(header:&serde_json::Value) -> anyhow::Result<(usize, usize)> {
+  let width = header["width"].as_u64().ok_or(anyhow::anyhow!("header : width not found"))?;
+  let height = header["height"].as_u64().ok_or(anyhow::anyhow!("header : height not found"))?;
+  Ok((width as usize, height as usize))
 }
 
-
 pub fn csx_call(code:&str) {
   let temp = tempfile::tempdir().unwrap();
-  let path = temp.path().to_string_lossy().into_owned();
-  let file_path = format!(r"{path}\temp.csx");
+  let file_path = temp.path().join("temp.csx").to_string_lossy().into_owned();
   {  
     use std::io::Write;
     let mut buf_writer = std::io::BufWriter::new(std::fs::File::create(&file_path).unwrap());
-    buf_writer.write(code.as_bytes()).unwrap();
+    buf_writer.write_all(code.as_bytes()).unwrap();
     buf_writer.flush().unwrap();
   } 
   let dst = std::process::Command::new("powershell")
-    .args(&["dotnet", "script", &file_path])
+    .args(["dotnet", "script", &file_path])
     .stdout(std::process::Stdio::inherit())
     .output().expect("failed to execute process");
 
@@ -59,70 +43,189 @@ pub fn csx_call(code:&str) {
   
 }
 
+/*** policy ***/
+
+/// restrictions for decoder scripts, archives from outside can not be trusted
+#[derive(Clone, Debug)]
+pub struct ScriptPolicy {
+  /// lua with only `buffer`, `bit32`, `math` and `string`, read-only builtins
+  pub sandbox: bool,
+  pub allow_python: bool,
+  /// interrupts per lua state, roughly one per loop iteration or function call. fuel of a wasm decoder, `wasm_default_fuel` when `None`
+  pub instruction_limit: Option<u64>,
+  /// per lua state, measured from its creation
+  pub time_limit: Option<std::time::Duration>,
+  /// bytes per lua state, linear memory of a wasm decoder
+  pub memory_limit: Option<usize>,
+}
+
+impl Default for ScriptPolicy {
+  fn default() -> Self {
+    ScriptPolicy { sandbox: true, allow_python: false, instruction_limit: None, time_limit: None, memory_limit: None }
+  }
+}
+
+impl ScriptPolicy {
+
+  /// full lua stdlib and python, no limits
+  pub fn trusted() -> Self {
+    ScriptPolicy { sandbox: false, allow_python: true, ..Default::default() }
+  }
+
+}
+
 /*** scripting***/
 
+/*
+  backends, each behind the cargo feature of the same name
+    lua  : rawnumber::lua    (mlua, luau jit)
+    py   : rawnumber::python (pyo3, needs a system python)
+    rhai : rawnumber::rhai   (pure rust)
+    wasm : rawnumber::wasm   (wasmi, decoder only)
+*/
+#[allow(clippy::wrong_self_convention)]
 pub trait HrawScripting {
-  fn from_lua_script(&mut self, code:&str, src:&[u8], width:usize, height:usize);
-  fn from_py_script(&mut self, code:&str, src:&[u8], width:usize, height:usize);
-}
-impl HrawScripting for [i32] {
-  fn from_lua_script(&mut self, code:&str, src:&[u8], width:usize, height:usize){
-    let lua = Lua::new();
-    lua.call_func(src, self, width, height, code).unwrap(); // Tをジェネリクスにするため
-    /* 
-      let globals = lua.globals();
-      globals.set("src", src).unwrap();
-      let func: mlua::Function = lua.load(code).eval().unwrap();
-      (0..width*height).for_each(|i|{ 
-        dst[i] = func.call::<_, i32>(i).unwrap();
-      });
-    */
+  #[cfg(feature = "lua")]
+  fn from_lua_script(&mut self, code:&str, src:&[u8], header:&serde_json::Value, policy:&ScriptPolicy) -> anyhow::Result<()> {
+    self.from_lua_script_with(code, &ScriptModules::new(), src, header, policy)
   }
-  fn from_py_script(&mut self, code:&str, src:&[u8], width:usize, height:usize){
-    Python::with_gil(|py| {
-      let module = PyModule::from_code_bound(py, code, "", "",).unwrap();
-      module.add("src", src).unwrap();
-      let func = module.getattr("function").unwrap();
-      (0..width*height).for_each(|i|{ 
-        self[i] = func.call1((i,)).unwrap().extract::<i32>().unwrap();
-      });
-    });
+  #[cfg(feature = "py")]
+  fn from_py_script(&mut self, code:&str, src:&[u8], header:&serde_json::Value, policy:&ScriptPolicy) -> anyhow::Result<()> {
+    self.from_py_script_with(code, &ScriptModules::new(), src, header, policy)
   }
-}
-impl HrawScripting for [f32] {
-  fn from_lua_script(&mut self, code:&str, src:&[u8], width:usize, height:usize){
-    let lua = Lua::new();
-    lua.call_func(src, self, width, height, code).unwrap(); // Tをジェネリクスにするため
+  #[cfg(feature = "rhai")]
+  fn from_rhai_script(&mut self, code:&str, src:&[u8], header:&serde_json::Value, policy:&ScriptPolicy) -> anyhow::Result<()> {
+    self.from_rhai_script_with(code, &ScriptModules::new(), src, header, policy)
   }
-  fn from_py_script(&mut self, code:&str, src:&[u8], width:usize, height:usize){
-    Python::with_gil(|py| {
-      let module = PyModule::from_code_bound(py, code, "", "",).unwrap();
-      module.add("src", src).unwrap();
-      let func = module.getattr("function").unwrap();
-      (0..width*height).for_each(|i|{ 
-        self[i] = func.call1((i,)).unwrap().extract::<f32>().unwrap();
-      });
-    });
+  /// `modules` can be loaded with `require` (lua), `import` (python, rhai)
+  #[cfg(feature = "lua")]
+  fn from_lua_script_with(&mut self, code:&str, modules:&ScriptModules, src:&[u8], header:&serde_json::Value, policy:&ScriptPolicy) -> anyhow::Result<()>;
+  #[cfg(feature = "py")]
+  fn from_py_script_with(&mut self, code:&str, modules:&ScriptModules, src:&[u8], header:&serde_json::Value, policy:&ScriptPolicy) -> anyhow::Result<()>;
+  #[cfg(feature = "rhai")]
+  fn from_rhai_script_with(&mut self, code:&str, modules:&ScriptModules, src:&[u8], header:&serde_json::Value, policy:&ScriptPolicy) -> anyhow::Result<()>;
+  #[cfg(feature = "wasm")]
+  fn from_wasm(&mut self, wasm:&[u8], src:&[u8], header:&serde_json::Value, policy:&ScriptPolicy) -> anyhow::Result<()>;
+  /// native, always available
+  fn from_layout(&mut self, layout:&Layout, src:&[u8], header:&serde_json::Value) -> anyhow::Result<()>;
+  /// transforms the decoded frame in place, see `rawnumber::postprocess`
+  fn postprocess(&mut self, source:&PostprocessSource, inputs:&PostInputs, header:&serde_json::Value, policy:&ScriptPolicy) -> anyhow::Result<()>;
+  /// inverse of `from_*_script_with`, `function(index, value)` returns the bytes of one pixel
+  #[cfg(feature = "lua")]
+  fn to_lua_script_with(&self, code:&str, modules:&ScriptModules, header:&serde_json::Value, policy:&ScriptPolicy) -> anyhow::Result<Vec<u8>>;
+  #[cfg(feature = "py")]
+  fn to_py_script_with(&self, code:&str, modules:&ScriptModules, header:&serde_json::Value, policy:&ScriptPolicy) -> anyhow::Result<Vec<u8>>;
+  #[cfg(feature = "rhai")]
+  fn to_rhai_script_with(&self, code:&str, modules:&ScriptModules, header:&serde_json::Value, policy:&ScriptPolicy) -> anyhow::Result<Vec<u8>>;
+
+  /// dispatches on `lang` of the decoder
+  #[allow(unused_variables)]
+  fn from_source(&mut self, source:&DecoderSource, src:&[u8], header:&serde_json::Value, policy:&ScriptPolicy) -> anyhow::Result<()> {
+    match source.lang.as_str() {
+      #[cfg(feature = "lua")]
+      "lua" => self.from_lua_script_with(&source.code, &source.modules, src, header, policy),
+      #[cfg(feature = "py")]
+      "py" => self.from_py_script_with(&source.code, &source.modules, src, header, policy),
+      #[cfg(feature = "rhai")]
+      "rhai" => self.from_rhai_script_with(&source.code, &source.modules, src, header, policy),
+      #[cfg(feature = "wasm")]
+      "wasm" => self.from_wasm(&source.binary, src, header, policy),
+      "layout" => self.from_layout(source.layout.as_ref().ok_or(anyhow::anyhow!("decoder : layout not found"))?, src, header),
+      lang => anyhow::bail!("decoder : lang {lang} is not supported, check the cargo features")
+    }
   }
-}
-impl HrawScripting for [f64] {
-  fn from_lua_script(&mut self, code:&str, src:&[u8], width:usize, height:usize){
-    let lua = Lua::new();
-    lua.call_func(src, self, width, height, code).unwrap(); // Tをジェネリクスにするため
+  /// dispatches on `lang` of the encoder
+  #[allow(unused_variables)]
+  fn to_source(&self, source:&DecoderSource, header:&serde_json::Value, policy:&ScriptPolicy) -> anyhow::Result<Vec<u8>> {
+    match source.lang.as_str() {
+      #[cfg(feature = "lua")]
+      "lua" => self.to_lua_script_with(&source.code, &source.modules, header, policy),
+      #[cfg(feature = "py")]
+      "py" => self.to_py_script_with(&source.code, &source.modules, header, policy),
+      #[cfg(feature = "rhai")]
+      "rhai" => self.to_rhai_script_with(&source.code, &source.modules, header, policy),
+      lang => anyhow::bail!("encoder : lang {lang} is not supported, check the cargo features")
+    }
   }
-  fn from_py_script(&mut self, code:&str, src:&[u8], width:usize, height:usize){
-    Python::with_gil(|py| {
-      let module = PyModule::from_code_bound(py, code, "", "",).unwrap();
-      module.add("src", src).unwrap();
-      let func = module.getattr("function").unwrap();
-      (0..width*height).for_each(|i|{ 
-        self[i] = func.call1((i,)).unwrap().extract::<f64>().unwrap();
-      });
-    });
+}
+macro_rules! impl_hraw_scripting { ($($t:ty)*) => {
+  $(
+    impl HrawScripting for [$t] {
+      #[cfg(feature = "lua")]
+      fn from_lua_script_with(&mut self, code:&str, modules:&ScriptModules, src:&[u8], header:&serde_json::Value, policy:&ScriptPolicy) -> anyhow::Result<()> {
+        lua_par_call(code, modules, src, self, header, policy)
+      }
+      #[cfg(feature = "py")]
+      fn from_py_script_with(&mut self, code:&str, modules:&ScriptModules, src:&[u8], header:&serde_json::Value, policy:&ScriptPolicy) -> anyhow::Result<()> {
+        py_script_call(code, modules, src, self, header, policy)
+      }
+      #[cfg(feature = "rhai")]
+      fn from_rhai_script_with(&mut self, code:&str, modules:&ScriptModules, src:&[u8], header:&serde_json::Value, policy:&ScriptPolicy) -> anyhow::Result<()> {
+        rhai_par_call(code, modules, src, self, header, policy)
+      }
+      #[cfg(feature = "wasm")]
+      fn from_wasm(&mut self, wasm:&[u8], src:&[u8], header:&serde_json::Value, policy:&ScriptPolicy) -> anyhow::Result<()> {
+        let (width, height) = script_size(header)?;
+        wasm_call(wasm, src, self, width, height, policy)
+      }
+      fn from_layout(&mut self, layout:&Layout, src:&[u8], header:&serde_json::Value) -> anyhow::Result<()> {
+        layout_call(layout, src, self, header)
+      }
+      fn postprocess(&mut self, source:&PostprocessSource, inputs:&PostInputs, header:&serde_json::Value, policy:&ScriptPolicy) -> anyhow::Result<()> {
+        postprocess_call(source, self, inputs, header, policy)
+      }
+      #[cfg(feature = "lua")]
+      fn to_lua_script_with(&self, code:&str, modules:&ScriptModules, header:&serde_json::Value, policy:&ScriptPolicy) -> anyhow::Result<Vec<u8>> {
+        lua_par_encode(code, modules, self, header, policy)
+      }
+      #[cfg(feature = "py")]
+      fn to_py_script_with(&self, code:&str, modules:&ScriptModules, header:&serde_json::Value, policy:&ScriptPolicy) -> anyhow::Result<Vec<u8>> {
+        py_script_encode(code, modules, self, header, policy)
+      }
+      #[cfg(feature = "rhai")]
+      fn to_rhai_script_with(&self, code:&str, modules:&ScriptModules, header:&serde_json::Value, policy:&ScriptPolicy) -> anyhow::Result<Vec<u8>> {
+        rhai_encode(code, modules, self, header, policy)
+      }
+    }
+  )*
+}}
+impl_hraw_scripting!{ i32 f32 f64 }
+
+/*** check ***/
+
+/// (line in the script, message)
+pub type ScriptFault = (Option<usize>, String);
+/// decoded value of one pixel of a dry run
+pub type CheckResult = Result<f64, ScriptFault>;
+
+/// runs the decoder on `indices` only, see `Hraw::check_decoder`.
+/// `Err` when the decoder fails as a whole (compile error, missing entry point)
+#[allow(unused_variables)]
+pub fn check_source(source:&DecoderSource, src:&[u8], header:&serde_json::Value, policy:&ScriptPolicy, indices:&[usize]) -> Result<Vec<CheckResult>, ScriptFault> {
+  match source.lang.as_str() {
+    #[cfg(feature = "lua")]
+    "lua" => lua_check(&source.code, &source.modules, src, header, policy, indices),
+    #[cfg(feature = "py")]
+    "py" => py_check(&source.code, &source.modules, src, header, policy, indices),
+    #[cfg(feature = "rhai")]
+    "rhai" => rhai_check(&source.code, &source.modules, src, header, policy, indices),
+    #[cfg(feature = "wasm")]
+    "wasm" => wasm_check(&source.binary, src, header, policy, indices),
+    "layout" => match source.layout.as_ref() {
+      Some(layout) => layout_check(layout, src, header, indices),
+      None => Err((None, "decoder : layout not found".to_string()))
+    },
+    lang => Err((None, format!("decoder : lang {lang} is not supported, check the cargo features")))
   }
 }
 
-
+/// finite numbers only, NaN and inf are reported
+pub fn check_number(value:f64) -> CheckResult {
+  match value.is_finite() {
+    true => Ok(value),
+    false => Err((None, format!("decoded value is {value}")))
+  }
+}
 
 pub fn csx_call_array(code:&str, src:&[u8], _dst:&mut [i32], _width:usize, _height:usize) {
   use indoc::formatdoc;
@@ -150,6 +253,3 @@ pub fn csx_call_array(code:&str, src:&[u8], _dst:&mut [i32], _width:usize, _heig
   "#};
   csx_call(csx_code.as_str());
 }
-
-
-
@@ -0,0 +1,89 @@
+use crate::rawnumber::{ClampFrom, ScriptPolicy, ScriptFault, CheckResult, check_number, script_size};
+use wasmi::*;
+
+/*
+  wasm decoder
+    exports : `memory`, `decode(src_ptr, len, dst_ptr, width, height)` (all i32)
+    src     : raw bytes of the entry (offset included), placed in newly grown pages
+    dst     : width * height little-endian f64 right after src (8 byte aligned)
+  no imports are linked, the module can only touch its own memory.
+  `ScriptPolicy::instruction_limit` is the fuel, `memory_limit` bounds the linear memory
+  fuel is always consumed, without `instruction_limit` it is `wasm_default_fuel` so a decoder can not run forever
+*/
+
+const PAGE : usize = 65536;
+
+/// fuel per pixel without `instruction_limit`
+pub const WASM_FUEL_PER_PIXEL : u64 = 1024;
+
+/// `WASM_FUEL_PER_PIXEL` per pixel plus 2^20 for the setup
+pub fn wasm_default_fuel(width:usize, height:usize) -> u64 {
+  ((width * height) as u64).saturating_mul(WASM_FUEL_PER_PIXEL).saturating_add(1 << 20)
+}
+
+struct WasmState { limits: StoreLimits }
+
+/// decodes `width * height` pixels of `src` into `dst` with the `decode` export of `wasm`
+pub fn wasm_call<T:ClampFrom<f64>>(wasm:&[u8], src:&[u8], dst:&mut [T], width:usize, height:usize, policy:&ScriptPolicy) -> anyhow::Result<()> {
+  wasm_run(wasm, src, dst, width, height, policy, None)
+}
+
+/// `wasm_call` with `src.len()` bytes of `fill` right after `src`
+fn wasm_run<T:ClampFrom<f64>>(wasm:&[u8], src:&[u8], dst:&mut [T], width:usize, height:usize, policy:&ScriptPolicy, fill:Option<u8>) -> anyhow::Result<()> {
+  let total = width * height;
+  anyhow::ensure!(dst.len() >= total, "wasm : dst has {} pixels, {width}x{height} expected", dst.len());
+
+  let mut config = Config::default();
+  config.consume_fuel(true);
+  let engine = Engine::new(&config);
+  let module = Module::new(&engine, wasm).map_err(|e| anyhow::anyhow!("wasm : {e}"))?;
+
+  let mut limits = StoreLimitsBuilder::new();
+  if let Some(limit) = policy.memory_limit { limits = limits.memory_size(limit); }
+  let mut store = Store::new(&engine, WasmState { limits: limits.build() });
+  store.limiter(|state| &mut state.limits);
+  let fuel = policy.instruction_limit.unwrap_or(wasm_default_fuel(width, height));
+  store.set_fuel(fuel).map_err(|e| anyhow::anyhow!("wasm : {e}"))?;
+
+  let linker = Linker::<WasmState>::new(&engine);
+  let instance = linker.instantiate(&mut store, &module).and_then(|n| n.start(&mut store)).map_err(|e| anyhow::anyhow!("wasm : {e}"))?;
+  let memory = instance.get_memory(&store, "memory").ok_or(anyhow::anyhow!("wasm : memory is not exported"))?;
+  let decode = instance.get_typed_func::<(i32, i32, i32, i32, i32), ()>(&store, "decode")
+    .map_err(|e| anyhow::anyhow!("wasm : decode(src_ptr, len, dst_ptr, width, height) not found, {e}"))?;
+
+  // srcとdstは末尾に追加したpageに置く
+  let src_ptr = memory.data(&store).len();
+  let pad = fill.map_or(0, |_| src.len());
+  let dst_ptr = src_ptr + (src.len() + pad).next_multiple_of(8);
+  let end = dst_ptr + total * std::mem::size_of::<f64>();
+  anyhow::ensure!(end <= i32::MAX as usize, "wasm : frame does not fit in 32-bit memory");
+  let pages = u32::try_from((end - src_ptr).div_ceil(PAGE))?;
+  memory.grow(&mut store, core::Pages::new(pages).ok_or(anyhow::anyhow!("wasm : too many pages"))?)
+    .map_err(|e| anyhow::anyhow!("wasm : memory grow failed, {e}"))?;
+  memory.data_mut(&mut store)[src_ptr..src_ptr + src.len()].copy_from_slice(src);
+  if let Some(fill) = fill {
+    memory.data_mut(&mut store)[src_ptr + src.len()..src_ptr + src.len() + pad].fill(fill);
+  }
+
+  let args = (src_ptr as i32, src.len() as i32, dst_ptr as i32, width as i32, height as i32);
+  decode.call(&mut store, args).map_err(|e| anyhow::anyhow!("wasm : {e}"))?;
+
+  let out = &memory.data(&store)[dst_ptr..end];
+  out.chunks_exact(std::mem::size_of::<f64>()).zip(dst.iter_mut())
+    .for_each(|(b, d)| *d = T::clamp_from(f64::from_le_bytes(b.try_into().unwrap())));
+  Ok(())
+}
+
+/// the whole frame is decoded twice with `src.len()` bytes of 0x00 / 0xff after `src`,
+/// a pixel that differs read past the end. traps and fuel exhaustion fail as a whole
+pub fn wasm_check(wasm:&[u8], src:&[u8], header:&serde_json::Value, policy:&ScriptPolicy, indices:&[usize]) -> Result<Vec<CheckResult>, ScriptFault> {
+  let (width, height) = script_size(header).map_err(|e| (None, e.to_string()))?;
+  let mut low = vec![0f64; width * height];
+  let mut high = vec![0f64; width * height];
+  wasm_run(wasm, src, &mut low, width, height, policy, Some(0x00)).map_err(|e| (None, e.to_string()))?;
+  wasm_run(wasm, src, &mut high, width, height, policy, Some(0xff)).map_err(|e| (None, e.to_string()))?;
+  Ok(indices.iter().map(|&i| match low[i].to_bits() == high[i].to_bits() {
+    true => check_number(low[i]),
+    false => Err((None, format!("wasm : reads past the end of src ({} bytes)", src.len()))),
+  }).collect())
+}
@@ -0,0 +1,151 @@
+use crate::*;
+use crate::access::HrawRandomAccess;
+
+/*** regions ***/
+
+/*
+  active_area : { x : 16, y : 2, width : 1024, height : 768 }   # default : the frame without embedded_lines
+  optical_black :                                                # margins or rectangles
+    - { x : 0, y : 2, width : 16, height : 768 }
+  embedded_lines : 2                                             # top lines, or { top : 2, bottom : 1 }
+  all regions are in pixels of the `width x height` frame, embedded lines hold metadata bytes instead of pixels
+*/
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+pub struct Rect {
+  pub x: usize,
+  pub y: usize,
+  pub width: usize,
+  pub height: usize,
+}
+
+impl Rect {
+  pub fn contains(&self, x:usize, y:usize) -> bool {
+    self.x <= x && x < self.x + self.width && self.y <= y && y < self.y + self.height
+  }
+  pub fn is_empty(&self) -> bool { self.width == 0 || self.height == 0 }
+  /// fits in a `width x height` frame
+  pub fn is_inside(&self, width:usize, height:usize) -> bool {
+    self.x + self.width <= width && self.y + self.height <= height
+  }
+  /// shrunk to even x / y so CFA channels keep their index
+  pub fn align_cfa(&self) -> Rect {
+    let (dx, dy) = (self.x % 2, self.y % 2);
+    Rect { x: self.x + dx, y: self.y + dy, width: self.width.saturating_sub(dx), height: self.height.saturating_sub(dy) }
+  }
+}
+
+/// `optical_black` as written in the header
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum HeaderOpticalBlack {
+  Margins(ObMargins),
+  Rects(Vec<Rect>),
+}
+
+impl HeaderOpticalBlack {
+  pub fn rects(&self, width:usize, height:usize) -> Vec<Rect> {
+    match self {
+      HeaderOpticalBlack::Margins(n) => n.rects(width, height),
+      HeaderOpticalBlack::Rects(n) => n.to_owned(),
+    }
+  }
+}
+
+impl ObMargins {
+  /// left / right full height, top / bottom between them
+  pub fn rects(&self, width:usize, height:usize) -> Vec<Rect> {
+    let inner = width.saturating_sub(self.left + self.right);
+    [
+      Rect { x: 0, y: 0, width: self.left, height },
+      Rect { x: width.saturating_sub(self.right), y: 0, width: self.right, height },
+      Rect { x: self.left, y: 0, width: inner, height: self.top },
+      Rect { x: self.left, y: height.saturating_sub(self.bottom), width: inner, height: self.bottom },
+    ].into_iter().filter(|n| !n.is_empty()).collect()
+  }
+}
+
+/// `embedded_lines` as written in the header
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy)]
+#[serde(untagged)]
+pub enum HeaderEmbeddedLines {
+  Top(usize),
+  Lines {
+    #[serde(default)]
+    top: usize,
+    #[serde(default)]
+    bottom: usize
+  },
+}
+
+impl HeaderEmbeddedLines {
+  /// (top, bottom)
+  pub fn lines(&self) -> (usize, usize) {
+    match *self {
+      HeaderEmbeddedLines::Top(top) => (top, 0),
+      HeaderEmbeddedLines::Lines { top, bottom } => (top, bottom),
+    }
+  }
+}
+
+/// pixels of `rect` out of a `width` wide frame
+pub fn crop<T:Copy>(src:&[T], width:usize, rect:&Rect) -> Vec<T> {
+  (rect.y..rect.y + rect.height).flat_map(|y| src[y * width + rect.x..y * width + rect.x + rect.width].iter().copied()).collect()
+}
+
+impl Hraw {
+
+  /// `active_area` of the header, the frame without `embedded_lines` when not given
+  pub fn active_area(&mut self) -> anyhow::Result<Rect> {
+    let header = self.header().to_struct();
+    let (top, bottom) = header.embedded_lines.map(|n| n.lines()).unwrap_or_default();
+    let rect = match header.active_area {
+      Some(rect) => rect,
+      None => Rect { x: 0, y: top, width: header.width, height: header.height.saturating_sub(top + bottom) },
+    };
+    anyhow::ensure!(rect.is_inside(header.width, header.height), "active_area {rect:?} out of range {}x{}", header.width, header.height);
+    Ok(rect)
+  }
+
+  /// `optical_black` of the header as rectangles, empty when not given
+  pub fn optical_black(&mut self) -> anyhow::Result<Vec<Rect>> {
+    let header = self.header().to_struct();
+    let rects = header.optical_black.map(|n| n.rects(header.width, header.height)).unwrap_or_default();
+    if let Some(rect) = rects.iter().find(|n| !n.is_inside(header.width, header.height)) {
+      anyhow::bail!("optical_black {rect:?} out of range {}x{}", header.width, header.height);
+    }
+    Ok(rects)
+  }
+
+  /// pixels of the active area, row major
+  pub fn read_active<T:RawNumber, U:PathOrIndex>(&mut self, subpath:U) -> anyhow::Result<Vec<T::Item>> {
+    let rect = self.active_area()?;
+    self.read_roi::<T, U>(subpath, rect.x, rect.y, rect.width, rect.height)
+  }
+
+  /// pixels of each optical black rectangle
+  pub fn read_optical_black<T:RawNumber, U:PathOrIndex>(&mut self, subpath:U) -> anyhow::Result<Vec<(Rect, Vec<T::Item>)>> {
+    let path = subpath.to_name(self)?;
+    self.optical_black()?.into_iter()
+      .map(|rect| Ok((rect, self.read_roi::<T, _>(path.as_str(), rect.x, rect.y, rect.width, rect.height)?)))
+      .collect()
+  }
+
+  /// raw bytes of the embedded lines, top lines then bottom lines.
+  /// a line is `stride` pixels of the bitfield, or `(len - offset) / height` bytes with `unknown`
+  pub fn read_embedded<T:PathOrIndex>(&mut self, subpath:T) -> anyhow::Result<Vec<u8>> {
+    let header = self.header().to_struct();
+    let (top, bottom) = header.embedded_lines.map(|n| n.lines()).unwrap_or_default();
+    anyhow::ensure!(top + bottom <= header.height, "embedded_lines exceed height {}", header.height);
+    let path = subpath.to_name(self)?;
+    let line = match header.bitfield.size() {
+      Some(size) => header.stride * size,
+      None => (self.zip.by_name(path.as_str())?.size() as usize).saturating_sub(header.offset) / header.height.max(1),
+    };
+    let spans = (0..top).chain(header.height - bottom..header.height)
+      .map(|y| ((header.offset + y * line) as u64, line))
+      .collect::<Vec<_>>();
+    self.read_spans(path.as_str(), &spans)
+  }
+
+}
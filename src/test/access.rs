@@ -0,0 +1,126 @@
+use std::io::Write;
+
+/* width 5, height 4, offset 6, le_u16, value = x + y * 10 */
+fn create_archive(path:&std::path::Path, method:zip::CompressionMethod) -> anyhow::Result<()> {
+  let header = "width : 5\nheight : 4\noffset : 6\nbitfield : le_u16\ndata :\n  - data.raw\n";
+  let mut body = vec![0xFFu8; 6];
+  for y in 0..4u16 {
+    for x in 0..5u16 { body.extend((x + y * 10).to_le_bytes()); }
+  }
+  let options = zip::write::FileOptions::default().compression_method(method);
+  let mut zip = zip::ZipWriter::new(std::fs::File::create(path)?);
+  zip.start_file("header.yaml", options)?;
+  zip.write_all(header.as_bytes())?;
+  zip.start_file("data.raw", options)?;
+  zip.write_all(&body)?;
+  zip.finish()?;
+  Ok(())
+}
+
+#[test]
+fn random_access() -> anyhow::Result<()> {
+  use crate::access::*;
+  use crate::rawnumber::*;
+
+  let temp = tempfile::tempdir()?;
+  for method in [zip::CompressionMethod::Stored, zip::CompressionMethod::Deflated] {
+    let path = temp.path().join(format!("{method:?}.zip"));
+    create_archive(&path, method)?;
+    let mut hraw = crate::Hraw::new(path.to_str().unwrap())?;
+
+    assert_eq!(hraw.read_pixel::<le_u16, _>("data.raw", 0, 0)?, 0);
+    assert_eq!(hraw.read_pixel::<le_u16, _>(0, 4, 3)?, 34);
+    assert_eq!(hraw.read_row::<le_u16, _>("data.raw", 2)?, vec![20, 21, 22, 23, 24]);
+    assert_eq!(hraw.read_roi::<le_u16, _>("data.raw", 1, 1, 2, 3)?, vec![11, 12, 21, 22, 31, 32]);
+
+    /* 範囲外, BitFieldの不一致 */
+    assert!(hraw.read_pixel::<le_u16, _>("data.raw", 5, 0).is_err());
+    assert!(hraw.read_roi::<le_u16, _>("data.raw", 0, 3, 1, 2).is_err());
+    assert!(hraw.read_pixel::<le_i32, _>("data.raw", 0, 0).is_err());
+    assert!(hraw.read_roi::<le_u16, _>("data.raw", 1, 0, usize::MAX, 1).is_err());
+    assert!(hraw.read_roi::<le_u16, _>("data.raw", 0, 1, 1, usize::MAX).is_err());
+  }
+
+  /* bitfield unknownはdecoderが要る */
+  let fixture = super::fixture::Fixture::new();
+  let mut hraw = crate::Hraw::new(&fixture.unknown("lua"))?;
+  assert!(hraw.read_pixel::<le_i32, _>(0, 0, 0).unwrap_err().to_string().contains("unknown"));
+  Ok(())
+}
+
+#[test]
+fn memory_mapped() -> anyhow::Result<()> {
+  use crate::rawnumber::*;
+
+  let temp = tempfile::tempdir()?;
+  let path = temp.path().join("mapped.zip");
+  {
+    let header = "width : 3\nheight : 2\noffset : 4\nbitfield : le_f32\n";
+    let mut body = vec![0u8; 4];
+    (0..6).for_each(|i| body.extend((i as f32 * 0.5).to_le_bytes()));
+    let stored = zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Stored);
+    let mut zip = zip::ZipWriter::new(std::fs::File::create(&path)?);
+    zip.start_file("header.yaml", stored)?;
+    zip.write_all(header.as_bytes())?;
+    zip.start_file_aligned("data.raw", stored, 8)?;
+    zip.write_all(&body)?;
+    zip.start_file("deflated.raw", zip::write::FileOptions::default())?;
+    zip.write_all(&body)?;
+    zip.finish()?;
+  }
+
+  let mut hraw = crate::Hraw::new(path.to_str().unwrap())?;
+  let map = hraw.map_entry("data.raw")?;
+  assert_eq!(map.as_bytes().len(), 24);
+  #[cfg(target_endian = "little")]
+  assert_eq!(map.as_slice::<le_f32>()?, &[0.0, 0.5, 1.0, 1.5, 2.0, 2.5]);
+  assert!(map.as_slice::<be_f32>().is_err());
+  assert!(map.as_slice::<le_i32>().is_err());
+  assert!(hraw.map_entry("deflated.raw").is_err());
+  Ok(())
+}
+
+#[test]
+fn iterator_2d() -> anyhow::Result<()> {
+  use crate::buffer::*;
+  use crate::rawnumber::*;
+
+  let temp = tempfile::tempdir()?;
+  let path = temp.path().join("iter.zip");
+  create_archive(&path, zip::CompressionMethod::Deflated)?;
+  let path = path.to_str().unwrap();
+
+  let mut hraw = crate::Hraw::new(path)?;
+  assert!(hraw.enumerate_path::<le_u16>("data.raw").xy().all(|(x, y, n)| n as usize == x + y * 10));
+
+  let mut hraw = crate::Hraw::new(path)?;
+  let rows = hraw.enumerate_path::<le_u16>("data.raw").rows().collect::<Vec<_>>();
+  assert_eq!(rows.len(), 4);
+  assert_eq!(rows[3], (3, vec![30, 31, 32, 33, 34]));
+
+  let mut hraw = crate::Hraw::new(path)?;
+  let column = hraw.enumerate_path::<le_u16>("data.raw").column(2).collect::<Vec<_>>();
+  assert_eq!(column, vec![(0, 2), (1, 12), (2, 22), (3, 32)]);
+
+  let mut hraw = crate::Hraw::new(path)?;
+  let plane = hraw.enumerate_path::<le_u16>("data.raw").bayer_plane((1, 0)).collect::<Vec<_>>();
+  assert_eq!(plane, vec![(0, 0, 1), (1, 0, 3), (0, 1, 21), (1, 1, 23)]);
+
+  let mut hraw = crate::Hraw::new(path)?;
+  let sub = hraw.enumerate_path::<le_u16>("data.raw").subsample((0, 1), (3, 2)).map(|n| n.2).collect::<Vec<_>>();
+  assert_eq!(sub, vec![10, 13, 30, 33]);
+  Ok(())
+}
+
+#[test]
+#[should_panic(expected = "must be positive")]
+fn subsample_zero_step() {
+  use crate::buffer::*;
+  use crate::rawnumber::*;
+
+  let temp = tempfile::tempdir().unwrap();
+  let path = temp.path().join("iter.zip");
+  create_archive(&path, zip::CompressionMethod::Deflated).unwrap();
+  let mut hraw = crate::Hraw::new(path.to_str().unwrap()).unwrap();
+  let _ = hraw.enumerate_path::<le_u16>("data.raw").subsample((0, 0), (0, 2)).count();
+}
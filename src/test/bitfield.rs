@@ -0,0 +1,107 @@
+/*
+  BitField x destination type
+  HrawWriter -> enumerate_path / FromHraw
+*/
+
+macro_rules! round_trip { ($dir:ident; $($t:ident : $u:ty = [$($v:expr),*];)*) => {$(
+  {
+    use crate::rawnumber::*;
+    use crate::buffer::*;
+    let src : Vec<$u> = vec![$($v),*];
+    let header = serde_json::json!({ "width" : src.len(), "height" : 1, "offset" : 3, "bitfield" : stringify!($t) });
+    let path = $dir.archive::<$t>(concat!(stringify!($t), ".zip"), header, &[("data.raw", src.clone())]);
+    let path = path.as_str();
+
+    /* NaNを含むのでDebug表記で比較 */
+    let mut hraw = crate::Hraw::new(path)?;
+    let dst = hraw.enumerate_path::<$t>("data.raw").map(|n| n.1).collect::<Vec<$u>>();
+    assert_eq!(format!("{:?}", src), format!("{:?}", dst), stringify!($t));
+
+    let mut dst = vec![0i32; src.len()];
+    dst.as_mut_slice().from_hraw(path, "data.raw")?;
+    assert_eq!(dst, src.iter().map(|n| i32::clamp_from(*n)).collect::<Vec<_>>(), stringify!($t));
+
+    let mut dst = vec![0f32; src.len()];
+    dst.as_mut_slice().from_hraw(path, 0)?;
+    let expected = src.iter().map(|n| f32::clamp_from(*n)).collect::<Vec<_>>();
+    assert_eq!(format!("{:?}", dst), format!("{:?}", expected), stringify!($t));
+
+    let mut dst = vec![0f64; src.len()];
+    dst.as_mut_slice().from_hraw(path, 0)?;
+    let expected = src.iter().map(|n| f64::clamp_from(*n)).collect::<Vec<_>>();
+    assert_eq!(format!("{:?}", dst), format!("{:?}", expected), stringify!($t));
+  }
+)*}}
+
+#[test]
+fn bitfield_round_trip() -> anyhow::Result<()> {
+  let fixture = super::fixture::Fixture::new();
+  round_trip!{ fixture;
+    le_u8  : u8  = [0, 1, 0x7F, 0x80, u8::MAX];
+    be_u8  : u8  = [0, 1, 0x7F, 0x80, u8::MAX];
+    le_i8  : i8  = [0, -1, i8::MIN, i8::MAX];
+    be_i8  : i8  = [0, -1, i8::MIN, i8::MAX];
+    le_u16 : u16 = [0, 1, 0x0102, u16::MAX];
+    be_u16 : u16 = [0, 1, 0x0102, u16::MAX];
+    le_i16 : i16 = [0, -2, 0x0102, i16::MIN, i16::MAX];
+    be_i16 : i16 = [0, -2, 0x0102, i16::MIN, i16::MAX];
+    le_u32 : u32 = [0, 0x01020304, i32::MAX as u32, u32::MAX];
+    be_u32 : u32 = [0, 0x01020304, i32::MAX as u32, u32::MAX];
+    le_i32 : i32 = [0, -3, 0x01020304, i32::MIN, i32::MAX];
+    be_i32 : i32 = [0, -3, 0x01020304, i32::MIN, i32::MAX];
+    le_u64 : u64 = [0, 0x0102030405060708, u64::MAX];
+    be_u64 : u64 = [0, 0x0102030405060708, u64::MAX];
+    le_i64 : i64 = [0, -4, i64::MIN, i64::MAX, i32::MIN as i64 - 1];
+    be_i64 : i64 = [0, -4, i64::MIN, i64::MAX, i32::MIN as i64 - 1];
+    le_f32 : f32 = [0.0, -1.5, 1e10, f32::MIN, f32::MAX, f32::NAN, f32::INFINITY, f32::NEG_INFINITY];
+    be_f32 : f32 = [0.0, -1.5, 1e10, f32::MIN, f32::MAX, f32::NAN, f32::INFINITY, f32::NEG_INFINITY];
+    le_f64 : f64 = [0.0, -1.5, 1e300, f64::MIN, f64::MAX, f64::NAN, f64::INFINITY, f64::NEG_INFINITY];
+    be_f64 : f64 = [0.0, -1.5, 1e300, f64::MIN, f64::MAX, f64::NAN, f64::INFINITY, f64::NEG_INFINITY];
+  }
+  Ok(())
+}
+
+#[test]
+fn bitfield_byte_order() -> anyhow::Result<()> {
+  use crate::buffer::*;
+  use std::io::Write;
+
+  /* writerを介さずにbyte列を直接書く */
+  let temp = tempfile::tempdir()?;
+  let bytes = [0x01u8, 0x02, 0x03, 0x84];
+  let expected = [
+    ("le_i32", i32::from_le_bytes(bytes)), ("be_i32", i32::from_be_bytes(bytes)),
+    ("le_u16", 0x0201), ("be_u16", 0x0102),
+    ("le_i16", 0x0201), ("be_i16", 0x0102),
+  ];
+  for (bitfield, value) in expected {
+    let path = temp.path().join(format!("{bitfield}.zip"));
+    {
+      let mut zip = zip::ZipWriter::new(std::fs::File::create(&path)?);
+      zip.start_file("header.yaml", zip::write::FileOptions::default())?;
+      write!(zip, "width : 1\nheight : 1\nbitfield : {bitfield}\n")?;
+      zip.start_file("data.raw", zip::write::FileOptions::default())?;
+      zip.write_all(&bytes)?;
+      zip.finish()?;
+    }
+    let mut dst = vec![0i32; 1];
+    dst.as_mut_slice().from_hraw(path.to_str().unwrap(), 0)?;
+    assert_eq!(dst[0], value, "{bitfield}");
+  }
+  Ok(())
+}
+
+#[test]
+fn bitfield_clamp_boundary() {
+  use crate::rawnumber::ClampFrom;
+
+  assert_eq!(i32::clamp_from(u32::MAX), i32::MAX);
+  assert_eq!(i32::clamp_from(i64::MIN), i32::MIN);
+  assert_eq!(i32::clamp_from(u64::MAX), i32::MAX);
+  assert_eq!(i32::clamp_from(f32::INFINITY), i32::MAX);
+  assert_eq!(i32::clamp_from(f64::NEG_INFINITY), i32::MIN);
+  assert_eq!(i32::clamp_from(f32::NAN), 0);
+  assert_eq!(i32::clamp_from(-1.5f64), -1);
+  assert!(f32::clamp_from(f64::NAN).is_nan());
+  assert_eq!(f32::clamp_from(f64::MAX), f32::INFINITY);
+}
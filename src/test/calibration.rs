@@ -0,0 +1,87 @@
+use super::fixture::*;
+use crate::calibration::*;
+use crate::rawnumber::*;
+
+/* dark = 100 + x % 3, vignette = 1 - x / 128, light = dark + 500 * vignette */
+fn dark(n:usize) -> Vec<i32> { (0..WIDTH * HEIGHT).map(|i| 100 + (i % WIDTH % 3) as i32 + n as i32 % 2).collect() }
+fn vignette(i:usize) -> f64 { 1.0 - (i % WIDTH) as f64 / 128.0 }
+fn exposed(n:usize, level:f64) -> Vec<i32> { dark(n).iter().enumerate().map(|(i, d)| d + (level * vignette(i)).round() as i32).collect() }
+
+fn calibration_archive(fixture:&Fixture) -> String {
+  let frames = vec![
+    ("light.raw", exposed(0, 500.0)),
+    ("dark/0.raw", dark(0)), ("dark/1.raw", dark(1)), ("dark/2.raw", dark(2)),
+    ("flat/0.raw", exposed(0, 1000.0)), ("flat/1.raw", exposed(1, 1000.0)), ("flat/2.raw", exposed(2, 1000.0)),
+  ];
+  fixture.archive::<le_i32>("calibration.zip", header(), &frames)
+}
+
+#[test]
+fn master_frames() -> anyhow::Result<()> {
+  let frames = [vec![1.0, 10.0], vec![2.0, 10.0], vec![9.0, 40.0]];
+  assert_eq!(master_frame(&frames, Combine::Mean)?, vec![4.0, 20.0]);
+  assert_eq!(master_frame(&frames, Combine::Median)?, vec![2.0, 10.0]);
+  assert_eq!(master_frame(&frames[0..2], Combine::Median)?, vec![1.5, 10.0]);
+  assert!(master_frame::<Vec<f64>>(&[], Combine::Mean).is_err());
+  assert!(master_frame(&[vec![1.0], vec![1.0, 2.0]], Combine::Mean).is_err());
+
+  /* CFAごとに平均1 */
+  let flat = [100.0, 200.0, 300.0, 600.0, 300.0, 600.0, 100.0, 200.0];
+  let normalized = normalize_flat(&flat, None, 4, true);
+  assert_eq!(normalized, vec![0.5, 0.5, 1.5, 1.5, 1.5, 1.5, 0.5, 0.5]);
+  assert_eq!(normalize_flat(&flat, Some(&[100.0; 8]), 4, false)[0], 0.0);
+  Ok(())
+}
+
+#[test]
+fn dark_and_flat_correction() -> anyhow::Result<()> {
+  let fixture = Fixture::new();
+  let policy = ScriptPolicy::default();
+  let path = calibration_archive(&fixture);
+  let mut hraw = crate::Hraw::new(&path)?;
+
+  let dark = hraw.master_frame(&["dark/0.raw", "dark/1.raw", "dark/2.raw"], Combine::Median, &policy)?;
+  assert_eq!(dark, dark_frame());
+  let flat = hraw.master_frame(&["flat/0.raw", "flat/1.raw", "flat/2.raw"], Combine::Mean, &policy)?;
+
+  /* 補正後はvignetteが消えて 500 * mean(vignette) 付近で一定 */
+  let calibration = Calibration::new(Some(dark.clone()), Some(&flat), 2.0, WIDTH, false);
+  let mut light = hraw.frame_f64("light.raw", &policy)?;
+  calibration.apply(&mut light)?;
+  let mean = light.iter().sum::<f64>() / light.len() as f64;
+  assert!(light.iter().all(|n| (n - mean).abs() < 3.0), "{mean}");
+  assert!((mean - 2.0 * 500.0 * (0..WIDTH).map(vignette).sum::<f64>() / WIDTH as f64).abs() < 3.0);
+
+  /* darkのみ, i32のまま */
+  let mut light = exposed(0, 500.0);
+  Calibration { dark: Some(dark.clone()), flat: None, gain: 1.0 }.apply(&mut light)?;
+  assert!(light.iter().enumerate().all(|(i, n)| (*n as f64 - 500.0 * vignette(i)).abs() <= 1.0));
+  assert!(Calibration { dark: Some(vec![0.0; 4]), flat: None, gain: 1.0 }.apply(&mut light).is_err());
+
+  /* 書き戻してheaderから読む */
+  let out = fixture.path("calibrated.zip");
+  let mut writer = crate::writer::HrawWriter::new(&out, header())?;
+  writer.write_data::<le_i32>("light.raw", &exposed(0, 500.0))?;
+  writer.write_calibration(Some(&dark), Some(&flat), 2.0, false)?;
+  writer.finish()?;
+  let mut hraw = crate::Hraw::new(&out)?;
+  assert_eq!(hraw.header()["calibration"]["dark"], CALIBRATION_DARK);
+  let loaded = hraw.calibration(&policy)?.expect("calibration");
+  assert_eq!(loaded.dark.as_deref(), Some(dark.as_slice()));
+  let (mut a, mut b) = (hraw.frame_f64(0, &policy)?, hraw.frame_f64(0, &policy)?);
+  loaded.apply(&mut a)?;
+  calibration.apply(&mut b)?;
+  assert!(a.iter().zip(b.iter()).all(|(a, b)| (a - b).abs() < 1e-3));
+
+  /* dataのentryをそのまま参照 */
+  let mut header = header();
+  header["calibration"] = serde_json::json!({ "dark" : "dark.raw" });
+  let path = fixture.archive::<le_i32>("data_dark.zip", header, &[("light.raw", exposed(0, 500.0)), ("dark.raw", dark_i32())]);
+  let loaded = crate::Hraw::new(&path)?.calibration(&policy)?.expect("calibration");
+  assert_eq!(loaded.dark, Some(dark));
+  assert!(crate::Hraw::new(&fixture.i32())?.calibration(&policy)?.is_none());
+  Ok(())
+}
+
+fn dark_frame() -> Vec<f64> { dark(0).iter().map(|n| *n as f64).collect() }
+fn dark_i32() -> Vec<i32> { dark_frame().iter().map(|n| *n as i32).collect() }
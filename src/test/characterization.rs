@@ -0,0 +1,76 @@
+use super::fixture::*;
+use crate::characterization::*;
+use crate::rawnumber::*;
+
+/*
+  K = 0.5 DN/e-, read noise 2 DN, DSNU 1.5 DN, PRNU 1 %, offset 100 DN, 600 DN で飽和
+  QE 0.5 : electrons = photons / 2, photons = 200 * time
+*/
+const K : f64 = 0.5;
+
+fn sensor_frame(noise:&mut Rng, fixed:&[(f64, f64)], electrons:f64) -> Vec<i32> {
+  fixed.iter().map(|(offset, gain)| {
+    let e = electrons * gain;
+    let e = e + e.sqrt() * noise.normal();
+    (100.0 + offset + K * e + 2.0 * noise.normal()).round().min(600.0) as i32
+  }).collect()
+}
+
+fn level_archive(fixture:&Fixture, noise:&mut Rng, fixed:&[(f64, f64)], time:f64) -> String {
+  let frames = ["light/0.raw", "light/1.raw", "dark/0.raw", "dark/1.raw"].iter().enumerate()
+    .map(|(n, entry)| (*entry, sensor_frame(noise, fixed, if n < 2 { time * 100.0 } else { 0.0 })))
+    .collect::<Vec<_>>();
+  let mut header = header();
+  header["exposure"] = serde_json::json!({ "time" : time, "photons" : time * 200.0 });
+  fixture.archive::<le_i32>(&format!("level_{time}.zip"), header, &frames)
+}
+
+#[test]
+fn emva_1288() -> anyhow::Result<()> {
+  let fixture = Fixture::new();
+  let mut noise = Rng::new(0x2545F4914F6CDD1D);
+  let fixed = (0..WIDTH * HEIGHT).map(|_| (1.5 * noise.normal(), 1.0 + 0.01 * noise.normal())).collect::<Vec<_>>();
+  let paths = (1..=12).map(|t| level_archive(&fixture, &mut noise, &fixed, t as f64)).collect::<Vec<_>>();
+  let report = characterize_archives(&paths.iter().map(String::as_str).collect::<Vec<_>>(), &ScriptPolicy::default())?;
+
+  let near = |value:f64, expected:f64, tolerance:f64| (value - expected).abs() <= expected * tolerance;
+  assert_eq!(report.levels.len(), 12);
+  assert!(near(report.conversion_gain, K, 0.05), "{report:?}");
+  assert!(near(report.read_noise_dn, 2.0, 0.1), "{report:?}");
+  assert!(near(report.read_noise_e, 4.0, 0.15), "{report:?}");
+  assert!(near(report.dsnu_dn, 1.5, 0.15), "{report:?}");
+  assert!(near(report.prnu, 1.0, 0.3), "{report:?}");
+  assert!(near(report.full_well_e, 900.0, 0.1), "{report:?}");
+  assert!(report.linearity_error < 1.0, "{report:?}");
+  assert!(near(report.quantum_efficiency.unwrap(), 0.5, 0.05), "{report:?}");
+
+  let json = serde_json::to_value(&report)?;
+  assert!(json["conversion_gain"].is_f64() && json["levels"][0]["time"] == 1.0);
+  assert!(image::load_from_memory(&report.ptc_png(320, 240)).is_ok());
+  assert!(image::load_from_memory(&report.linearity_png(320, 240)).is_ok());
+  Ok(())
+}
+
+#[test]
+fn exposure_header() -> anyhow::Result<()> {
+  let fixture = Fixture::new();
+  let frames = (0..4).map(|n| (DATA.get(n).copied().unwrap_or("dark.raw"), frame(n % 2))).collect::<Vec<_>>();
+  let mut header = header();
+  header["exposure"] = serde_json::json!(2.5);
+  let path = fixture.archive::<le_i32>("scalar.zip", header, &frames);
+  let mut hraw = crate::Hraw::new(&path)?;
+  assert_eq!(hraw.exposure().unwrap().time, 2.5);
+
+  /* light = data[0..2], dark = data[2..4] : frame(0), frame(1) の組, 差は一定なので時間方向の分散は0 */
+  let level = hraw.exposure_level(&ScriptPolicy::default())?;
+  assert_eq!(level.time, 2.5);
+  assert_eq!(level.photons, None);
+  assert_eq!(level.temporal_variance, 0.0);
+  assert_eq!(level.signal(), 0.0);
+
+  assert!(crate::Hraw::new(&fixture.i32())?.exposure_level(&ScriptPolicy::default()).is_err());
+  assert!(characterize(&[level]).is_err());
+  assert_eq!(linear_fit(&[(1.0, 3.0), (2.0, 5.0), (3.0, 7.0)]), Some((2.0, 1.0)));
+  assert_eq!(linear_fit(&[(1.0, 3.0), (1.0, 5.0)]), None);
+  Ok(())
+}
@@ -0,0 +1,79 @@
+use super::fixture::*;
+use crate::convert::*;
+use crate::rawnumber::*;
+
+#[test]
+fn welford() -> anyhow::Result<()> {
+  let mut welford = Welford::new(2);
+  for frame in [[1.0, 10.0], [2.0, 10.0], [6.0, 40.0]] { welford.push(&frame)?; }
+  assert_eq!(welford.count(), 3);
+  assert_eq!(welford.mean(), vec![3.0, 20.0]);
+  assert_eq!(welford.variance(), vec![7.0, 300.0]);
+  assert_eq!(welford.min(), vec![1.0, 10.0]);
+  assert_eq!(welford.max(), vec![6.0, 40.0]);
+  assert!(welford.push(&[1.0]).is_err());
+
+  let mut single = Welford::new(1);
+  single.push(&[5.0])?;
+  assert_eq!(single.std(), vec![0.0]);
+  Ok(())
+}
+
+#[test]
+fn convert_archive() -> anyhow::Result<()> {
+  let fixture = Fixture::new();
+  let policy = ScriptPolicy::default();
+  let src = fixture.i32();
+
+  /* frame(n)はframeごとに100000ずつ増える */
+  let dst = fixture.path("ave.zip");
+  let stats = convert(&src, &dst, &ConvertOptions::default(), &policy)?;
+  assert_eq!(stats.frames, 3);
+  assert_eq!(stats.mean, frame(1).iter().map(|n| *n as f64).collect::<Vec<_>>());
+  assert!(stats.std.iter().all(|n| (n - 100_000.0).abs() < 1e-6));
+
+  let mut hraw = crate::Hraw::new(&dst)?;
+  let header = hraw.header();
+  assert_eq!(header["bitfield"], "le_f32");
+  assert_eq!(header["convert"]["frames"], 3);
+  assert_eq!(header["data"], serde_json::json!([CONVERT_SINGLE, CONVERT_MEAN, CONVERT_STD, CONVERT_MIN, CONVERT_MAX]));
+  let read = |hraw:&mut crate::Hraw, name:&str| hraw.frame_f64(name, &policy);
+  assert_eq!(read(&mut hraw, CONVERT_SINGLE)?, frame(0).iter().map(|n| *n as f64).collect::<Vec<_>>());
+  assert_eq!(read(&mut hraw, CONVERT_MIN)?, frame(0).iter().map(|n| *n as f64).collect::<Vec<_>>());
+  assert_eq!(read(&mut hraw, CONVERT_MAX)?, frame(2).iter().map(|n| *n as f64).collect::<Vec<_>>());
+  assert!(read(&mut hraw, CONVERT_STD)?.iter().all(|n| *n == 100_000.0));
+
+  /* --num 2 --fixed 100 */
+  let options = ConvertOptions { num: Some(2), fixed: 100.0 };
+  let stats = convert(&src, &dst, &options, &policy)?;
+  assert_eq!(stats.frames, 2);
+  let mut hraw = crate::Hraw::new(&dst)?;
+  assert_eq!(hraw.header()["convert"]["fixed"], 100.0);
+  let mean = read(&mut hraw, CONVERT_MEAN)?;
+  assert_eq!(mean[0], ((frame(0)[0] + frame(1)[0]) as f64 / 2.0 * 100.0) as f32 as f64);
+
+  /* levelsもfixed倍, 領域はそのまま */
+  let mut header = super::fixture::header();
+  header["black_level"] = serde_json::json!(64);
+  header["white_level"] = serde_json::json!(4095);
+  header["optical_black"] = serde_json::json!({ "left" : 2 });
+  let frames = DATA.iter().enumerate().map(|(n, entry)| (*entry, frame(n))).collect::<Vec<_>>();
+  let levels_src = fixture.archive::<le_i32>("levels.zip", header.clone(), &frames);
+  convert(&levels_src, &dst, &options, &policy)?;
+  let mut hraw = crate::Hraw::new(&dst)?;
+  let levels = hraw.levels(CONVERT_MEAN, &policy)?;
+  assert_eq!(levels.black, crate::levels::BlackLevel::Global(6400.0));
+  assert_eq!(levels.white, 409500.0);
+  assert_eq!(hraw.header()["optical_black"], header["optical_black"]);
+  header["black_level"] = serde_json::json!([1, 2, 3, 4]);
+  header.as_object_mut().unwrap().remove("white_level");
+  let levels_src = fixture.archive::<le_i32>("levels_cfa.zip", header, &frames);
+  convert(&levels_src, &dst, &options, &policy)?;
+  let levels = crate::Hraw::new(&dst)?.levels(CONVERT_MEAN, &policy)?;
+  assert_eq!(levels.black, crate::levels::BlackLevel::Cfa([100.0, 200.0, 300.0, 400.0]));
+  assert_eq!(levels.white, i32::MAX as f64 * 100.0);
+
+  assert!(convert(&src, &dst, &ConvertOptions { num: Some(4), fixed: 1.0 }, &policy).is_err());
+  assert!(convert(&src, &dst, &ConvertOptions { num: Some(0), fixed: 1.0 }, &policy).is_err());
+  Ok(())
+}
@@ -0,0 +1,123 @@
+use super::fixture::*;
+use crate::defects::*;
+use crate::rawnumber::*;
+
+/*
+  dark : 100 + (x + y + n) % 3, hot (3, 4) = 400, noisy (7, 2) = 100 / 180 交互
+  flat : 1000 + CFA channel * 200 + (x + n) % 5, dead (10, 6), stuck (12, 9) は一定
+*/
+fn dark(n:usize) -> Vec<i32> {
+  (0..WIDTH * HEIGHT).map(|i| match (i % WIDTH, i / WIDTH) {
+    (3, 4) => 400,
+    (7, 2) => [100, 180][n % 2],
+    (x, y) => 100 + ((x + y + n) % 3) as i32
+  }).collect()
+}
+fn flat(n:usize) -> Vec<i32> {
+  (0..WIDTH * HEIGHT).map(|i| match (i % WIDTH, i / WIDTH) {
+    (10, 6) => 150,
+    (12, 9) => 1500,
+    (x, y) => 1000 + ((x % 2 + (y % 2) * 2) * 200 + (x + n) % 5) as i32
+  }).collect()
+}
+
+fn expected() -> Vec<Defect> {
+  vec![
+    Defect { x: 7, y: 2, kind: DefectKind::Noisy },
+    Defect { x: 3, y: 4, kind: DefectKind::Hot },
+    Defect { x: 10, y: 6, kind: DefectKind::Dead },
+    Defect { x: 12, y: 9, kind: DefectKind::Stuck },
+  ]
+}
+
+#[test]
+fn detect_from_stacks() -> anyhow::Result<()> {
+  let fixture = Fixture::new();
+  let policy = ScriptPolicy::default();
+  let frames = (0..4).flat_map(|n| [(format!("dark/{n}.raw"), dark(n)), (format!("flat/{n}.raw"), flat(n))]).collect::<Vec<_>>();
+  let frames = frames.iter().map(|(k, v)| (k.as_str(), v.clone())).collect::<Vec<_>>();
+  let path = fixture.archive::<le_i32>("defects.zip", header(), &frames);
+  let mut hraw = crate::Hraw::new(&path)?;
+
+  let darks = ["dark/0.raw", "dark/1.raw", "dark/2.raw", "dark/3.raw"];
+  let flats = ["flat/0.raw", "flat/1.raw", "flat/2.raw", "flat/3.raw"];
+  let defects = hraw.detect_defects(&darks, &flats, &DefectThresholds::default(), &policy)?;
+  assert_eq!(defects, expected());
+
+  /* darkのみ, flatのみ, なし */
+  let defects = hraw.detect_defects(&darks, &[], &DefectThresholds::default(), &policy)?;
+  assert_eq!(defects.iter().map(|n| n.kind).collect::<Vec<_>>(), [DefectKind::Noisy, DefectKind::Hot]);
+  let defects = hraw.detect_defects(&[], &flats[0..1], &DefectThresholds::default(), &policy)?;
+  assert_eq!(defects, vec![Defect { x: 10, y: 6, kind: DefectKind::Dead }]); // 1枚ではstuckは判定しない
+  let defects = hraw.detect_defects(&[], &flats[0..2], &DefectThresholds::default(), &policy)?;
+  assert_eq!(defects, vec![Defect { x: 10, y: 6, kind: DefectKind::Dead }]); // 2枚でも判定しない
+  let defects = hraw.detect_defects(&[], &flats[0..3], &DefectThresholds::default(), &policy)?;
+  assert_eq!(defects.iter().map(|n| n.kind).collect::<Vec<_>>(), [DefectKind::Dead, DefectKind::Stuck]);
+  assert!(hraw.detect_defects::<&str>(&[], &[], &DefectThresholds::default(), &policy).is_err());
+
+  /* darkとflatの画素数が違う */
+  let dark = stack_stats(&[vec![100.0; 12], vec![101.0; 12]])?;
+  let flat = stack_stats(&[vec![1000.0; 8], vec![1001.0; 8], vec![1002.0; 8]])?;
+  assert!(detect_defects(Some(&dark), Some(&flat), 4, &DefectThresholds::default()).is_err());
+  assert!(detect_defects(Some(&dark), None, 0, &DefectThresholds::default()).is_err());
+  Ok(())
+}
+
+#[test]
+fn defect_storage() -> anyhow::Result<()> {
+  let fixture = Fixture::new();
+  for entry in [None, Some("defects.raw")] {
+    let path = fixture.path("stored.zip");
+    let mut writer = crate::writer::HrawWriter::new(&path, header())?;
+    writer.write_data::<le_i32>("data.raw", &frame(0))?;
+    writer.write_defects(&expected(), entry)?;
+    writer.finish()?;
+    let mut defects = crate::Hraw::new(&path)?.defects()?;
+    defects.sort_by_key(|n| (n.y, n.x));
+    assert_eq!(defects, expected());
+  }
+
+  /* 座標のみ, kind省略 */
+  let mut header = header();
+  header["defects"] = serde_json::json!([[1, 2], { "x" : 3, "y" : 4 }, { "x" : 5, "y" : 6, "kind" : "stuck" }]);
+  let path = fixture.archive::<le_i32>("list.zip", header, &[("data.raw", frame(0))]);
+  assert_eq!(crate::Hraw::new(&path)?.defects()?, vec![
+    Defect { x: 1, y: 2, kind: DefectKind::Hot }, Defect { x: 3, y: 4, kind: DefectKind::Hot }, Defect { x: 5, y: 6, kind: DefectKind::Stuck }
+  ]);
+  assert!(crate::Hraw::new(&fixture.i32())?.defects()?.is_empty());
+  Ok(())
+}
+
+#[test]
+fn cfa_aware_correction() {
+  /* CFA channelごとに一定値 */
+  let (width, height) = (8, 6);
+  let bayer = |x:usize, y:usize| [100, 200, 300, 400][x % 2 + (y % 2) * 2];
+  let clean = (0..width * height).map(|i| bayer(i % width, i / width)).collect::<Vec<i32>>();
+  let defects = [Defect { x: 3, y: 3, kind: DefectKind::Hot }, Defect { x: 5, y: 3, kind: DefectKind::Dead }, Defect { x: 0, y: 0, kind: DefectKind::Hot }];
+  let mut src = clean.clone();
+  defects.iter().for_each(|n| src[n.x + n.y * width] = 4000);
+  correct_defects(&mut src, width, height, &defects, true);
+  assert_eq!(src, clean);
+
+  /* mono : 隣接pixel */
+  let mut src = vec![10f32; 9];
+  src[4] = 99.0;
+  correct_defects(&mut src, 3, 3, &[Defect { x: 1, y: 1, kind: DefectKind::Hot }], false);
+  assert_eq!(src, vec![10f32; 9]);
+
+  let png = crate::processing::slice_to_png_defects(&clean, width, height, &defects, 4, None, 5);
+  assert!(image::load_from_memory(&png).is_ok());
+
+  /* defects -> levels -> stretch をまとめて */
+  use crate::processing::*;
+  let mut src = clean.clone();
+  defects.iter().for_each(|n| src[n.x + n.y * width] = 4000);
+  let levels = crate::levels::Levels { black: crate::levels::BlackLevel::Global(50.0), white: 450.0 };
+  let options = PngOptions { defects: defects.to_vec(), levels: Some(levels.clone()), color: 5, ..Default::default() };
+  assert_eq!(slice_to_png_with(&src, width, height, &options).unwrap(), slice_to_png_levels(&clean, width, height, &levels, None, 5));
+  let options = PngOptions { stretch: Some((0.0, 100.0)), ..options };
+  assert_eq!(slice_to_png_with(&src, width, height, &options).unwrap(), slice_to_png_auto(&clean, width, height, 0.0, 100.0, None, 5).unwrap());
+  let options = PngOptions { defects: defects.to_vec(), bitshift: 4, color: 5, ..Default::default() };
+  assert_eq!(slice_to_png_with(&src, width, height, &options).unwrap(), png);
+}
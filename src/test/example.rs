@@ -1,43 +1,46 @@
-const ERR_FILE :&str = r".\err.zip";
-const TEST_FILE_I32 :&str = r".\ship_i32.zip";
-const TEST_FILE_U16 :&str = r".\ship_u16.zip";
-const TEST_FILE_UNKNOWN :&str = r".\ship_i32_unknown.zip";
+use super::fixture::*;
 
 #[test]
 fn hraw_header() -> anyhow::Result<()> {
   use crate::*;
+  let fixture = Fixture::new();
+  let test_file_i32 = fixture.i32();
 
   /* ファイル構造, headerのinfomationの表示 */
-  let mut hraw = crate::Hraw::new(TEST_FILE_I32)?;
+  let mut hraw = crate::Hraw::new(&test_file_i32)?;
   hraw.info().unwrap();
 
   /* headerをserde_json::Valueで取得, デシリアライズ*/
-  let mut hraw = crate::Hraw::new(TEST_FILE_I32)?;
+  let mut hraw = crate::Hraw::new(&test_file_i32)?;
   let value = hraw.header();
   let width = value["width"].as_u64().unwrap_or_default();
   let height = value["height"].as_u64().unwrap_or_default();
   let list = value["data"].as_array().unwrap();
   println!("value : {:?}", value);
   println!("{} {} {:?} {:?}", width, height, list, list[1].as_str());
-  
+  assert_eq!((width, height), (WIDTH as u64, HEIGHT as u64));
+  assert_eq!(list[1].as_str(), Some(DATA[1]));
+
   /* dataの存在チェック */
-  println!("{:?}", hraw.contain_poi("data.raw"));
-  println!("{:?}", hraw.contain_poi("subdir/1.raw"));
-  println!("{:?}", hraw.contain_poi(0));
-  println!("{:?}", hraw.contain_poi(1));
-  println!("{:?}", hraw.contain_poi(2));
-  println!("{:?}", hraw.contain_poi("hoge.raw"));
+  assert_eq!(hraw.contain_poi("data.raw")?, "data.raw");
+  assert_eq!(hraw.contain_poi("subdir/1.raw")?, "subdir/1.raw");
+  assert_eq!(hraw.contain_poi(0)?, DATA[0]);
+  assert_eq!(hraw.contain_poi(1)?, DATA[1]);
+  assert_eq!(hraw.contain_poi(2)?, DATA[2]);
+  assert!(hraw.contain_poi(3).is_err());
+  assert!(hraw.contain_poi("hoge.raw").is_err());
 
   /* headerをstrcutで取得 */
-  let mut hraw = crate::Hraw::new(TEST_FILE_I32)?;
+  let mut hraw = crate::Hraw::new(&test_file_i32)?;
   let value = hraw.header().to_struct();
   println!("value : {:?}", value);
 
   /* headerでのerr */
-  let mut hraw = crate::Hraw::new(ERR_FILE)?;
+  let mut hraw = crate::Hraw::new(&fixture.err())?;
   hraw.info()?;
   let value = hraw.header();
   println!("value : {:?}", value);
+  assert_eq!(value, serde_json::json!({}));
 
   Ok(())
 }
@@ -47,14 +50,17 @@ fn hraw_header() -> anyhow::Result<()> {
 fn hraw_data() -> anyhow::Result<()> {
   use crate::*;
   use crate::buffer::*;
+  let fixture = Fixture::new();
+  let test_file_i32 = fixture.i32();
 
-  let mut hraw = crate::Hraw::new(TEST_FILE_I32).unwrap();
+  let mut hraw = crate::Hraw::new(&test_file_i32).unwrap();
 
   /* 一括読み込み (u8) */
-  let vec = hraw.to_vec("data.raw");
-  let vec = hraw.to_vec("data.raw");
-  let vec = hraw.to_vec_poi(0);
-  
+  let vec = hraw.to_vec("data.raw")?;
+  assert_eq!(vec.len(), 16 + WIDTH * HEIGHT * 4);
+  let vec = hraw.to_vec_poi(1)?;
+  assert_eq!(vec.len(), 16 + WIDTH * HEIGHT * 4);
+
   /* stream 所有権は奪ったままなので使いにくい */
   let mut stream = hraw.to_stream("data.raw")?;
   let mut buf : [u8; 4] = unsafe { std::mem::MaybeUninit::zeroed().assume_init() };
@@ -64,30 +70,43 @@ fn hraw_data() -> anyhow::Result<()> {
   }
 
   /* iterator (BitField指定) */
-  let mut hraw = crate::Hraw::new(TEST_FILE_I32).unwrap();
-  for (index, dst) in hraw.enumerate_path::<le_i32>("data.raw").map(|n| (n.0, n.1 as i32)) {
-    if index < 3 || 640 * 480 - 3 < index { println!("{} : {} {} {}",dst ,index, index/640, index%640 )}
+  let mut hraw = crate::Hraw::new(&test_file_i32).unwrap();
+  let (width, height, _) = hraw.header().to_size();
+  for (x, y, dst) in hraw.enumerate_path::<le_i32>("data.raw").xy() {
+    if (y == 0 && x < 3) || (y == height - 1 && width - 3 <= x) { println!("{} : {} {}", dst, x, y) }
+    assert_eq!(dst, pattern(x, y, 0));
   }
 
   /* iteratorのwrapper (BitField自動) */
-  let mut vec_i32 = vec![0i32; 640*480];
-  vec_i32.as_mut_slice().from_hraw(TEST_FILE_I32, "data.raw");
+  let mut vec_i32 = vec![0i32; width * height];
+  vec_i32.as_mut_slice().from_hraw(&test_file_i32, "subdir/2.raw")?;
+  assert_eq!(vec_i32, frame(2));
+
+  let mut vec_i32 = vec![0i32; width * height];
+  vec_i32.as_mut_slice().from_hraw(&fixture.u16(), 1)?;
+  assert_eq!(vec_i32, frame(1).into_iter().map(|n| n.clamp(0, u16::MAX as i32)).collect::<Vec<_>>());
 
   Ok(())
 }
 
+#[cfg(all(feature = "lua", feature = "py"))]
 #[test]
 fn hraw_data_unknown() -> anyhow::Result<()> {
   use crate::buffer::*;
+  let fixture = Fixture::new();
 
-  let mut vec_i32_1 = vec![0i32; 640*480];
-  let mut vec_i32_2 = vec![0i32; 640*480];
+  let mut vec_i32_1 = vec![0i32; WIDTH * HEIGHT];
+  let mut vec_i32_2 = vec![0i32; WIDTH * HEIGHT];
+  let mut vec_i32_3 = vec![0i32; WIDTH * HEIGHT];
 
-  vec_i32_1.as_mut_slice().from_hraw(TEST_FILE_I32, "data.raw");
-  vec_i32_2.as_mut_slice().from_hraw(TEST_FILE_UNKNOWN, "data.raw");
+  vec_i32_1.as_mut_slice().from_hraw(&fixture.i32(), "data.raw")?;
+  vec_i32_2.as_mut_slice().from_hraw(&fixture.unknown("lua"), "data.raw")?;
+  vec_i32_3.as_mut_slice().from_hraw_with(&fixture.unknown("py"), "data.raw", &crate::ScriptPolicy::trusted())?;
 
   println!("{:?}", &vec_i32_1[0..3]);
   println!("{:?}", &vec_i32_2[0..3]);
+  assert_eq!(vec_i32_1, vec_i32_2);
+  assert_eq!(vec_i32_1, vec_i32_3);
 
   Ok(())
-}
\ No newline at end of file
+}
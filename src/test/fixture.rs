@@ -0,0 +1,175 @@
+/*
+  テスト用のarchiveをtempdirに生成する
+  Fixtureをdropするとファイルも消える
+*/
+use crate::rawnumber::*;
+use crate::writer::HrawWriter;
+use std::io::Write;
+
+pub const WIDTH : usize = 64;
+pub const HEIGHT : usize = 48;
+pub const DATA : [&str; 3] = ["data.raw", "subdir/1.raw", "subdir/2.raw"];
+
+pub const DECODER_LUA : &str = r#"
+function(index)
+  local i = index * 4 + 1
+  local buf = buffer.create(4)
+  for n = 0, 3 do
+    buffer.writeu8(buf, n, src[i + n])
+  end
+  return buffer.readi32(buf, 0)
+end
+"#;
+
+pub const DECODER_LUA_ROW : &str = r#"
+function decode_row(y, out)
+  local width = buffer.len(out) // 8
+  for x = 0, width - 1 do
+    buffer.writef64(out, x * 8, buffer.readi32(src, (x + y * width) * 4))
+  end
+end
+"#;
+
+pub const DECODER_LUA_BLOCK : &str = r#"
+function decode_block(start, count, out)
+  for n = 0, count - 1 do
+    buffer.writef64(out, n * 8, buffer.readi32(src, (start + n) * 4))
+  end
+end
+"#;
+
+pub const DECODER_PY : &str = r#"
+def function(index):
+  i = index * 4
+  return int.from_bytes(bytearray(src[i:i+4]), 'little', signed=True)
+"#;
+
+/// numpy importable, tests of the numpy entries return early without it
+#[cfg(feature = "py")]
+pub fn numpy() -> bool {
+  pyo3::Python::with_gil(|py| py.import_bound("numpy").is_ok())
+}
+
+/// pixel value of frame `n`, negative values included
+pub fn pattern(x:usize, y:usize, n:usize) -> i32 {
+  x as i32 + y as i32 * 1000 - 5000 + n as i32 * 100_000
+}
+
+pub fn frame(n:usize) -> Vec<i32> {
+  (0..WIDTH * HEIGHT).map(|i| pattern(i % WIDTH, i / WIDTH, n)).collect()
+}
+
+/// seeded noise of the synthetic sensor frames, the same sequence on every run
+pub struct Rng(crate::decoder::XorShift);
+
+impl Rng {
+  pub fn new(seed:u64) -> Rng { Rng(crate::decoder::XorShift(seed)) }
+  /// [0, 1)
+  pub fn uniform(&mut self) -> f64 {
+    (self.0.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+  }
+  /// standard normal, Box-Muller
+  pub fn normal(&mut self) -> f64 {
+    let (a, b) = (self.uniform().max(1e-12), self.uniform());
+    (-2.0 * a.ln()).sqrt() * (std::f64::consts::TAU * b).cos()
+  }
+}
+
+/// minimal header of the fixture frames
+pub fn header() -> serde_json::Value {
+  serde_json::json!({ "width" : WIDTH, "height" : HEIGHT })
+}
+
+pub struct Fixture {
+  dir: tempfile::TempDir,
+}
+
+impl Fixture {
+
+  pub fn new() -> Fixture {
+    Fixture { dir: tempfile::tempdir().unwrap() }
+  }
+
+  pub fn path(&self, name:&str) -> String {
+    self.dir.path().join(name).to_string_lossy().into_owned()
+  }
+
+  /// `header` without `data`, each frame is written in order
+  pub fn archive<T:RawNumber>(&self, name:&str, header:serde_json::Value, frames:&[(&str, Vec<T::Item>)]) -> String {
+    let path = self.path(name);
+    let mut writer = HrawWriter::new(&path, header).unwrap();
+    frames.iter().for_each(|(entry, src)| writer.write_data::<T>(entry, src).unwrap());
+    writer.finish().unwrap();
+    path
+  }
+
+  /// 64x48 le_i32, offset 16, 3 frames
+  pub fn i32(&self) -> String {
+    let header = serde_json::json!({ "width" : WIDTH, "height" : HEIGHT, "offset" : 16, "bitfield" : "le_i32" });
+    let frames = DATA.iter().enumerate().map(|(n, entry)| (*entry, frame(n))).collect::<Vec<_>>();
+    self.archive::<le_i32>("ship_i32.zip", header, &frames)
+  }
+
+  /// 64x48 be_u16, same pattern clamped to u16
+  pub fn u16(&self) -> String {
+    let header = serde_json::json!({ "width" : WIDTH, "height" : HEIGHT, "bitfield" : "be_u16" });
+    let frames = DATA.iter().enumerate()
+      .map(|(n, entry)| (*entry, frame(n).into_iter().map(u16::clamp_from).collect()))
+      .collect::<Vec<_>>();
+    self.archive::<be_u16>("ship_u16.zip", header, &frames)
+  }
+
+  /// `bitfield : unknown`, le_i32 body decoded by a `lang` script
+  pub fn unknown(&self, lang:&str) -> String {
+    let code = match lang { "py" => DECODER_PY, _ => DECODER_LUA };
+    self.decoder(&format!("ship_i32_{lang}.zip"), lang, code)
+  }
+
+  pub fn decoder(&self, name:&str, lang:&str, code:&str) -> String {
+    let header = serde_json::json!({
+      "width" : WIDTH, "height" : HEIGHT, "bitfield" : "unknown",
+      "decoder" : { "lang" : lang, "code" : code }
+    });
+    let frames = DATA.iter().enumerate().map(|(n, entry)| (*entry, frame(n))).collect::<Vec<_>>();
+    self.archive::<le_i32>(name, header, &frames)
+  }
+
+  /// `decoder` section as given, `files` are extra entries such as scripts and modules
+  pub fn decoder_files<B:AsRef<[u8]>>(&self, name:&str, decoder:serde_json::Value, files:&[(&str, B)]) -> String {
+    let header = serde_json::json!({ "width" : WIDTH, "height" : HEIGHT, "bitfield" : "unknown", "decoder" : decoder });
+    let path = self.path(name);
+    let mut writer = HrawWriter::new(&path, header).unwrap();
+    files.iter().for_each(|(entry, code)| writer.write_raw(entry, code.as_ref()).unwrap());
+    DATA.iter().enumerate().for_each(|(n, entry)| writer.write_data::<le_i32>(entry, &frame(n)).unwrap());
+    writer.finish().unwrap();
+    path
+  }
+
+  /// le_i32 frames of `header()` with `postprocess` as given
+  pub fn postprocess(&self, name:&str, postprocess:serde_json::Value) -> String {
+    let mut header = header();
+    header["postprocess"] = postprocess;
+    let frames = DATA.iter().enumerate().map(|(n, entry)| (*entry, frame(n))).collect::<Vec<_>>();
+    self.archive::<le_i32>(name, header, &frames)
+  }
+
+  /// broken yaml, missing data
+  pub fn err(&self) -> String {
+    let path = self.path("err.zip");
+    let mut zip = zip::ZipWriter::new(std::fs::File::create(&path).unwrap());
+    zip.start_file("header.yaml", zip::write::FileOptions::default()).unwrap();
+    zip.write_all(b"width : [64\nheight : \n").unwrap();
+    zip.add_directory("subdir", zip::write::FileOptions::default()).unwrap();
+    zip.finish().unwrap();
+    path
+  }
+
+  /// 64x48 rgba png
+  pub fn png(&self) -> String {
+    let path = self.path("test.png");
+    let img = image::RgbaImage::from_fn(WIDTH as u32, HEIGHT as u32, |x, y| image::Rgba([x as u8, y as u8, (x + y) as u8, 255]));
+    img.save(&path).unwrap();
+    path
+  }
+
+}
@@ -0,0 +1,102 @@
+use super::fixture::*;
+use crate::histogram::*;
+use crate::levels::*;
+use crate::rawnumber::*;
+
+#[test]
+fn full_range() -> anyhow::Result<()> {
+  let fixture = Fixture::new();
+  let policy = ScriptPolicy::default();
+  let mut hraw = crate::Hraw::new(&fixture.u16())?;
+  let histograms = hraw.histogram(0, &HistogramOptions::default(), &policy)?;
+  assert_eq!(histograms.len(), 1);
+  let n = &histograms[0];
+
+  /* be_u16 : 0 ..= 65535, bin 1 */
+  assert_eq!((n.range, n.bin_width, n.counts.len()), ([0.0, 65535.0], 1.0, 65536));
+  assert_eq!(n.total, (WIDTH * HEIGHT) as u64);
+  /* 負の値は0に飽和 : y < 5 の全て + (0, 5) */
+  assert_eq!((n.clipped_low, n.clipped_high, n.clipped()), (5 * WIDTH as u64 + 1, 0, 5 * WIDTH as u64 + 1));
+
+  let mut values = frame(0).into_iter().map(|n| u16::clamp_from(n) as f64).collect::<Vec<_>>();
+  values.sort_by(f64::total_cmp);
+  for p in [0.0, 1.0, 50.0, 99.0, 100.0] {
+    let index = ((p / 100.0 * values.len() as f64).ceil() as usize).max(1) - 1;
+    assert_eq!(n.percentile(p), values[index], "{p}");
+  }
+  assert_eq!(n.percentiles.iter().map(|n| n.0).collect::<Vec<_>>(), HistogramOptions::default().percentiles);
+  assert_eq!(n.mean, values.iter().sum::<f64>() / values.len() as f64);
+
+  /* bin幅 1000 : 65535 / 1000 + 1 */
+  let options = HistogramOptions { bin_width: Some(1000.0), ..Default::default() };
+  let n = &hraw.histogram(0, &options, &policy)?[0];
+  assert_eq!(n.counts.len(), 66);
+  assert_eq!(n.counts[1], WIDTH as u64); // y = 6 : 1000 ..= 1063
+  assert_eq!(n.percentile(50.0) % 1000.0, 0.0);
+
+  /* le_i32 bin 1 は bin数が多すぎる */
+  let mut hraw = crate::Hraw::new(&fixture.i32())?;
+  assert!(hraw.histogram(0, &HistogramOptions { bin_width: Some(1.0), ..Default::default() }, &policy).is_err());
+  assert_eq!(hraw.histogram(0, &HistogramOptions::default(), &policy)?[0].bin_width, 65536.0);
+  for bin_width in [1e-10, f64::NAN, f64::INFINITY] {
+    assert!(hraw.histogram(0, &HistogramOptions { bin_width: Some(bin_width), ..Default::default() }, &policy).is_err());
+  }
+
+  /* le_i32 + white_level : 範囲はbitfield全体, white_levelはclipped_highで数える */
+  let values = (0..WIDTH * HEIGHT).map(|i| (i * 4095 / (WIDTH * HEIGHT - 1)) as i32).collect::<Vec<_>>();
+  let mut header = header();
+  header["white_level"] = serde_json::json!(4095);
+  let path = fixture.archive::<le_i32>("white.zip", header, &[("data.raw", values)]);
+  let n = &crate::Hraw::new(&path)?.histogram(0, &HistogramOptions::default(), &policy)?[0];
+  assert_eq!((n.range, n.bin_width), ([i32::MIN as f64, i32::MAX as f64], 65536.0));
+  assert_eq!((n.clipped_low, n.clipped_high, n.nan), (0, 1, 0));
+  Ok(())
+}
+
+#[test]
+fn cfa_channels() -> anyhow::Result<()> {
+  let fixture = Fixture::new();
+  let policy = ScriptPolicy::default();
+  let values = [100u16, 200, 300, 400];
+  let bayer = (0..WIDTH * HEIGHT).map(|i| values[i % WIDTH % 2 + (i / WIDTH % 2) * 2]).collect::<Vec<_>>();
+  let mut header = header();
+  header["bitfield"] = serde_json::json!("le_u16");
+  header["white_level"] = serde_json::json!(300);
+  let path = fixture.archive::<le_u16>("bayer.zip", header, &[("data.raw", bayer.clone())]);
+
+  let options = HistogramOptions { cfa: true, ..Default::default() };
+  let histograms = crate::Hraw::new(&path)?.histogram(0, &options, &policy)?;
+  assert_eq!(histograms.len(), 4);
+  for (c, n) in histograms.iter().enumerate() {
+    assert_eq!(n.channel, c);
+    assert_eq!(n.total, (WIDTH * HEIGHT / 4) as u64);
+    assert_eq!(n.counts[values[c] as usize], n.total);
+    assert_eq!(n.percentile(50.0), values[c] as f64);
+    /* white_level 300 以上は飽和 */
+    assert_eq!(n.clipped_high, if c >= 2 { n.total } else { 0 });
+  }
+  assert!(image::load_from_memory(&histogram_png(&histograms, 320, 240, true)).is_ok());
+
+  /* 自動ストレッチ : channelごとの黒, 白は最大 */
+  let levels = auto_levels(&histograms, 0.1, 99.9)?;
+  assert_eq!(levels, Levels { black: BlackLevel::Cfa([100.0, 200.0, 300.0, 400.0]), white: 401.0 });
+  let bayer = bayer.iter().map(|n| *n as i32).collect::<Vec<_>>();
+  let png = crate::processing::slice_to_png_auto(&bayer, WIDTH, HEIGHT, 0.1, 99.9, None, 5)?;
+  assert!(image::load_from_memory(&png).is_ok());
+
+  /* float : データの最小 / 最大, 1024 bins */
+  let src = (0..1000).map(|n| n as f64 / 1000.0).collect::<Vec<_>>();
+  let n = &histogram(&src, 100, 10, Some(BitField::le_f32), None, &HistogramOptions::default())?[0];
+  assert_eq!((n.range, n.counts.len()), ([0.0, 0.999], 1025));
+  assert_eq!((n.clipped_low, n.clipped_high), (1, 1));
+
+  /* NaNは別に数え, binには入れない */
+  let src = (0..1000).map(|n| if n % 10 == 0 { f32::NAN } else { n as f32 / 1000.0 }).collect::<Vec<_>>();
+  let n = &histogram(&src, 100, 10, Some(BitField::le_f32), None, &HistogramOptions::default())?[0];
+  assert_eq!((n.range, n.nan, n.total), ([0.001f32 as f64, 0.999f32 as f64], 100, 900));
+  assert_eq!((n.clipped_low, n.counts[0]), (1, 1));
+  let preview = crate::processing::slice_to_png_auto(&(0..256).collect::<Vec<i32>>(), 16, 16, 0.0, 100.0, None, 0)?;
+  let preview = image::load_from_memory(&preview)?.to_luma8();
+  assert_eq!((preview.get_pixel(0, 0)[0], preview.get_pixel(15, 15)[0]), (0, 255));
+  Ok(())
+}
@@ -0,0 +1,93 @@
+use super::fixture::*;
+use crate::levels::*;
+use crate::regions::*;
+use crate::rawnumber::*;
+
+/* left 4列とtop 2行がOB, OBの値は 60 + y + CFA channel, activeは 1000 + x */
+fn ob_frame() -> Vec<u16> {
+  (0..WIDTH * HEIGHT).map(|i| {
+    let (x, y) = (i % WIDTH, i / WIDTH);
+    match x < 4 || y < 2 {
+      true => (60 + y + x % 2 + (y % 2) * 2) as u16,
+      false => (1000 + x) as u16
+    }
+  }).collect()
+}
+
+fn ob_archive(fixture:&Fixture, name:&str, levels:serde_json::Value) -> String {
+  let mut header = header();
+  header["bitfield"] = serde_json::json!("le_u16");
+  header["optical_black"] = serde_json::json!({ "left" : 4, "top" : 2 });
+  levels.as_object().unwrap().iter().for_each(|(k, v)| header[k] = v.clone());
+  fixture.archive::<le_u16>(name, header, &[("data.raw", ob_frame())])
+}
+
+#[test]
+fn header_levels() -> anyhow::Result<()> {
+  let fixture = Fixture::new();
+  let policy = ScriptPolicy::default();
+
+  let path = ob_archive(&fixture, "none.zip", serde_json::json!({}));
+  assert_eq!(crate::Hraw::new(&path)?.levels(0, &policy)?, Levels { black: BlackLevel::Global(0.0), white: 65535.0 });
+  let path = ob_archive(&fixture, "global.zip", serde_json::json!({ "black_level" : 64, "white_level" : 4095 }));
+  assert_eq!(crate::Hraw::new(&path)?.levels(0, &policy)?, Levels { black: BlackLevel::Global(64.0), white: 4095.0 });
+  let path = ob_archive(&fixture, "cfa.zip", serde_json::json!({ "black_level" : [64, 65, 66, 67] }));
+  let levels = crate::Hraw::new(&path)?.levels(0, &policy)?;
+  assert_eq!((levels.black.at(0, 0), levels.black.at(1, 0), levels.black.at(2, 1), levels.black.at(3, 3)), (64.0, 65.0, 66.0, 67.0));
+
+  /* unknownはwhite_levelが必要 */
+  let path = fixture.unknown("lua");
+  assert!(crate::Hraw::new(&path)?.levels(0, &policy).is_err());
+  Ok(())
+}
+
+#[test]
+fn estimate_from_optical_black() -> anyhow::Result<()> {
+  let fixture = Fixture::new();
+  let policy = ScriptPolicy::default();
+
+  /* rows : left 4列の平均 */
+  let path = ob_archive(&fixture, "rows.zip", serde_json::json!({ "black_level" : { "ob" : "rows" } }));
+  let levels = crate::Hraw::new(&path)?.levels(0, &policy)?;
+  let BlackLevel::Rows(rows) = &levels.black else { panic!("{:?}", levels.black) };
+  assert_eq!(rows.len(), HEIGHT);
+  assert!(rows.iter().enumerate().skip(2).all(|(y, n)| *n == 60.0 + y as f64 + 0.5 + (y % 2 * 2) as f64));
+
+  /* columns : top 2行の平均 */
+  let mut hraw = crate::Hraw::new(&path)?;
+  let BlackLevel::Columns(columns) = hraw.estimate_black_level(0, ObMode::Columns, &policy)? else { panic!() };
+  assert_eq!(columns[10], 60.5 + 1.0);
+
+  /* cfa, global */
+  assert_eq!(hraw.estimate_black_level(0, ObMode::Cfa, &policy)?.at(1, 1), {
+    let src = ob_frame();
+    let ob = (0..WIDTH * HEIGHT).filter(|i| (i % WIDTH < 4 || i / WIDTH < 2) && i % 2 == 1 && (i / WIDTH) % 2 == 1).map(|i| src[i] as f64).collect::<Vec<_>>();
+    ob.iter().sum::<f64>() / ob.len() as f64
+  });
+  assert!(matches!(hraw.estimate_black_level(0, ObMode::Global, &policy)?, BlackLevel::Global(n) if n > 60.0 && n < 120.0));
+
+  /* 宣言なし, 全面OB, 該当pixelなし */
+  let mut hraw = crate::Hraw::new(&fixture.u16())?;
+  assert!(hraw.estimate_black_level(0, ObMode::Global, &policy).is_err());
+  let src = ob_frame();
+  assert!(estimate_black_level(&src, WIDTH, HEIGHT, &[Rect { x: 0, y: 0, width: WIDTH + 1, height: 1 }], ObMode::Global).is_err());
+  assert!(estimate_black_level(&src, WIDTH, HEIGHT, &ObMargins { top: 2, ..Default::default() }.rects(WIDTH, HEIGHT), ObMode::Rows).is_err());
+  Ok(())
+}
+
+#[test]
+fn normalize_to_png() -> anyhow::Result<()> {
+  let src = ob_frame().into_iter().map(|n| n as i32).collect::<Vec<_>>();
+  let black = estimate_black_level(&src, WIDTH, HEIGHT, &ObMargins { left: 4, ..Default::default() }.rects(WIDTH, HEIGHT), ObMode::Rows)?;
+  let levels = Levels { black, white: 1063.0 + 64.0 };
+
+  let normalized = levels.apply(&src, WIDTH, HEIGHT);
+  assert!(normalized.iter().enumerate().filter(|(i, _)| i % WIDTH < 4).all(|(_, n)| n.abs() < 0.05));
+  assert!(levels.subtract(&src, WIDTH, HEIGHT)[WIDTH * 10 + 4] > 900.0);
+
+  let png = crate::processing::slice_to_png_levels(&src, WIDTH, HEIGHT, &levels, None, 0);
+  let img = image::load_from_memory(&png)?.into_rgb8();
+  assert!(img.get_pixel(0, 10)[0] < 5);
+  assert!(img.get_pixel(WIDTH as u32 - 1, 10)[0] > 230);
+  Ok(())
+}
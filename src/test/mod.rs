@@ -1,6 +1,29 @@
 #![allow(dead_code, unused_variables)]
 #[cfg(test)]
-
+pub mod fixture;
+#[cfg(test)]
 pub mod snippet;
+#[cfg(test)]
 pub mod example;
+#[cfg(test)]
 pub mod scripting;
+#[cfg(test)]
+pub mod access;
+#[cfg(test)]
+pub mod bitfield;
+#[cfg(test)]
+pub mod levels;
+#[cfg(test)]
+pub mod regions;
+#[cfg(test)]
+pub mod calibration;
+#[cfg(test)]
+pub mod defects;
+#[cfg(test)]
+pub mod convert;
+#[cfg(test)]
+pub mod characterization;
+#[cfg(test)]
+pub mod noise;
+#[cfg(test)]
+pub mod histogram;
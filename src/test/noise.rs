@@ -0,0 +1,96 @@
+use super::fixture::*;
+use crate::noise::*;
+use crate::rawnumber::*;
+
+/*
+  CFA channel c : 1000 + 100 * c
+  + 6 on every 8th column (固定パターン, channel 0 / 2 のみ)
+  + row banding sd 2 (frameごとに変わる) + pixel temporal noise sd 3
+*/
+fn banded(noise:&mut Rng) -> Vec<i32> {
+  let rows = (0..HEIGHT).map(|_| 2.0 * noise.normal()).collect::<Vec<_>>();
+  (0..WIDTH * HEIGHT).map(|i| {
+    let (x, y) = (i % WIDTH, i / WIDTH);
+    let column = if x % 8 == 0 { 6.0 } else { 0.0 };
+    (1000.0 + 100.0 * (x % 2 + (y % 2) * 2) as f64 + column + rows[y] + 3.0 * noise.normal()).round() as i32
+  }).collect()
+}
+
+fn archive(fixture:&Fixture, name:&str, header:serde_json::Value) -> String {
+  let mut noise = Rng::new(0x9E3779B97F4A7C15);
+  let names = (0..16).map(|n| format!("{n}.raw")).collect::<Vec<_>>();
+  let frames = names.iter().map(|n| (n.as_str(), banded(&mut noise))).collect::<Vec<_>>();
+  fixture.archive::<le_i32>(name, header, &frames)
+}
+
+#[test]
+fn noise_decomposition() -> anyhow::Result<()> {
+  let fixture = Fixture::new();
+  let path = archive(&fixture, "banded.zip", header());
+  let subpaths = (0..16).collect::<Vec<usize>>();
+  let analysis = crate::Hraw::new(&path)?.noise_analysis(&subpaths, true, &ScriptPolicy::default())?;
+  assert_eq!(analysis.frames, 16);
+  assert_eq!(analysis.channels.len(), 4);
+
+  let near = |value:f64, expected:f64, tolerance:f64| (value - expected).abs() <= tolerance;
+  for n in analysis.channels.iter() {
+    let pattern = n.channel % 2 == 0;
+    assert_eq!((n.width, n.height), (WIDTH / 2, HEIGHT / 2));
+    assert_eq!((n.row_profile.len(), n.column_profile.len()), (HEIGHT / 2, WIDTH / 2));
+    assert!(near(n.mean, 1000.0 + 100.0 * n.channel as f64 + if pattern { 1.5 } else { 0.0 }, 0.5), "{n:?}");
+    assert!(near(n.temporal, (9f64 + 4.0).sqrt(), 0.3), "{n:?}");
+    assert!(near(n.row_temporal, 2.0, 0.4), "{n:?}");
+    assert!(n.column_temporal < 0.8 && n.row_fpn < 0.8, "{n:?}");
+    /* 32列中8列が+6 : sd = 6 * sqrt(1/4 * 3/4 * 32/31) */
+    assert!(near(n.column_fpn, if pattern { 2.64 } else { 0.0 }, 0.4), "{n:?}");
+    assert!(near(n.total, (n.temporal.powi(2) + n.spatial.powi(2)).sqrt(), 0.3), "{n:?}");
+
+    /* 周期4 pixel (plane) -> 32 / 4 = 8 と高調波 16 */
+    let median = |src:&[f64]| { let mut n = src.to_vec(); n.sort_by(f64::total_cmp); n[n.len() / 2] };
+    assert_eq!(n.horizontal_spectrum.len(), WIDTH / 4 + 1);
+    assert_eq!(n.vertical_spectrum.len(), HEIGHT / 4 + 1);
+    assert_eq!(n.horizontal_spectrum[8] > 20.0 * median(&n.horizontal_spectrum), pattern, "{:?}", n.horizontal_spectrum);
+
+    let spectrum = n.spectrum_2d();
+    assert_eq!(spectrum.len(), n.width * n.height);
+    assert_eq!(spectrum[(16 + 8) + 12 * n.width] > 20.0 * median(&spectrum), pattern);
+    assert!(image::load_from_memory(&spectrum_png(&spectrum, n.width, n.height)).is_ok());
+  }
+
+  /* mono */
+  let analysis = crate::Hraw::new(&path)?.noise_analysis(&subpaths[0..4], false, &ScriptPolicy::default())?;
+  assert_eq!(analysis.channels.len(), 1);
+  assert_eq!(analysis.channels[0].row_profile.len(), HEIGHT);
+
+  /* 奇数のactive_areaはCFAを保つよう偶数にそろえる */
+  let mut header = header();
+  header["active_area"] = serde_json::json!({ "x" : 1, "y" : 1, "width" : 63, "height" : 47 });
+  let path = archive(&fixture, "offset.zip", header);
+  let analysis = crate::Hraw::new(&path)?.noise_analysis(&subpaths[0..4], true, &ScriptPolicy::default())?;
+  assert!(near(analysis.channels[3].mean, 1300.0, 1.0));
+  assert_eq!((analysis.channels[0].width, analysis.channels[1].width), (31, 31));
+
+  assert!(crate::Hraw::new(&path)?.noise_analysis(&subpaths[0..1], true, &ScriptPolicy::default()).is_err());
+  Ok(())
+}
+
+#[test]
+fn spectra() {
+  /* Parseval : 平均を除いたpowerの合計 = 分散 * (n - 1) / n */
+  let src = (0..64).map(|n| (n as f64 * 0.5).sin() * 3.0 + n as f64 % 3.0).collect::<Vec<_>>();
+  let spectrum = power_spectrum(&src);
+  assert_eq!(spectrum.len(), 33);
+  let full = spectrum[0] + spectrum[32] + spectrum[1..32].iter().sum::<f64>() * 2.0;
+  let mean = src.iter().sum::<f64>() / 64.0;
+  let variance = src.iter().map(|n| (n - mean).powi(2)).sum::<f64>() / 64.0;
+  assert!((full / 64.0 - variance).abs() < 1e-9);
+  assert!(spectrum[0].abs() < 1e-9);
+  assert!(power_spectrum(&[]).is_empty());
+  assert!(power_spectrum_2d(&[], 0, 4).is_empty());
+  assert!(power_spectrum_2d(&[], 4, 0).is_empty());
+
+  let (w, h, plane) = plane(&(0..12).map(|n| n as f64).collect::<Vec<_>>(), 4, 3, 1, true);
+  assert_eq!((w, h, plane), (2, 2, vec![1.0, 3.0, 9.0, 11.0]));
+  assert_eq!(row_means(&[1.0, 3.0, 5.0, 7.0], 2, 2), vec![2.0, 6.0]);
+  assert_eq!(column_means(&[1.0, 3.0, 5.0, 7.0], 2, 2), vec![3.0, 5.0]);
+}
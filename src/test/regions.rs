@@ -0,0 +1,65 @@
+use super::fixture::*;
+use crate::regions::*;
+use crate::rawnumber::*;
+
+/* 8x6, le_u16, value = x + y * 10, 1行目と最終行がembedded, 左2列がOB */
+fn region_archive(fixture:&Fixture, name:&str, regions:serde_json::Value) -> String {
+  let mut header = serde_json::json!({ "width" : 8, "height" : 6, "offset" : 4, "bitfield" : "le_u16" });
+  regions.as_object().unwrap().iter().for_each(|(k, v)| header[k] = v.clone());
+  let mut writer = crate::writer::HrawWriter::new(&fixture.path(name), header).unwrap();
+  let body = [0xEEu8; 4].into_iter().chain((0..48u16).flat_map(|i| (i % 8 + i / 8 * 10).to_le_bytes())).collect::<Vec<_>>();
+  writer.write_raw("data.raw", &body).unwrap();
+  writer.finish().unwrap();
+  fixture.path(name)
+}
+
+#[test]
+fn active_and_optical_black() -> anyhow::Result<()> {
+  let fixture = Fixture::new();
+  let path = region_archive(&fixture, "regions.zip", serde_json::json!({
+    "active_area" : { "x" : 2, "y" : 1, "width" : 6, "height" : 4 },
+    "optical_black" : [{ "x" : 0, "y" : 1, "width" : 2, "height" : 4 }],
+    "embedded_lines" : { "top" : 1, "bottom" : 1 }
+  }));
+  let mut hraw = crate::Hraw::new(&path)?;
+  let active = hraw.read_active::<le_u16, _>("data.raw")?;
+  assert_eq!(active.len(), 24);
+  assert_eq!((active[0], active[5], active[23]), (12, 17, 47));
+  let ob = hraw.read_optical_black::<le_u16, _>("data.raw")?;
+  assert_eq!(ob, vec![(Rect { x: 0, y: 1, width: 2, height: 4 }, vec![10, 11, 20, 21, 30, 31, 40, 41])]);
+  let embedded = hraw.read_embedded("data.raw")?;
+  assert_eq!(embedded.len(), 2 * 8 * 2);
+  assert_eq!((embedded[2], embedded[16], embedded[30]), (1, 50, 57));
+
+  /* crop of a decoded frame */
+  let decoded = (0..48).map(|i| i % 8 + i / 8 * 10).collect::<Vec<u16>>();
+  assert_eq!(crop(&decoded, 8, &hraw.active_area()?), active);
+
+  /* default : embedded_linesを除いた全体, margins */
+  let path = region_archive(&fixture, "default.zip", serde_json::json!({ "embedded_lines" : 2, "optical_black" : { "left" : 1, "top" : 1 } }));
+  let mut hraw = crate::Hraw::new(&path)?;
+  assert_eq!(hraw.active_area()?, Rect { x: 0, y: 2, width: 8, height: 4 });
+  assert_eq!(hraw.optical_black()?, vec![Rect { x: 0, y: 0, width: 1, height: 6 }, Rect { x: 1, y: 0, width: 7, height: 1 }]);
+  assert_eq!(hraw.read_embedded(0)?.len(), 2 * 8 * 2);
+  let path = region_archive(&fixture, "none.zip", serde_json::json!({}));
+  let mut hraw = crate::Hraw::new(&path)?;
+  assert!(hraw.optical_black()?.is_empty() && hraw.read_embedded(0)?.is_empty());
+
+  /* 範囲外 */
+  let path = region_archive(&fixture, "out.zip", serde_json::json!({
+    "active_area" : { "x" : 4, "y" : 0, "width" : 6, "height" : 1 },
+    "optical_black" : [{ "x" : 0, "y" : 5, "width" : 1, "height" : 2 }]
+  }));
+  let mut hraw = crate::Hraw::new(&path)?;
+  assert!(hraw.read_active::<le_u16, _>(0).is_err());
+  assert!(hraw.read_optical_black::<le_u16, _>(0).is_err());
+
+  /* unknown : lineは (len - offset) / height bytes */
+  let mut header = header();
+  header["bitfield"] = serde_json::json!("unknown");
+  header["embedded_lines"] = serde_json::json!(1);
+  let path = fixture.archive::<le_i32>("unknown.zip", header, &[("data.raw", frame(0))]);
+  let embedded = crate::Hraw::new(&path)?.read_embedded(0)?;
+  assert_eq!(embedded, frame(0)[0..WIDTH].iter().flat_map(|n| n.to_le_bytes()).collect::<Vec<_>>());
+  Ok(())
+}
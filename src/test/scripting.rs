@@ -1,7 +1,9 @@
 // pyo3 : pythonのモジュールが必要なので可搬性はない
 // mmfとVirtualAllocExどっちにしよう
 
+#[cfg(all(feature = "lua", feature = "py"))]
 #[test]
+#[ignore = "requires powershell and dotnet-script"]
 fn env() -> anyhow::Result<()> {
   use indoc::indoc;
 
@@ -15,7 +17,7 @@ fn env() -> anyhow::Result<()> {
       return 1
     end
   "};
-  crate::rawnumber::scripting::lua_call(info_lua);
+  crate::rawnumber::lua::lua_call(info_lua);
 
   let info_py = indoc! {"
     import sys
@@ -27,7 +29,7 @@ fn env() -> anyhow::Result<()> {
     def function():
       return 1
   "};
-  crate::rawnumber::scripting::py_call(info_py);
+  crate::rawnumber::python::py_call(info_py);
 
   let info_csx = indoc! {r#"
     using System;
@@ -53,6 +55,7 @@ fn env() -> anyhow::Result<()> {
 }
 
 
+#[cfg(all(feature = "lua", feature = "py"))]
 #[test]
 fn bitwise_operation() {
   use indoc::indoc;
@@ -156,6 +159,7 @@ fn bitwise_operation() {
 }
 
 
+#[cfg(all(feature = "lua", feature = "py"))]
 #[test]
 fn bitwise_operation_from_array() {
   use indoc::indoc;
@@ -186,16 +190,18 @@ fn bitwise_operation_from_array() {
   use crate::*;
   let src = vec![1,2,3,4,5,6,7,8,9,10,11,12];
   let mut dst = vec![0i32;src.len()];
-  dst.from_lua_script(lua, src.as_slice(), 3, 1);
+  dst.from_lua_script(lua, src.as_slice(), &serde_json::json!({ "width" : 3, "height" : 1 }), &ScriptPolicy::default()).unwrap();
   println!("{:?}", dst);
 
-  dst.from_py_script(py, src.as_slice(), 3, 1);
+  dst.from_py_script(py, src.as_slice(), &serde_json::json!({ "width" : 3, "height" : 1 }), &ScriptPolicy::trusted()).unwrap();
   println!("{:?}", dst);
 
 }
 
 
+#[cfg(feature = "py")]
 #[test]
+#[ignore = "requires seaborn, pandas and matplotlib"]
 fn plot() {
   use indoc::indoc;
   
@@ -225,6 +231,7 @@ fn plot() {
 /*
 mlua
 */
+#[cfg(feature = "lua")]
 #[test]
 fn mlua() -> anyhow::Result<()> {
   use mlua::prelude::*;
@@ -264,6 +271,7 @@ eval_bound
 run_bound
 from_code_bound
 */
+#[cfg(feature = "py")]
 #[test]
 fn pyo3() {
   use indoc::indoc;
@@ -325,20 +333,23 @@ fn pyo3() {
 
 }
 
+#[cfg(feature = "py")]
 #[pyo3::prelude::pyclass]
 struct LoggingStdout;
 
+#[cfg(feature = "py")]
 #[pyo3::prelude::pymethods]
 impl LoggingStdout {
   fn write(&self, src: &str) { 
     match src {
-      "\n" => println!(""),
+      "\n" => println!(),
       _ => print!("\x1b[43m{}\x1b[49m", src.replace('\n', "\r\n"))
     }
   }
 }
 
 #[test]
+#[ignore = "requires powershell and dotnet-script"]
 fn csx() {
   use indoc::indoc;
   use std::io::Write;
@@ -347,19 +358,18 @@ fn csx() {
   // let file_name = tempfile.path().to_string_lossy();
 
   let temp = tempfile::tempdir().unwrap();
-  let path = temp.path().to_string_lossy().into_owned();
-  let file_path = format!(r"{path}\temp.csx");
+  let file_path = temp.path().join("temp.csx").to_string_lossy().into_owned();
 
   let csx_code = indoc! {r#"
     Console.WriteLine("a");
   "#};
   {
     let mut buf_writer = std::io::BufWriter::new(std::fs::File::create(&file_path).unwrap());
-    buf_writer.write(csx_code.as_bytes()).unwrap();
+    buf_writer.write_all(csx_code.as_bytes()).unwrap();
     buf_writer.flush().unwrap();
   }
   let dst = std::process::Command::new("powershell")
-    .args(&["dotnet", "script", &file_path])
+    .args(["dotnet", "script", &file_path])
     .output().expect("failed to execute process");
   //   let dst = std::process::Command::new("powershell")
   //     .args(&["-ExecutionPolicy", "Bypass", "-File", file_path.as_str()])
@@ -372,65 +382,70 @@ fn csx() {
 /*
 scripting in hraw
 */
+#[cfg(all(feature = "lua", feature = "py"))]
 #[test]
 fn hraw_read_with_scripting() -> anyhow::Result<()> {
   use crate::*;
+  use super::fixture::*;
+  let fixture = Fixture::new();
   {
-    const TEST_FILE_I32 :&str = r".\src\test\pics\lua.zip";
-    let mut hraw = crate::Hraw::new(TEST_FILE_I32).unwrap();
-    let header  = hraw.header().to_struct();
+    let mut hraw = crate::Hraw::new(&fixture.unknown("lua")).unwrap();
+    let value = hraw.header();
+    let header  = value.to_struct();
     let vec = hraw.to_vec_poi(0)?;
     let mut dst = vec![0i32; header.width * header.height];
     let decoder = header.decoder.unwrap();
     println!("lang : {}", decoder.lang);
     println!("code : \r\n{}", decoder.code);
-    dst.from_lua_script(decoder.code.as_str(), vec.as_slice(), header.width, header.height);
-    println!("{:?}", &dst);
+    dst.from_lua_script(decoder.code.as_str(), vec.as_slice(), &value, &ScriptPolicy::default())?;
+    println!("{:?}", &dst[0..3]);
+    assert_eq!(dst, frame(0));
   }
   {
-    const TEST_FILE_I32 :&str = r".\src\test\pics\py.zip";
-    let mut hraw = crate::Hraw::new(TEST_FILE_I32).unwrap();
-    let header  = hraw.header().to_struct();
+    let mut hraw = crate::Hraw::new(&fixture.unknown("py")).unwrap();
+    let value = hraw.header();
+    let header  = value.to_struct();
     let vec = hraw.to_vec_poi(0)?;
     let mut dst = vec![0i32; header.width * header.height];
     let decoder = header.decoder.unwrap();
     println!("lang : {}", decoder.lang);
     println!("code : \r\n{}", decoder.code);
   
-    dst.from_py_script(decoder.code.as_str(), vec.as_slice(), header.width, header.height);
-    println!("{:?}", &dst);
+    dst.from_py_script(decoder.code.as_str(), vec.as_slice(), &value, &ScriptPolicy::trusted())?;
+    println!("{:?}", &dst[0..3]);
+    assert_eq!(dst, frame(0));
   }
 
   {
     use crate::buffer::FromHraw;
+    let test_file = fixture.unknown("py");
+    let mut dst = vec![0i32; WIDTH * HEIGHT];
 
-    // const TEST_FILE_I32 :&str = r".\src\test\pics\py.zip";
-    // let mut hraw = crate::Hraw::new(TEST_FILE_I32).unwrap();
-    // let header  = hraw.header().to_struct();
-    // let mut dst = vec![0i32; header.width * header.height];
-
-    // dst.as_mut_slice().from_hraw(TEST_FILE_I32, 0);
-    // println!("0 : {:?}", &dst[0..2]);
-    // dst.as_mut_slice().from_hraw(TEST_FILE_I32, 1);
-    // println!("1 : {:?}", &dst[0..2]);
-    // dst.as_mut_slice().from_hraw(TEST_FILE_I32, "data.raw");
-    // println!("data.raw : {:?}", &dst[0..2]);
-    // dst.as_mut_slice().from_hraw(TEST_FILE_I32, "subdir/1.raw");
-    // println!("subdir/1.raw : {:?}", &dst[0..2]);
-
+    let policy = ScriptPolicy { allow_python: true, ..Default::default() };
+    assert!(dst.as_mut_slice().from_hraw_with(&test_file, 0, &ScriptPolicy::default()).is_err());
+    assert!(dst.as_mut_slice().from_hraw(&test_file, 0).is_err());
+    dst.as_mut_slice().from_hraw_with(&test_file, 0, &policy)?;
+    assert_eq!(dst, frame(0));
+    dst.as_mut_slice().from_hraw_with(&test_file, 1, &policy)?;
+    assert_eq!(dst, frame(1));
+    dst.as_mut_slice().from_hraw_with(&test_file, "subdir/2.raw", &policy)?;
+    assert_eq!(dst, frame(2));
   }
   Ok(())
 }
 
 
 #[test]
+#[ignore = "requires powershell"]
 fn powershell() -> anyhow::Result<()> {
   use std::process::Stdio;
-  let outputs = std::fs::File::create("out.txt")?;
+  use super::fixture::*;
+  let fixture = Fixture::new();
+  let outputs = std::fs::File::create(fixture.path("out.txt"))?;
   let errors = outputs.try_clone()?;
 
   let dst = std::process::Command::new("powershell")
-    .args(&["ls"])
+    .args(["ls"])
     .stdout(Stdio::from(outputs))
     .stderr(Stdio::from(errors))
     .spawn().unwrap()
@@ -443,3 +458,787 @@ fn powershell() -> anyhow::Result<()> {
 
 
 
+
+
+#[cfg(feature = "lua")]
+#[test]
+fn lua_block_decoder() -> anyhow::Result<()> {
+  use crate::*;
+  use crate::buffer::FromHraw;
+  use super::fixture::*;
+  let fixture = Fixture::new();
+
+  let src = frame(1).iter().flat_map(|n| n.to_le_bytes()).collect::<Vec<u8>>();
+  for code in [DECODER_LUA, DECODER_LUA_ROW, DECODER_LUA_BLOCK] {
+    let mut dst = vec![0i32; WIDTH * HEIGHT];
+    dst.from_lua_script(code, src.as_slice(), &header(), &ScriptPolicy::default())?;
+    assert_eq!(dst, frame(1));
+
+    let mut dst = vec![0f64; WIDTH * HEIGHT];
+    dst.from_lua_script(code, src.as_slice(), &header(), &ScriptPolicy::default())?;
+    assert_eq!(dst, frame(1).into_iter().map(|n| n as f64).collect::<Vec<_>>());
+  }
+
+  let test_file = fixture.decoder("block.zip", "lua", DECODER_LUA_BLOCK);
+  let mut dst = vec![0f32; WIDTH * HEIGHT];
+  dst.as_mut_slice().from_hraw(&test_file, 2)?;
+  assert_eq!(dst, frame(2).into_iter().map(|n| n as f32).collect::<Vec<_>>());
+
+  let lua = mlua::Lua::new();
+  let mut dst = vec![0i32; WIDTH * HEIGHT];
+  assert!(lua.call_func(src.as_slice(), dst.as_mut_slice(), &header(), "local a = 1").is_err());
+  Ok(())
+}
+
+#[cfg(feature = "lua")]
+#[test]
+fn lua_parallel_decoder() -> anyhow::Result<()> {
+  use crate::*;
+  use super::fixture::*;
+
+  let src = frame(2).iter().flat_map(|n| n.to_le_bytes()).collect::<Vec<u8>>();
+  for code in [DECODER_LUA, DECODER_LUA_ROW, DECODER_LUA_BLOCK] {
+    let mut serial = vec![0i32; WIDTH * HEIGHT];
+    mlua::Lua::new().call_func(src.as_slice(), serial.as_mut_slice(), &header(), code)?;
+
+    /* 48行を5分割 (10行 x 4 + 8行) */
+    for threads in [1, 5, 64] {
+      let pool = rayon::ThreadPoolBuilder::new().num_threads(threads).build()?;
+      let mut dst = vec![0i32; WIDTH * HEIGHT];
+      pool.install(|| lua_par_call(code, &ScriptModules::new(), src.as_slice(), dst.as_mut_slice(), &header(), &ScriptPolicy::default()))?;
+      assert_eq!(dst, serial);
+    }
+    assert_eq!(serial, frame(2));
+
+    /* 空のフレーム */
+    let mut empty = header();
+    empty["width"] = serde_json::json!(0);
+    lua_par_call(code, &ScriptModules::new(), &[], &mut [0i32; 0], &empty, &ScriptPolicy::default())?;
+    assert!(lua_par_encode::<i32>(code, &ScriptModules::new(), &[], &empty, &ScriptPolicy::default())?.is_empty());
+  }
+  Ok(())
+}
+
+#[cfg(feature = "lua")]
+#[test]
+fn lua_sandbox() -> anyhow::Result<()> {
+  use crate::*;
+  use indoc::indoc;
+
+  let src = vec![0u8; 16];
+  let run = |code:&str, policy:&ScriptPolicy| {
+    let mut dst = vec![0i32; 4];
+    dst.from_lua_script(code, src.as_slice(), &serde_json::json!({ "width" : 2, "height" : 2 }), policy).map(|_| dst)
+  };
+
+  /* stdlibの制限 */
+  let os = "function(index) return os.time() end";
+  let req = "function(index) return require('x') end";
+  let load = "function(index) return loadstring('return 1')() end";
+  assert!(run(os, &ScriptPolicy::trusted()).is_ok());
+  assert!(run(os, &ScriptPolicy::default()).is_err());
+  assert!(run(req, &ScriptPolicy::default()).is_err());
+  assert!(run(load, &ScriptPolicy::default()).is_err());
+  assert_eq!(run("function(index) return math.floor(string.len('abc') + bit32.lshift(index, 1)) end", &ScriptPolicy::default())?, vec![3, 5, 7, 9]);
+
+  /* 無限ループ, メモリ */
+  let forever = indoc! {"
+    function(index)
+      local n = 0
+      while true do n = n + 1 end
+      return n
+    end
+  "};
+  let alloc = indoc! {"
+    function(index)
+      local t = {}
+      for n = 1, 10000000 do t[n] = buffer.create(1024) end
+      return 0
+    end
+  "};
+  let policy = ScriptPolicy { instruction_limit: Some(100_000), ..Default::default() };
+  assert!(run(forever, &policy).unwrap_err().to_string().contains("instruction limit"));
+  let policy = ScriptPolicy { time_limit: Some(std::time::Duration::from_millis(50)), ..Default::default() };
+  assert!(run(forever, &policy).unwrap_err().to_string().contains("time limit"));
+  let policy = ScriptPolicy { memory_limit: Some(16 * 1024 * 1024), ..Default::default() };
+  assert!(run(alloc, &policy).is_err());
+
+  /* 使い回すstateでもrow rangeごとに数え直す */
+  let busy = "function(index) local n = 0 for i = 1, 100 do n = n + i end return 0 end";
+  let lua = ScriptPolicy { instruction_limit: Some(1000), ..Default::default() }.lua()?;
+  let src = crate::rawnumber::LuaSrc::new(src.as_slice());
+  let mut dst = vec![0i32; 4];
+  for _ in 0..3 {
+    lua.call_range(&src, dst.as_mut_slice(), 0, &serde_json::json!({ "width" : 2, "height" : 2 }), busy)?;
+  }
+  Ok(())
+}
+
+#[cfg(all(feature = "lua", feature = "py"))]
+#[test]
+fn script_header() -> anyhow::Result<()> {
+  use crate::*;
+  use indoc::indoc;
+
+  let lua = "function(index) return x + y * header.scale + header.offset end";
+  let lua_row = indoc! {"
+    function decode_row(y, out)
+      for x = 0, header.width - 1 do
+        buffer.writef64(out, x * 8, x + y * header.scale + header.stride)
+      end
+    end
+  "};
+  let py = indoc! {"
+    def function(index):
+      return x + y * header['scale'] + header['offset']
+  "};
+
+  /* 同じscriptで解像度違い */
+  for (width, height) in [(4, 3), (6, 2)] {
+    let value = serde_json::json!({ "width" : width, "height" : height, "scale" : 100, "bitfield" : "unknown" });
+    let expected = (0..width * height).map(|i| (i % width + i / width * 100) as i32).collect::<Vec<_>>();
+    let mut dst = vec![0i32; width * height];
+    dst.from_lua_script(lua, &[], &value, &ScriptPolicy::default())?;
+    assert_eq!(dst, expected);
+    dst.from_lua_script(lua_row, &[], &value, &ScriptPolicy::default())?;
+    assert_eq!(dst, expected.iter().map(|n| n + width as i32).collect::<Vec<_>>());
+    dst.from_py_script(py, &[], &value, &ScriptPolicy::trusted())?;
+    assert_eq!(dst, expected);
+  }
+
+  /* read-only */
+  let value = serde_json::json!({ "width" : 1, "height" : 1, "nested" : { "a" : 1, "list" : [{ "b" : 1 }] } });
+  let mut dst = [0i32; 1];
+  assert!(dst.from_lua_script("function(index) header.width = 2 return 0 end", &[], &value, &ScriptPolicy::default()).is_err());
+  assert!(dst.from_lua_script("function(index) header.nested.a = 2 return 0 end", &[], &value, &ScriptPolicy::trusted()).is_err());
+  let py = indoc! {"
+    def function(index):
+      header['width'] = 2
+      return 0
+  "};
+  assert!(dst.from_py_script(py, &[], &value, &ScriptPolicy::trusted()).is_err());
+  for body in ["header['nested']['a'] = 2", "header['nested']['list'][0]['b'] = 2", "header['nested']['list'].append(1)"] {
+    let py = format!("def function(index):\n  {body}\n  return 0\n");
+    assert!(dst.from_py_script(&py, &[], &value, &ScriptPolicy::trusted()).is_err(), "{body}");
+  }
+  let py = "def function(index):\n  return header['nested']['list'][0]['b'] + len(header['nested'])\n";
+  dst.from_py_script(py, &[], &value, &ScriptPolicy::trusted())?;
+  assert_eq!(dst, [3]);
+  Ok(())
+}
+
+#[cfg(all(feature = "lua", feature = "py"))]
+#[test]
+fn decoder_file_and_modules() -> anyhow::Result<()> {
+  use super::fixture::*;
+  use crate::buffer::*;
+  use indoc::indoc;
+  let fixture = Fixture::new();
+  let expected = frame(1);
+
+  /* lua : file + require */
+  let bits = indoc! {"
+    local bits = {}
+    function bits.i32(buf, offset)
+      return buffer.readi32(buf, offset)
+    end
+    return bits
+  "};
+  let unpack = indoc! {"
+    local bits = require('bits')
+    local util = require('sub/util')
+    function decode_row(y, out)
+      for x = 0, header.width - 1 do
+        buffer.writef64(out, x * 8, util.id(bits.i32(src, (x + y * header.width) * 4)))
+      end
+    end
+  "};
+  let util = "return { id = function(v) return v end }";
+  let files = [("decoders/unpack.luau", unpack), ("decoders/lib/bits.luau", bits), ("decoders/lib/sub/util.lua", util)];
+  let decoder = serde_json::json!({ "lang" : "lua", "file" : "decoders/unpack.luau", "modules" : ["decoders/lib"] });
+  let path = fixture.decoder_files("lua_file.zip", decoder, &files);
+  let mut dst = vec![0i32; WIDTH * HEIGHT];
+  dst.as_mut_slice().from_hraw_with(&path, 1, &crate::ScriptPolicy::default())?;
+  assert_eq!(dst, expected);
+
+  /* 無いmodule, 無いfile, codeとfileの両方 */
+  let decoder = serde_json::json!({ "lang" : "lua", "file" : "decoders/unpack.luau" });
+  let path = fixture.decoder_files("lua_nomodule.zip", decoder, &files);
+  assert!(dst.as_mut_slice().from_hraw_with(&path, 1, &crate::ScriptPolicy::default()).is_err());
+  let decoder = serde_json::json!({ "lang" : "lua", "file" : "decoders/none.luau" });
+  let path = fixture.decoder_files("lua_nofile.zip", decoder, &files);
+  assert!(dst.as_mut_slice().from_hraw_with(&path, 1, &crate::ScriptPolicy::default()).is_err());
+  let decoder = serde_json::json!({ "lang" : "lua", "file" : "decoders/unpack.luau", "code" : DECODER_LUA });
+  let path = fixture.decoder_files("lua_both.zip", decoder, &files);
+  assert!(dst.as_mut_slice().from_hraw_with(&path, 1, &crate::ScriptPolicy::default()).is_err());
+
+  /* python : file + import */
+  let bits = indoc! {"
+    def i32(src, i):
+      return int.from_bytes(bytearray(src[i:i+4]), 'little', signed=True)
+  "};
+  let unpack = indoc! {"
+    import bits
+    def function(index):
+      return bits.i32(src, index * 4)
+  "};
+  let files = [("decoders/unpack.py", unpack), ("decoders/lib/bits.py", bits)];
+  let decoder = serde_json::json!({ "lang" : "py", "file" : "decoders/unpack.py", "modules" : ["decoders/lib"] });
+  let path = fixture.decoder_files("py_file.zip", decoder, &files);
+  let mut dst = vec![0i32; WIDTH * HEIGHT];
+  dst.as_mut_slice().from_hraw_with(&path, 1, &crate::ScriptPolicy::trusted())?;
+  assert_eq!(dst, expected);
+
+  Ok(())
+}
+
+#[cfg(all(feature = "lua", feature = "py"))]
+#[test]
+fn encoder_round_trip() -> anyhow::Result<()> {
+  use super::fixture::*;
+  use crate::buffer::*;
+  use crate::writer::HrawWriter;
+  use indoc::indoc;
+  let fixture = Fixture::new();
+
+  /* 24bit big endian, decoder / encoderともarchive内のfile */
+  let decoder = indoc! {"
+    function decode_row(y, out)
+      for x = 0, header.width - 1 do
+        local i = header.offset + (x + y * header.width) * 3
+        local v = bit32.bor(bit32.lshift(buffer.readu8(src, i), 16), bit32.lshift(buffer.readu8(src, i + 1), 8), buffer.readu8(src, i + 2))
+        if v >= 0x800000 then v -= 0x1000000 end
+        buffer.writef64(out, x * 8, v)
+      end
+    end
+  "};
+  let encoder = indoc! {"
+    local be24 = require('be24')
+    function encode_row(y, values)
+      local out = buffer.create(header.width * 3)
+      for x = 0, header.width - 1 do
+        be24.write(out, x * 3, buffer.readf64(values, x * 8))
+      end
+      return out
+    end
+  "};
+  let be24 = indoc! {"
+    return { write = function(buf, i, v)
+      v = bit32.band(v, 0xffffff)
+      buffer.writeu8(buf, i, bit32.rshift(v, 16))
+      buffer.writeu8(buf, i + 1, bit32.band(bit32.rshift(v, 8), 0xff))
+      buffer.writeu8(buf, i + 2, bit32.band(v, 0xff))
+    end }
+  "};
+  let header = serde_json::json!({
+    "width" : WIDTH, "height" : HEIGHT, "bitfield" : "unknown", "offset" : 4,
+    "decoder" : { "lang" : "lua", "file" : "codec/decode.luau" },
+    "encoder" : { "lang" : "lua", "file" : "codec/encode.luau", "modules" : ["codec/lib"] }
+  });
+
+  let path = fixture.path("be24.zip");
+  let mut writer = HrawWriter::new(&path, header.clone())?;
+  writer.write_raw("codec/decode.luau", decoder.as_bytes())?;
+  writer.write_raw("codec/encode.luau", encoder.as_bytes())?;
+  writer.write_raw("codec/lib/be24.luau", be24.as_bytes())?;
+  DATA.iter().enumerate().try_for_each(|(n, entry)| writer.write_encoded(entry, frame(n).as_slice(), &crate::ScriptPolicy::default()))?;
+  writer.finish()?;
+
+  let mut hraw = crate::Hraw::new(&path)?;
+  assert_eq!(hraw.to_vec("data.raw")?.len(), 4 + WIDTH * HEIGHT * 3);
+  let mut dst = vec![0i32; WIDTH * HEIGHT];
+  dst.as_mut_slice().from_hraw(&path, 2)?;
+  assert_eq!(dst, frame(2));
+
+  /* 処理して再出力 */
+  let processed = dst.iter().map(|n| n + 1).collect::<Vec<_>>();
+  let path2 = fixture.path("be24_2.zip");
+  let mut writer = HrawWriter::new(&path2, hraw.header())?;
+  writer.copy_entries(&mut hraw)?;
+  writer.write_encoded("data.raw", processed.as_slice(), &crate::ScriptPolicy::default())?;
+  writer.finish()?;
+  let mut dst = vec![0i32; WIDTH * HEIGHT];
+  dst.as_mut_slice().from_hraw(&path2, 0)?;
+  assert_eq!(dst, processed);
+
+  /* python, per pixel */
+  let header = serde_json::json!({
+    "width" : WIDTH, "height" : HEIGHT, "bitfield" : "unknown",
+    "decoder" : { "lang" : "py", "code" : DECODER_PY },
+    "encoder" : { "lang" : "py", "code" : "def function(index, value):\n  return int(value).to_bytes(4, 'little', signed=True)\n" }
+  });
+  let path = fixture.path("py_encoder.zip");
+  let mut writer = HrawWriter::new(&path, header)?;
+  writer.write_encoded("data.raw", frame(1).as_slice(), &crate::ScriptPolicy::trusted())?;
+  assert!(writer.write_encoded("1.raw", frame(1).as_slice(), &crate::ScriptPolicy::default()).is_err());
+  writer.finish()?;
+  let mut dst = vec![0i32; WIDTH * HEIGHT];
+  dst.as_mut_slice().from_hraw_with(&path, 0, &crate::ScriptPolicy::trusted())?;
+  assert_eq!(dst, frame(1));
+
+  Ok(())
+}
+
+#[cfg(feature = "py")]
+#[test]
+fn py_numpy_decoder() -> anyhow::Result<()> {
+  use super::fixture::*;
+  if !numpy() { return Ok(()); }
+  use crate::buffer::*;
+  use indoc::indoc;
+  let fixture = Fixture::new();
+
+  let code = indoc! {"
+    import numpy as np
+    def decode(src: np.ndarray, header: dict) -> np.ndarray:
+      return src[header['offset']:].view('<i4').reshape(header['height'], header['width'])
+  "};
+  let path = fixture.decoder("numpy.zip", "py", code);
+  let mut dst = vec![0i32; WIDTH * HEIGHT];
+  dst.as_mut_slice().from_hraw_with(&path, 2, &crate::ScriptPolicy::trusted())?;
+  assert_eq!(dst, frame(2));
+
+  /* shape違い, srcを保持 */
+  let code = "def decode(src, header):\n  return src[0:4]\n";
+  let path = fixture.decoder("numpy_shape.zip", "py", code);
+  assert!(dst.as_mut_slice().from_hraw_with(&path, 0, &crate::ScriptPolicy::trusted()).is_err());
+  let code = indoc! {"
+    import numpy as np
+    kept = []
+    def decode(src, header):
+      kept.append(src)
+      return np.zeros((header['height'], header['width']))
+  "};
+  let path = fixture.decoder("numpy_keep.zip", "py", code);
+  dst.as_mut_slice().from_hraw_with(&path, 0, &crate::ScriptPolicy::trusted())?;
+  assert!(dst.iter().all(|n| *n == 0));
+  Ok(())
+}
+
+#[cfg(feature = "wasm")]
+#[test]
+fn wasm_decoder() -> anyhow::Result<()> {
+  use super::fixture::*;
+  use crate::buffer::*;
+  use indoc::indoc;
+  let fixture = Fixture::new();
+
+  /* le_i32 -> f64 */
+  let wat = indoc! {r#"
+    (module
+      (memory (export "memory") 1)
+      (func (export "decode") (param $src i32) (param $len i32) (param $dst i32) (param $w i32) (param $h i32)
+        (local $i i32)
+        (block $done (loop $next
+          (br_if $done (i32.ge_u (local.get $i) (i32.mul (local.get $w) (local.get $h))))
+          (f64.store
+            (i32.add (local.get $dst) (i32.shl (local.get $i) (i32.const 3)))
+            (f64.convert_i32_s (i32.load (i32.add (local.get $src) (i32.shl (local.get $i) (i32.const 2))))))
+          (local.set $i (i32.add (local.get $i) (i32.const 1)))
+          (br $next)))))
+  "#};
+  let wasm = wat::parse_str(wat)?;
+  let decoder = serde_json::json!({ "lang" : "wasm", "file" : "decoder.wasm" });
+  let path = fixture.decoder_files("wasm.zip", decoder, &[("decoder.wasm", &wasm)]);
+  let mut dst = vec![0i32; WIDTH * HEIGHT];
+  dst.as_mut_slice().from_hraw_with(&path, 1, &crate::ScriptPolicy::default())?;
+  assert_eq!(dst, frame(1));
+  let mut dst = vec![0f32; WIDTH * HEIGHT];
+  dst.as_mut_slice().from_hraw_with(&path, 2, &crate::ScriptPolicy::default())?;
+  assert_eq!(dst, frame(2).into_iter().map(|n| n as f32).collect::<Vec<_>>());
+  assert!(crate::Hraw::new(&path)?.check_decoder()?.is_ok());
+
+  /* 2byteずれて末尾の画素だけsrcの外を読む */
+  let shifted = wat::parse_str(wat.replace("(i32.add (local.get $src)", "(i32.add (i32.add (local.get $src) (i32.const 2))"))?;
+  let decoder = serde_json::json!({ "lang" : "wasm", "file" : "decoder.wasm" });
+  let report = crate::Hraw::new(&fixture.decoder_files("wasm_shifted.zip", decoder, &[("decoder.wasm", &shifted)]))?.check_decoder()?;
+  assert_eq!(report.faults.iter().map(|n| n.index).collect::<Vec<_>>(), [Some(WIDTH * HEIGHT - 1)]);
+
+  /* fuel, memory */
+  let policy = crate::ScriptPolicy { instruction_limit: Some(1000), ..Default::default() };
+  assert!(dst.as_mut_slice().from_hraw_with(&path, 0, &policy).is_err());
+  let policy = crate::ScriptPolicy { instruction_limit: Some(10_000_000), ..Default::default() };
+  dst.as_mut_slice().from_hraw_with(&path, 0, &policy)?;
+  let policy = crate::ScriptPolicy { memory_limit: Some(65536), ..Default::default() };
+  assert!(dst.as_mut_slice().from_hraw_with(&path, 0, &policy).is_err());
+
+  /* instruction_limit無しでも既定のfuelで止まる */
+  let wasm = wat::parse_str(r#"(module (memory (export "memory") 1) (func (export "decode") (param i32 i32 i32 i32 i32) (loop $l (br $l))))"#)?;
+  let mut forever = vec![0f64; WIDTH * HEIGHT];
+  let error = crate::rawnumber::wasm_call(&wasm, &[0u8; 4], &mut forever, WIDTH, HEIGHT, &crate::ScriptPolicy::default()).unwrap_err();
+  assert!(error.to_string().contains("fuel"), "{error}");
+
+  /* trap, export無し, inline code */
+  let wasm = wat::parse_str(r#"(module (memory (export "memory") 1) (func (export "decode") (param i32 i32 i32 i32 i32) unreachable))"#)?;
+  let decoder = serde_json::json!({ "lang" : "wasm", "file" : "decoder.wasm" });
+  let path = fixture.decoder_files("wasm_trap.zip", decoder, &[("decoder.wasm", &wasm)]);
+  assert!(dst.as_mut_slice().from_hraw_with(&path, 0, &crate::ScriptPolicy::default()).is_err());
+  let wasm = wat::parse_str(r#"(module (memory (export "memory") 1))"#)?;
+  let decoder = serde_json::json!({ "lang" : "wasm", "file" : "decoder.wasm" });
+  let path = fixture.decoder_files("wasm_noexport.zip", decoder, &[("decoder.wasm", &wasm)]);
+  assert!(dst.as_mut_slice().from_hraw_with(&path, 0, &crate::ScriptPolicy::default()).is_err());
+  let path = fixture.decoder("wasm_code.zip", "wasm", "(module)");
+  assert!(dst.as_mut_slice().from_hraw_with(&path, 0, &crate::ScriptPolicy::default()).is_err());
+  Ok(())
+}
+
+#[cfg(feature = "rhai")]
+#[test]
+fn rhai_decoder() -> anyhow::Result<()> {
+  use crate::*;
+  use super::fixture::*;
+  use crate::buffer::*;
+  use crate::writer::HrawWriter;
+  use indoc::indoc;
+  let fixture = Fixture::new();
+  let policy = crate::ScriptPolicy::default();
+
+  /* per pixel, per row */
+  let pixel = "fn decode(index) { parse_le_int(src, header.offset + index * 4, 4) }";
+  let row = indoc! {"
+    fn decode_row(y) {
+      let dst = [];
+      for x in 0..header.width {
+        dst.push(parse_le_int(src, (x + y * header.width) * 4, 4));
+      }
+      dst
+    }
+  "};
+  for code in [pixel, row] {
+    let path = fixture.decoder("rhai.zip", "rhai", code);
+    let mut dst = vec![0i32; WIDTH * HEIGHT];
+    dst.as_mut_slice().from_hraw_with(&path, 1, &policy)?;
+    assert_eq!(dst, frame(1));
+    let mut dst = vec![0f64; WIDTH * HEIGHT];
+    dst.as_mut_slice().from_hraw_with(&path, 2, &policy)?;
+    assert_eq!(dst, frame(2).into_iter().map(|n| n as f64).collect::<Vec<_>>());
+  }
+
+  /* x, y, header */
+  let value = serde_json::json!({ "width" : 4, "height" : 3, "scale" : 100 });
+  let mut dst = vec![0i32; 12];
+  dst.from_rhai_script("fn decode(index) { x + y * header.scale }", &[], &value, &policy)?;
+  assert_eq!(dst, (0..12).map(|i| i % 4 + i / 4 * 100).collect::<Vec<_>>());
+  assert!(dst.from_rhai_script("fn decode(index) { header.width = 1; 0 }", &[], &value, &policy).is_err());
+  assert!(dst.from_rhai_script("fn decode_row(y) { [0] }", &[], &value, &policy).is_err());
+  assert!(dst.from_rhai_script("fn other(index) { 0 }", &[], &value, &policy).is_err());
+  /* top levelは一度だけ評価 */
+  dst.from_rhai_script("const SCALE = 100; fn decode(index) { x + y * global::SCALE }", &[], &value, &policy)?;
+  assert_eq!(dst, (0..12).map(|i| i % 4 + i / 4 * 100).collect::<Vec<_>>());
+  let empty = serde_json::json!({ "width" : 0, "height" : 3 });
+  [0i32; 0].as_mut_slice().from_rhai_script("fn decode(index) { 0 }", &[], &empty, &policy)?;
+
+  /* instruction limit */
+  let endless = "fn decode(index) { loop { } }";
+  let limited = crate::ScriptPolicy { instruction_limit: Some(10_000), ..Default::default() };
+  assert!(dst.from_rhai_script(endless, &[], &value, &limited).is_err());
+
+  /* memory limit */
+  let large = "fn decode(index) { let s = \"\"; s.pad(4096, 'x'); 0 }";
+  let limited = crate::ScriptPolicy { memory_limit: Some(1024), ..Default::default() };
+  assert!(dst.from_rhai_script(large, &[], &value, &limited).is_err());
+  let mut frame1 = vec![0i32; WIDTH * HEIGHT];
+  frame1.as_mut_slice().from_hraw_with(&fixture.decoder("rhai.zip", "rhai", pixel), 1, &limited)?;
+  assert_eq!(frame1, frame(1));
+
+  /* file + modules, ファイルシステムからのimportは不可 */
+  let unpack = indoc! {r#"
+    import "bits" as bits;
+    fn decode(index) { bits::i32(src, index * 4) }
+  "#};
+  let bits = "fn i32(src, i) { let v = parse_le_int(src, i, 4); if v >= 0x80000000 { v - 0x100000000 } else { v } }";
+  let files = [("decoders/unpack.rhai", unpack), ("decoders/lib/bits.rhai", bits)];
+  let decoder = serde_json::json!({ "lang" : "rhai", "file" : "decoders/unpack.rhai", "modules" : ["decoders/lib"] });
+  let path = fixture.decoder_files("rhai_file.zip", decoder, &files);
+  let mut dst = vec![0i32; WIDTH * HEIGHT];
+  dst.as_mut_slice().from_hraw_with(&path, 0, &policy)?;
+  assert_eq!(dst, frame(0));
+  let outside = format!("import \"{}\" as m; fn decode(index) {{ 0 }}", fixture.path("bits"));
+  std::fs::write(fixture.path("bits.rhai"), bits)?;
+  assert!(dst.as_mut_slice().from_rhai_script(&outside, &[], &header(), &policy).is_err());
+
+  /* encoder */
+  let header = serde_json::json!({
+    "width" : WIDTH, "height" : HEIGHT, "bitfield" : "unknown",
+    "decoder" : { "lang" : "rhai", "code" : pixel },
+    "encoder" : { "lang" : "rhai", "code" : "fn encode(index, value) { let b = blob(4); b.write_le(0, 4, value.to_int()); b }" }
+  });
+  let path = fixture.path("rhai_encoder.zip");
+  let mut writer = HrawWriter::new(&path, header)?;
+  writer.write_encoded("data.raw", frame(2).as_slice(), &policy)?;
+  writer.finish()?;
+  dst.as_mut_slice().from_hraw_with(&path, 0, &policy)?;
+  assert_eq!(dst, frame(2));
+  Ok(())
+}
+
+#[cfg(feature = "lua")]
+#[test]
+fn check_decoder() -> anyhow::Result<()> {
+  use super::fixture::*;
+  use indoc::indoc;
+  let fixture = Fixture::new();
+
+  let mut raw = crate::Hraw::new(&fixture.unknown("lua"))?;
+  let report = raw.check_decoder()?;
+  assert!(report.is_ok(), "{:?}", report.faults);
+  assert!(report.samples.len() > 2);
+  assert!(report.samples.iter().all(|&(i, n)| n == frame(0)[i] as f64));
+
+  /* 1pixelずれて末尾で範囲外 */
+  let shifted = indoc! {"
+    function(index)
+      local i = index * 4 + 5
+      local buf = buffer.create(4)
+      for n = 0, 3 do buffer.writeu8(buf, n, src[i + n]) end
+      return buffer.readi32(buf, 0)
+    end
+  "};
+  let mut raw = crate::Hraw::new(&fixture.decoder("shifted.zip", "lua", shifted))?;
+  let report = raw.check_decoder()?;
+  let fault = report.faults.iter().find(|n| n.index == Some(WIDTH * HEIGHT - 1)).expect("last pixel");
+  assert_eq!(fault.xy, Some((WIDTH - 1, HEIGHT - 1)));
+  assert_eq!(fault.line, Some(4), "{fault:?}");
+  assert!(fault.message.contains("out of range"), "{fault}");
+  assert_eq!(fault.bytes, frame(0)[WIDTH * HEIGHT - 1].to_le_bytes());
+
+  /* 戻り値の型, NaN, 文法エラー */
+  let mut raw = crate::Hraw::new(&fixture.decoder("string.zip", "lua", "function(index) return 'a' end"))?;
+  assert!(raw.check_decoder()?.faults.iter().all(|n| n.message.contains("number expected")));
+  let mut raw = crate::Hraw::new(&fixture.decoder("nan.zip", "lua", "function(index) return 0 / 0 end"))?;
+  assert!(!raw.check_decoder()?.is_ok());
+  let mut raw = crate::Hraw::new(&fixture.decoder("syntax.zip", "lua", "function decode_row(y, out)\n  local = 1\nend"))?;
+  let report = raw.check_decoder()?;
+  assert_eq!(report.faults.len(), 1);
+  assert_eq!((report.faults[0].index, report.faults[0].line), (None, Some(2)), "{}", report.faults[0]);
+
+  /* row decoder */
+  let mut raw = crate::Hraw::new(&fixture.decoder("row.zip", "lua", DECODER_LUA_ROW))?;
+  assert!(raw.check_decoder()?.is_ok());
+  Ok(())
+}
+
+#[cfg(feature = "py")]
+#[test]
+fn check_decoder_py() -> anyhow::Result<()> {
+  use super::fixture::*;
+  let fixture = Fixture::new();
+  let policy = crate::ScriptPolicy::trusted();
+
+  let mut raw = crate::Hraw::new(&fixture.unknown("py"))?;
+  assert!(raw.check_decoder_with(0, 16, &policy)?.is_ok());
+  assert!(!raw.check_decoder()?.is_ok()); // default policy
+
+  /* slice past the end is an error, not a short read */
+  let shifted = "def function(index):\n  i = index * 4 + 4\n  return int.from_bytes(src[i:i+4], 'little', signed=True)\n";
+  let mut raw = crate::Hraw::new(&fixture.decoder("py_shifted.zip", "py", shifted))?;
+  let report = raw.check_decoder_with(0, 16, &policy)?;
+  assert_eq!(report.faults.len(), 1);
+  assert_eq!((report.faults[0].index, report.faults[0].line), (Some(WIDTH * HEIGHT - 1), Some(3)));
+  Ok(())
+}
+
+#[cfg(feature = "rhai")]
+#[test]
+fn check_decoder_rhai() -> anyhow::Result<()> {
+  use super::fixture::*;
+  let fixture = Fixture::new();
+  let code = "fn decode(index) {\n  if index == 10 { throw \"bad\"; }\n  parse_le_int(src, index * 4, 4)\n}";
+  let mut raw = crate::Hraw::new(&fixture.decoder("rhai_check.zip", "rhai", code))?;
+  let report = raw.check_decoder_with(0, WIDTH * HEIGHT, &crate::ScriptPolicy::default())?;
+  assert_eq!(report.faults.len(), 1);
+  assert_eq!((report.faults[0].index, report.faults[0].line), (Some(10), Some(2)));
+
+  /* 2byteずれて末尾で範囲外, 切り詰めずにfault */
+  for code in ["fn decode(index) { parse_le_int(src, index * 4 + 2, 4) }", "fn decode(index) { src.get(index * 4 + 5) }"] {
+    let mut raw = crate::Hraw::new(&fixture.decoder("rhai_shifted.zip", "rhai", code))?;
+    let report = raw.check_decoder()?;
+    assert_eq!(report.faults.len(), 1, "{code}");
+    assert_eq!(report.faults[0].index, Some(WIDTH * HEIGHT - 1));
+    assert!(report.faults[0].message.contains("out of range"));
+  }
+  Ok(())
+}
+
+#[test]
+fn layout_decoder() -> anyhow::Result<()> {
+  use super::fixture::*;
+  use crate::buffer::*;
+  use crate::writer::HrawWriter;
+  use crate::rawnumber::HrawScripting;
+  let fixture = Fixture::new();
+  let policy = crate::ScriptPolicy::default();
+  let packed = |name:&str, header:serde_json::Value, body:&[u8]| {
+    let path = fixture.path(name);
+    let mut writer = HrawWriter::new(&path, header).unwrap();
+    writer.write_raw("data.raw", body).unwrap();
+    writer.finish().unwrap();
+    path
+  };
+
+  /* 4 bytes LE, bits 8..32 signed */
+  let layout = serde_json::json!({ "lang" : "layout", "layout" : { "group" : 4, "samples" : [{ "bits" : [8, 32], "signed" : true }] } });
+  let path = fixture.decoder_files::<&str>("layout_i24.zip", layout, &[]);
+  let mut dst = vec![0i32; WIDTH * HEIGHT];
+  dst.as_mut_slice().from_hraw_with(&path, 1, &policy)?;
+  assert_eq!(dst, frame(1).into_iter().map(|n| n >> 8).collect::<Vec<_>>());
+  assert!(crate::Hraw::new(&path)?.check_decoder()?.is_ok());
+
+  /* 3 bytes packing two 12-bit samples, continuous over rows (odd width) */
+  let (width, height) = (5, 3);
+  let expected = (0..width * height).map(|i| (i * 273 % 4096) as u16).collect::<Vec<_>>();
+  let mut body = vec![0u8; 7]; // offset
+  expected.chunks(2).for_each(|n| {
+    let v = n[0] as u32 | (*n.get(1).unwrap_or(&0) as u32) << 12;
+    body.extend_from_slice(&v.to_le_bytes()[0..3]);
+  });
+  let header = serde_json::json!({
+    "width" : width, "height" : height, "offset" : 7, "bitfield" : "unknown", "data" : ["data.raw"],
+    "decoder" : { "lang" : "layout", "layout" : { "group" : 3, "samples" : [{ "bits" : [0, 12], "repeat" : 2 }] } }
+  });
+  let path = packed("layout_12.zip", header, &body);
+  let mut dst = vec![0f32; width * height];
+  dst.as_mut_slice().from_hraw_with(&path, 0, &policy)?;
+  assert_eq!(dst, expected.iter().map(|&n| n as f32).collect::<Vec<_>>());
+  let report = crate::Hraw::new(&path)?.check_decoder_with(0, 64, &policy)?;
+  assert!(report.is_ok() && report.samples.iter().all(|&(i, n)| n == expected[i] as f64));
+
+  /* MIPI RAW10 : 4 high bytes + 1 byte of low bits, rows padded to 8 bytes */
+  let (width, height) = (4, 2);
+  let expected = (0..width * height).map(|i| (i * 131 % 1024) as i32).collect::<Vec<_>>();
+  let mut body = Vec::new();
+  expected.chunks(4).for_each(|n| {
+    n.iter().for_each(|v| body.push((v >> 2) as u8));
+    body.push(n.iter().enumerate().fold(0u8, |dst, (i, v)| dst | ((v & 3) as u8) << (i * 2)));
+    body.extend_from_slice(&[0xff; 3]);
+  });
+  let raw10 = serde_json::json!({
+    "group" : 5, "row_bytes" : 8,
+    "samples" : [{ "fields" : [{ "bits" : [0, 8] }, { "bits" : [32, 34] }], "repeat" : 4 }]
+  });
+  let header = serde_json::json!({
+    "width" : width, "height" : height, "bitfield" : "unknown", "data" : ["data.raw"],
+    "decoder" : { "lang" : "layout", "layout" : raw10 }
+  });
+  let path = packed("layout_raw10.zip", header, &body);
+  let mut dst = vec![0i32; width * height];
+  dst.as_mut_slice().from_hraw_with(&path, 0, &policy)?;
+  assert_eq!(dst, expected);
+  let report = crate::Hraw::new(&path)?.check_decoder_with(0, 64, &policy)?;
+  assert!(report.is_ok() && report.samples.iter().all(|&(i, n)| n == expected[i] as f64));
+
+  /* big endian : 2 bytes, high 12 bits */
+  let header = serde_json::json!({
+    "width" : 2, "height" : 1, "bitfield" : "unknown", "data" : ["data.raw"],
+    "decoder" : { "lang" : "layout", "layout" : { "group" : 2, "endian" : "be", "samples" : [{ "bits" : [4, 16] }] } }
+  });
+  let path = packed("layout_be.zip", header.clone(), &[0xab, 0xc0, 0x12, 0x30]);
+  let mut dst = vec![0i32; 2];
+  dst.as_mut_slice().from_hraw_with(&path, 0, &policy)?;
+  assert_eq!(dst, [0xabc, 0x123]);
+
+  /* 短いdata, groupを越えるbits, bitsとfieldsの両方 */
+  let path = packed("layout_short.zip", header, &[0xab, 0xc0, 0x12]);
+  assert!(dst.as_mut_slice().from_hraw_with(&path, 0, &policy).is_err());
+  let report = crate::Hraw::new(&path)?.check_decoder()?;
+  assert_eq!((report.samples.clone(), report.faults.len(), report.faults[0].index), (vec![(0, 0xabc as f64)], 1, Some(1)));
+  let size = serde_json::json!({ "width" : 2, "height" : 1 });
+  for samples in [serde_json::json!([{ "bits" : [4, 17] }]), serde_json::json!([{ "bits" : [0, 8], "fields" : [{ "bits" : [8, 16] }] }]), serde_json::json!([])] {
+    let layout = serde_json::from_value::<crate::Layout>(serde_json::json!({ "group" : 2, "samples" : samples }))?;
+    assert!(dst.as_mut_slice().from_layout(&layout, &[0; 4], &size).is_err());
+  }
+
+  /* 幅0, 高さ0 */
+  let layout = serde_json::from_value::<crate::Layout>(serde_json::json!({ "group" : 2, "samples" : [{ "bits" : [0, 16] }] }))?;
+  for size in [serde_json::json!({ "width" : 0, "height" : 1 }), serde_json::json!({ "width" : 2, "height" : 0 })] {
+    assert!(dst.as_mut_slice().from_layout(&layout, &[0; 4], &size).is_err());
+  }
+  Ok(())
+}
+
+#[test]
+fn postprocess_ops() -> anyhow::Result<()> {
+  use super::fixture::*;
+  use crate::buffer::*;
+  let fixture = Fixture::new();
+  let policy = crate::ScriptPolicy::default();
+
+  /* darkを引いて半分, clamp */
+  let path = fixture.postprocess("ops.zip", serde_json::json!({
+    "lang" : "ops", "inputs" : { "dark" : "data.raw" },
+    "ops" : [{ "sub" : "dark" }, { "mul" : 0.5 }, { "clamp" : [0, 40000] }]
+  }));
+  let mut dst = vec![0i32; WIDTH * HEIGHT];
+  dst.as_mut_slice().from_hraw_processed(&path, 1, &policy)?;
+  assert!(dst.iter().all(|n| *n == 40000));
+  dst.as_mut_slice().from_hraw_with(&path, 1, &policy)?; // postprocessなし
+  assert_eq!(dst, frame(1));
+
+  /* lut, 範囲外はclamp */
+  let path = fixture.postprocess("lut.zip", serde_json::json!({ "lang" : "ops", "ops" : [{ "lut" : [10, 20, 30] }] }));
+  let mut dst = vec![0f32; WIDTH * HEIGHT];
+  dst.as_mut_slice().from_hraw_processed(&path, 0, &policy)?;
+  assert_eq!(dst, frame(0).iter().map(|&n| if n <= 0 { 10.0 } else if n == 1 { 20.0 } else { 30.0 }).collect::<Vec<_>>());
+
+  /* langが無ければops, 他のlangとopsは不可 */
+  let path = fixture.postprocess("ops_only.zip", serde_json::json!({ "ops" : [{ "mul" : 2 }] }));
+  let mut dst = vec![0i32; WIDTH * HEIGHT];
+  dst.as_mut_slice().from_hraw_processed(&path, 1, &policy)?;
+  assert_eq!(dst, frame(1).iter().map(|n| n * 2).collect::<Vec<_>>());
+  let path = fixture.postprocess("ops_lua.zip", serde_json::json!({ "lang" : "lua", "code" : "function(index, value) return value end", "ops" : [{ "mul" : 2 }] }));
+  assert!(dst.as_mut_slice().from_hraw_processed(&path, 1, &policy).unwrap_err().to_string().contains("lang : ops"));
+
+  /* clampは有限で min <= max */
+  let path = fixture.postprocess("clamp.zip", serde_json::json!({ "ops" : [{ "clamp" : [10, 0] }] }));
+  assert!(dst.as_mut_slice().from_hraw_processed(&path, 0, &policy).is_err());
+  assert!(crate::rawnumber::ops_call(&[crate::rawnumber::PostOp::Clamp([f64::NAN, 1.0])], &mut [0.0], &Default::default()).is_err());
+
+  /* 無いinput */
+  let path = fixture.postprocess("missing.zip", serde_json::json!({ "lang" : "ops", "ops" : [{ "sub" : "dark" }] }));
+  assert!(dst.as_mut_slice().from_hraw_processed(&path, 0, &policy).is_err());
+  let path = fixture.postprocess("missing_entry.zip", serde_json::json!({ "lang" : "ops", "inputs" : { "dark" : "none.raw" }, "ops" : [] }));
+  assert!(dst.as_mut_slice().from_hraw_processed(&path, 0, &policy).is_err());
+  Ok(())
+}
+
+#[cfg(all(feature = "lua", feature = "py"))]
+#[test]
+fn postprocess_script() -> anyhow::Result<()> {
+  use super::fixture::*;
+  use crate::buffer::*;
+  let fixture = Fixture::new();
+  let expected = (0..WIDTH * HEIGHT).map(|i| 100_000 + (i % WIDTH) as i32).collect::<Vec<_>>();
+
+  /* lua */
+  let code = "function(index, value, inputs) return value - inputs.dark + x end";
+  let path = fixture.postprocess("lua.zip", serde_json::json!({ "lang" : "lua", "code" : code, "inputs" : { "dark" : "data.raw" } }));
+  let mut dst = vec![0i32; WIDTH * HEIGHT];
+  dst.as_mut_slice().from_hraw_processed(&path, 1, &crate::ScriptPolicy::default())?;
+  assert_eq!(dst, expected);
+  let path = fixture.postprocess("lua_err.zip", serde_json::json!({ "lang" : "lua", "code" : "function(index, value, inputs) return inputs.none + 1 end" }));
+  assert!(dst.as_mut_slice().from_hraw_processed(&path, 1, &crate::ScriptPolicy::default()).is_err());
+
+  /* python, default policyでは不可 */
+  let code = "def function(index, value, inputs):\n  return value - inputs['dark'] + x\n";
+  let path = fixture.postprocess("py.zip", serde_json::json!({ "lang" : "py", "code" : code, "inputs" : { "dark" : "data.raw" } }));
+  let mut dst = vec![0f64; WIDTH * HEIGHT];
+  dst.as_mut_slice().from_hraw_processed(&path, 1, &crate::ScriptPolicy::trusted())?;
+  assert_eq!(dst, expected.iter().map(|&n| n as f64).collect::<Vec<_>>());
+  assert!(dst.as_mut_slice().from_hraw_processed(&path, 1, &crate::ScriptPolicy::default()).is_err());
+  Ok(())
+}
+
+#[cfg(feature = "py")]
+#[test]
+fn py_numpy_postprocess() -> anyhow::Result<()> {
+  use super::fixture::*;
+  if !numpy() { return Ok(()); }
+  use crate::buffer::*;
+  let fixture = Fixture::new();
+  let code = "def postprocess(frame, inputs, header):\n  return frame - inputs['dark']\n";
+  let path = fixture.postprocess("numpy_post.zip", serde_json::json!({ "lang" : "py", "code" : code, "inputs" : { "dark" : "data.raw" } }));
+  let mut dst = vec![0i32; WIDTH * HEIGHT];
+  dst.as_mut_slice().from_hraw_processed(&path, 2, &crate::ScriptPolicy::trusted())?;
+  assert!(dst.iter().all(|n| *n == 200_000));
+  Ok(())
+}
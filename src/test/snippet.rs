@@ -1,6 +1,5 @@
 #![allow(dead_code, unused_variables)]
 #[cfg(test)]
-
 use std::io::Write;
 
 #[test]
@@ -29,7 +28,7 @@ offset   : 64      # [byte]
 bitfield : le_i32  # [BitField]
 "##;
 
-  let value :serde_json::Value = serde_yaml::from_str(&test_yaml).unwrap();
+  let value :serde_json::Value = serde_yaml::from_str(test_yaml).unwrap();
   println!("value : {:?}", value);
 
   let obj: Header = serde_json::from_value(value).unwrap();
@@ -67,7 +66,8 @@ fn clamp() {
   println!("{:X}", i32::from_be_bytes([0, a, b, c]));
 
   use image::io::Reader as ImageReader;
-  let img = ImageReader::open("test.png").unwrap().decode().unwrap();
+  let fixture = super::fixture::Fixture::new();
+  let img = ImageReader::open(fixture.png()).unwrap().decode().unwrap();
   let width = img.width() as usize;
   let height = img.height() as usize;
   let src = img.into_bytes();
@@ -139,7 +139,7 @@ mod deprecation_processing {
       let y = (index / stride) % 2;
       let x = index % 2;
       
-      let dst = match (color, x, y) {
+      match (color, x, y) {
         (0, _, _) => (self[index], self[index], self[index]),
         (_, 0, 0) => {
           let r = self[index - 1] + self[index + 1];
@@ -166,8 +166,7 @@ mod deprecation_processing {
           (r / 2, g, b / 2)
         },
         (_, _, _) => (self[index], self[index], self[index]),
-      };
-      dst
+      }
     }
    }
   
@@ -203,7 +202,7 @@ mod deprecation_stream {
       
       let value = i32::from_le_bytes(buf) as u16;
       let dst = value.to_le_bytes();
-      sw.write(&dst).unwrap();
+      sw.write_all(&dst).unwrap();
     }
     sw.flush().unwrap();
     Ok(())
@@ -0,0 +1,136 @@
+use crate::*;
+use std::io::Write;
+use zip::write::FileOptions;
+
+/*** HrawWriter : write raw image archive ***/
+
+/// stored entries are aligned so they can be mapped with `Hraw::map_entry`
+const ALIGNMENT : u16 = 8;
+
+pub struct HrawWriter {
+  zip: zip::ZipWriter<std::fs::File>,
+  header: serde_json::Value,
+  data: Vec<String>,
+  compression: zip::CompressionMethod,
+  /// script entries written so far, the encoder `file` and `modules` are resolved from them
+  scripts: std::collections::BTreeMap<String, String>,
+}
+
+impl HrawWriter {
+
+  pub fn new(path:&str, header:serde_json::Value) -> anyhow::Result<HrawWriter> {
+    let file = std::fs::File::create(path)?;
+    Ok(HrawWriter {
+      zip: zip::ZipWriter::new(file),
+      header,
+      data: Vec::new(),
+      compression: zip::CompressionMethod::Stored,
+      scripts: Default::default(),
+    })
+  }
+
+  pub fn compression(&mut self, method:zip::CompressionMethod) -> &mut Self {
+    self.compression = method;
+    self
+  }
+
+  /// any entry as is, not listed in `data`
+  pub fn write_raw(&mut self, name:&str, src:&[u8]) -> anyhow::Result<()> {
+    let options = FileOptions::default().compression_method(self.compression);
+    match self.compression {
+      zip::CompressionMethod::Stored => { self.zip.start_file_aligned(name, options, ALIGNMENT)?; },
+      _ => self.zip.start_file(name, options)?
+    }
+    self.zip.write_all(src)?;
+    if ["lua", "py", "rhai"].iter().flat_map(|n| decoder::module_ext(n)).any(|ext| name.ends_with(ext)) {
+      if let Ok(code) = std::str::from_utf8(src) { self.scripts.insert(name.to_string(), code.to_string()); }
+    }
+    Ok(())
+  }
+
+  /// every entry of `src` except the header and its `data`, e.g. decoder / encoder scripts
+  pub fn copy_entries(&mut self, src:&mut Hraw) -> anyhow::Result<()> {
+    let data = src.header().to_struct().data.iter().filter_map(|n| n.as_str().map(str::to_string)).collect::<Vec<_>>();
+    let names = src.zip.file_names().map(str::to_string).collect::<Vec<_>>();
+    for name in names.iter().filter(|n| !HEADER_LIST.contains(&n.as_str()) && !data.contains(n) && !n.ends_with('/')) {
+      let mut buf = Vec::new();
+      src.zip.by_name(name)?.read_to_end(&mut buf)?;
+      self.write_raw(name, &buf)?;
+    }
+    Ok(())
+  }
+
+  /// pixels encoded as `T` after `offset` bytes of padding, listed in `data`
+  pub fn write_data<T:RawNumber>(&mut self, name:&str, src:&[T::Item]) -> anyhow::Result<()> {
+    let bitfield = match self.header.get("bitfield") {
+      Some(n) => serde_json::from_value::<BitField>(n.to_owned())?,
+      None => BitField::le_i32
+    };
+    anyhow::ensure!(bitfield == T::BITFIELD || bitfield == BitField::unknown, "bitfield {:?} does not match {:?}", bitfield, T::BITFIELD);
+    let offset = self.header["offset"].as_u64().unwrap_or_default() as usize;
+
+    let mut buf = vec![0u8; offset + src.len() * T::SIZE];
+    buf[offset..].chunks_exact_mut(T::SIZE).zip(src.iter()).for_each(|(dst, n)| T::to_bytes(*n, dst));
+    self.write_raw(name, &buf)?;
+    self.data.push(name.to_string());
+    Ok(())
+  }
+
+  /// pixels encoded by the `encoder` section of the header after `offset` bytes of padding, listed in `data`.
+  /// an encoder `file` must be written before, with `write_raw` or `copy_entries`
+  pub fn write_encoded<T>(&mut self, name:&str, src:&[T], policy:&ScriptPolicy) -> anyhow::Result<()> where [T] : HrawScripting {
+    let encoder = self.header.get("encoder").context("encoder not found in header")?;
+    let encoder = serde_json::from_value::<HeaderDecoder>(encoder.to_owned())?;
+    let names = self.scripts.keys().cloned().collect::<Vec<_>>();
+    let source = decoder::resolve_source("encoder", encoder, &names, |n| self.scripts.get(n).map(|n| n.clone().into_bytes()).with_context(|| format!("{n} : not written")))?;
+    let bytes = src.to_source(&source, &self.header, policy)?;
+    let offset = self.header["offset"].as_u64().unwrap_or_default() as usize;
+    let mut buf = vec![0u8; offset];
+    buf.extend_from_slice(&bytes);
+    self.write_raw(name, &buf)?;
+    self.data.push(name.to_string());
+    Ok(())
+  }
+
+  /// master frames as le_f32 entries (`calibration/dark.raw`, `calibration/flat.raw`), referenced from the `calibration` section
+  pub fn write_calibration(&mut self, dark:Option<&[f64]>, flat:Option<&[f64]>, gain:f64, cfa:bool) -> anyhow::Result<()> {
+    let mut section = serde_json::json!({ "gain" : gain, "cfa" : cfa });
+    for (key, name, src) in [("dark", CALIBRATION_DARK, dark), ("flat", CALIBRATION_FLAT, flat)] {
+      let Some(src) = src else { continue };
+      let mut buf = vec![0u8; src.len() * 4];
+      buf.chunks_exact_mut(4).zip(src.iter()).for_each(|(dst, n)| le_f32::to_bytes(*n as f32, dst));
+      self.write_raw(name, &buf)?;
+      section[key] = serde_json::json!(name);
+    }
+    self.header["calibration"] = section;
+    Ok(())
+  }
+
+  /// `defects` as a coordinate list in the header, or as a u8 mask `entry` of `width * height`
+  pub fn write_defects(&mut self, defects:&[Defect], entry:Option<&str>) -> anyhow::Result<()> {
+    self.header["defects"] = match entry {
+      None => serde_json::to_value(defects)?,
+      Some(name) => {
+        let width = self.header["width"].as_u64().context("width not found")? as usize;
+        let height = self.header["height"].as_u64().context("height not found")? as usize;
+        self.write_raw(name, &defect_mask(defects, width, height))?;
+        serde_json::json!(name)
+      }
+    };
+    Ok(())
+  }
+
+  /// writes `header.yaml`, `data` is filled with the written entries unless given
+  pub fn finish(mut self) -> anyhow::Result<()> {
+    if self.header.get("data").is_none() && !self.data.is_empty() {
+      self.header["data"] = serde_json::json!(self.data);
+    }
+    let yaml = serde_yaml::to_string(&self.header)?;
+    let options = FileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+    self.zip.start_file(HEADER_LIST[0], options)?;
+    self.zip.write_all(yaml.as_bytes())?;
+    self.zip.finish()?;
+    Ok(())
+  }
+
+}